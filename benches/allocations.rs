@@ -0,0 +1,224 @@
+//! Counts the heap allocations of sending one message to many clients. Every frame should be
+//! serialized once, no matter how many clients receive it.
+//!
+//! Run with `cargo bench --features ssr --bench allocations`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use criterion::{
+    BenchmarkId, Criterion, Throughput, criterion_group, criterion_main,
+    measurement::{Measurement, ValueFormatter},
+};
+use futures_channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures_util::StreamExt;
+use leptos_axum_socket::{DeliveryTarget, ServerSocket, SocketMsg, handlers::handle_connection};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Measures the number of allocations instead of the time.
+struct Allocations;
+
+impl Measurement for Allocations {
+    type Intermediate = u64;
+    type Value = u64;
+
+    fn start(&self) -> u64 {
+        ALLOCATIONS.load(Ordering::SeqCst)
+    }
+
+    fn end(&self, start: u64) -> u64 {
+        ALLOCATIONS.load(Ordering::SeqCst) - start
+    }
+
+    fn add(&self, v1: &u64, v2: &u64) -> u64 {
+        v1 + v2
+    }
+
+    fn zero(&self) -> u64 {
+        0
+    }
+
+    fn to_f64(&self, value: &u64) -> f64 {
+        *value as f64
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        self
+    }
+}
+
+impl ValueFormatter for Allocations {
+    fn scale_values(&self, _typical_value: f64, _values: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+
+    fn scale_throughputs(
+        &self,
+        _typical_value: f64,
+        throughput: &Throughput,
+        values: &mut [f64],
+    ) -> &'static str {
+        if let Throughput::Elements(elements) = throughput {
+            for value in values {
+                *value /= *elements as f64;
+            }
+        }
+
+        "allocs/client"
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct ChatMsg {
+    author: Uuid,
+    text: String,
+    sent_at: u64,
+    reactions: Vec<String>,
+}
+
+impl SocketMsg for ChatMsg {
+    type Key = u64;
+}
+
+struct Client {
+    id: Uuid,
+    incoming: UnboundedSender<String>,
+    outgoing: UnboundedReceiver<String>,
+}
+
+impl Client {
+    async fn connect(socket: &ServerSocket) -> Self {
+        let id = Uuid::new_v4();
+        let (incoming_tx, incoming_rx) = mpsc::unbounded();
+        let (outgoing_tx, outgoing_rx) = mpsc::unbounded();
+
+        tokio::spawn(handle_connection(
+            incoming_rx,
+            outgoing_tx,
+            socket.clone(),
+            id,
+            (),
+        ));
+
+        let mut client = Self {
+            id,
+            incoming: incoming_tx,
+            outgoing: outgoing_rx,
+        };
+        client.recv().await; // Welcome
+
+        client
+    }
+
+    fn subscribe(&self, room: u64) {
+        self.incoming
+            .unbounded_send(format!(r#"{{"Subscribe":{{"key":{room}}}}}"#))
+            .unwrap();
+    }
+
+    async fn recv(&mut self) -> String {
+        self.outgoing.next().await.unwrap()
+    }
+}
+
+fn chat_msg() -> ChatMsg {
+    ChatMsg {
+        author: Uuid::new_v4(),
+        text: "Lorem ipsum dolor sit amet, consectetur adipiscing elit. ".repeat(4),
+        sent_at: 1_700_000_000_000,
+        reactions: vec!["+1".to_string(), "heart".to_string(), "tada".to_string()],
+    }
+}
+
+fn allocations(c: &mut Criterion<Allocations>) {
+    let runtime = Runtime::new().unwrap();
+    let msg = chat_msg();
+
+    let mut group = c.benchmark_group("allocations");
+
+    for client_count in [1, 100] {
+        let socket = ServerSocket::new();
+
+        let mut clients = runtime.block_on(async {
+            let mut clients = Vec::with_capacity(client_count);
+            for _ in 0..client_count {
+                let mut client = Client::connect(&socket).await;
+                client.subscribe(1);
+                client.recv().await; // SubscribeResult
+                clients.push(client);
+            }
+            clients
+        });
+        let client_ids = clients.iter().map(|client| client.id).collect::<Vec<_>>();
+
+        group.throughput(Throughput::Elements(client_count as u64));
+
+        group.bench_function(BenchmarkId::new("broadcast", client_count), |b| {
+            b.iter(|| {
+                runtime.block_on(async {
+                    socket.lock().await.send(&1, &msg);
+
+                    for client in &mut clients {
+                        client.recv().await;
+                    }
+                })
+            });
+        });
+
+        group.bench_function(BenchmarkId::new("deliver", client_count), |b| {
+            b.iter(|| {
+                runtime.block_on(async {
+                    socket
+                        .lock()
+                        .await
+                        .deliver(DeliveryTarget::Clients(client_ids.clone()), &1, &msg)
+                        .await;
+
+                    for client in &mut clients {
+                        client.recv().await;
+                    }
+                })
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().with_measurement(Allocations);
+    targets = allocations
+}
+criterion_main!(benches);
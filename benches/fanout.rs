@@ -0,0 +1,125 @@
+//! Measures the server path of a message from one client to many subscribed clients.
+//!
+//! Run with `cargo bench --features ssr`.
+
+use std::time::Duration;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use futures_channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures_util::StreamExt;
+use leptos_axum_socket::{ServerSocket, handlers::handle_connection};
+use serde_json::json;
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+
+struct Client {
+    incoming: UnboundedSender<String>,
+    outgoing: UnboundedReceiver<String>,
+}
+
+impl Client {
+    fn connect(socket: &ServerSocket) -> Self {
+        let (incoming_tx, incoming_rx) = mpsc::unbounded();
+        let (outgoing_tx, outgoing_rx) = mpsc::unbounded();
+
+        tokio::spawn(handle_connection(
+            incoming_rx,
+            outgoing_tx,
+            socket.clone(),
+            Uuid::new_v4(),
+            (),
+        ));
+
+        Self {
+            incoming: incoming_tx,
+            outgoing: outgoing_rx,
+        }
+    }
+
+    fn send(&self, frame: &str) {
+        self.incoming.unbounded_send(frame.to_string()).unwrap();
+    }
+
+    async fn recv(&mut self) -> String {
+        self.outgoing.next().await.unwrap()
+    }
+}
+
+async fn setup(subscriber_count: usize) -> (Client, Vec<Client>) {
+    let socket = ServerSocket::new();
+
+    let mut publisher = Client::connect(&socket);
+    publisher.recv().await; // Welcome
+
+    let mut subscribers = Vec::with_capacity(subscriber_count);
+    for _ in 0..subscriber_count {
+        let mut subscriber = Client::connect(&socket);
+        subscriber.recv().await; // Welcome
+        subscriber.send(&json!({ "Subscribe": { "key": { "room": 1 } } }).to_string());
+        subscribers.push(subscriber);
+    }
+
+    // Wait until every subscription is processed
+    while socket
+        .lock()
+        .await
+        .snapshot()
+        .keys
+        .iter()
+        .map(|key| key.receiver_count)
+        .sum::<usize>()
+        < subscriber_count
+    {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    for subscriber in &mut subscribers {
+        subscriber.recv().await; // SubscribeResult
+    }
+
+    (publisher, subscribers)
+}
+
+fn chat_frame() -> String {
+    json!({
+        "Msg": {
+            "key": { "room": 1 },
+            "msg": {
+                "author": "5d1c3a2e-7a8b-4c6d-9e0f-112233445566",
+                "text": "Lorem ipsum dolor sit amet, consectetur adipiscing elit. ".repeat(4),
+                "sent_at": 1_700_000_000_000u64,
+                "reactions": ["+1", "heart", "tada"],
+            },
+        }
+    })
+    .to_string()
+}
+
+fn fanout(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let frame = chat_frame();
+
+    let mut group = c.benchmark_group("fanout");
+
+    for subscriber_count in [1, 100] {
+        let (publisher, mut subscribers) = runtime.block_on(setup(subscriber_count));
+
+        group.throughput(Throughput::Elements(subscriber_count as u64));
+        group.bench_function(BenchmarkId::from_parameter(subscriber_count), |b| {
+            b.iter(|| {
+                runtime.block_on(async {
+                    publisher.send(&frame);
+
+                    for subscriber in &mut subscribers {
+                        subscriber.recv().await;
+                    }
+                })
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, fanout);
+criterion_main!(benches);
@@ -88,7 +88,10 @@ struct ToastKey {
 
 impl SocketMsg for ToastMsg {
     type Key = ToastKey;
-    #[cfg(feature = "ssr")]
+}
+
+#[cfg(feature = "ssr")]
+impl leptos_axum_socket::ServerSocketMsg for ToastMsg {
     type AppState = crate::AppState;
 }
 
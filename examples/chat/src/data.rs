@@ -17,8 +17,6 @@ pub struct ChatKey {
 
 impl SocketMsg for ChatMsg {
     type Key = ChatKey;
-    #[cfg(feature = "ssr")]
-    type AppState = crate::AppState;
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+#[cfg(not(feature = "ssr"))]
+use leptos::prelude::*;
+#[cfg(not(feature = "ssr"))]
+use leptos_use::core::ConnectionReadyState;
+use uuid::Uuid;
+
+/// Defines how long the client waits before it reconnects after the connection was lost.
+///
+/// The delay starts at `initial` and is multiplied by `factor` (2 by default) after every failed
+/// attempt until it reaches `max`. To prevent thousands of clients from reconnecting in lockstep after a
+/// server restart, a random part of up to `jitter` (between 0 and 1, 0.5 by default) of the delay is
+/// subtracted.
+///
+/// See [`SocketOptions::reconnect_backoff`](crate::SocketOptions::reconnect_backoff).
+///
+/// ```
+/// # use std::time::Duration;
+/// # use leptos_axum_socket::ReconnectBackoff;
+/// #
+/// let backoff = ReconnectBackoff::new(Duration::from_millis(500), Duration::from_secs(60)).jitter(0.0);
+///
+/// assert_eq!(backoff.delay(0), Duration::from_millis(500));
+/// assert_eq!(backoff.delay(3), Duration::from_secs(4));
+/// assert_eq!(backoff.delay(20), Duration::from_secs(60));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReconnectBackoff {
+    initial: Duration,
+    max: Duration,
+    factor: f64,
+    jitter: f64,
+}
+
+impl Default for ReconnectBackoff {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1), Duration::from_secs(30))
+    }
+}
+
+impl ReconnectBackoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            factor: 2.0,
+            jitter: 0.5,
+        }
+    }
+
+    pub fn factor(mut self, factor: f64) -> Self {
+        self.factor = factor.max(1.0);
+        self
+    }
+
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Returns the delay before the reconnect attempt with the given number (starting at 0).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.min(i32::MAX as u32) as i32;
+        let delay =
+            (self.initial.as_secs_f64() * self.factor.powi(exponent)).min(self.max.as_secs_f64());
+
+        Duration::from_secs_f64(delay - delay * self.jitter * random_fraction())
+    }
+}
+
+/// Returns a random number in `[0, 1)`.
+fn random_fraction() -> f64 {
+    let (random, _) = Uuid::new_v4().as_u64_pair();
+
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Opens the connection again with `backoff` whenever it's closed while `manually_closed` is `false`.
+#[cfg(not(feature = "ssr"))]
+pub(crate) fn reconnect_with_backoff(
+    ready_state: Signal<ConnectionReadyState>,
+    open: impl Fn() + Clone + Send + Sync + 'static,
+    manually_closed: StoredValue<bool>,
+    backoff: ReconnectBackoff,
+) {
+    let attempt = StoredValue::new(0u32);
+
+    Effect::new(move |prev_ready_state: Option<ConnectionReadyState>| {
+        let ready_state_value = ready_state.get();
+
+        match ready_state_value {
+            ConnectionReadyState::Open => attempt.set_value(0),
+            ConnectionReadyState::Closed
+                if prev_ready_state.is_some_and(|prev| prev != ConnectionReadyState::Closed)
+                    && !manually_closed.get_value() =>
+            {
+                let delay = backoff.delay(attempt.get_value());
+                attempt.update_value(|attempt| *attempt = attempt.saturating_add(1));
+
+                let open = open.clone();
+                set_timeout(
+                    move || {
+                        if ready_state.try_get_untracked() == Some(ConnectionReadyState::Closed)
+                            && manually_closed.try_get_value() == Some(false)
+                        {
+                            open();
+                        }
+                    },
+                    delay,
+                );
+            }
+            _ => (),
+        }
+
+        ready_state_value
+    });
+}
@@ -0,0 +1,45 @@
+use serde_json::Value;
+
+/// Implement this to encrypt message payloads end to end. Install it on the client with
+/// [`SocketContext::set_cipher`](crate::SocketContext::set_cipher).
+///
+/// Only the message is passed through the cipher. The key stays visible to the server because it is
+/// needed to route the message to the subscribers. This also means that the server's send mappers
+/// only see the encrypted payload and can't deserialize it into your message type.
+///
+/// ## Example
+///
+/// ```
+/// # use leptos_axum_socket::PayloadCipher;
+/// # use serde_json::Value;
+/// #
+/// struct Rot13;
+///
+/// fn rot13(text: &str) -> String {
+///     text.chars()
+///         .map(|c| match c {
+///             'a'..='m' | 'A'..='M' => (c as u8 + 13) as char,
+///             'n'..='z' | 'N'..='Z' => (c as u8 - 13) as char,
+///             _ => c,
+///         })
+///         .collect()
+/// }
+///
+/// impl PayloadCipher for Rot13 {
+///     fn encrypt(&self, _key: &Value, msg: Value) -> Result<Value, String> {
+///         Ok(Value::String(rot13(&msg.to_string())))
+///     }
+///
+///     fn decrypt(&self, _key: &Value, msg: Value) -> Result<Value, String> {
+///         let text = msg.as_str().ok_or("Expected a string")?;
+///         serde_json::from_str(&rot13(text)).map_err(|err| err.to_string())
+///     }
+/// }
+/// ```
+pub trait PayloadCipher: Send + Sync + 'static {
+    /// Called with the serialized message before it is sent with the given key.
+    fn encrypt(&self, key: &Value, msg: Value) -> Result<Value, String>;
+
+    /// Called with the received payload before it is deserialized into the message type.
+    fn decrypt(&self, key: &Value, msg: Value) -> Result<Value, String>;
+}
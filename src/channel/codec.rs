@@ -0,0 +1,87 @@
+//! Serialization of keys, messages and frames.
+//!
+//! Both the client and the server use these functions so that keys are serialized identically on both
+//! sides. The serialization of messages can be adjusted with the `json-skip-nulls` and `json-pretty`
+//! features. Keys always use their canonical form.
+
+use leptos::server::codee::{Decoder, Encoder};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{CanonicalKey, ChannelMsg};
+
+/// Serializes a message into a JSON value.
+///
+/// With the `json-skip-nulls` feature enabled, object fields that are `null` (i.e. `Option::None`) are
+/// left out.
+pub(crate) fn to_value<T>(value: &T) -> serde_json::Result<Value>
+where
+    T: Serialize + ?Sized,
+{
+    let value = serde_json::to_value(value)?;
+
+    #[cfg(feature = "json-skip-nulls")]
+    let value = strip_nulls(value);
+
+    Ok(value)
+}
+
+/// Serializes a key into its canonical form. Keys don't depend on the `json-*` features, see
+/// [`CanonicalKey`].
+pub(crate) fn to_key<K>(key: &K) -> serde_json::Result<CanonicalKey>
+where
+    K: Serialize + ?Sized,
+{
+    CanonicalKey::from_key(key)
+}
+
+/// Serializes a frame to be sent over the wire.
+///
+/// With the `json-pretty` feature enabled, the frame is pretty printed.
+pub(crate) fn to_string(msg: &ChannelMsg) -> serde_json::Result<String> {
+    #[cfg(feature = "json-pretty")]
+    {
+        serde_json::to_string_pretty(msg)
+    }
+
+    #[cfg(not(feature = "json-pretty"))]
+    {
+        serde_json::to_string(msg)
+    }
+}
+
+#[cfg(feature = "json-skip-nulls")]
+fn strip_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, strip_nulls(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(strip_nulls).collect()),
+        value => value,
+    }
+}
+
+/// Codec that is used by the client for the WebSocket frames.
+#[cfg_attr(feature = "ssr", allow(dead_code))]
+pub(crate) struct ChannelMsgCodec;
+
+impl Encoder<ChannelMsg> for ChannelMsgCodec {
+    type Error = serde_json::Error;
+    type Encoded = String;
+
+    fn encode(val: &ChannelMsg) -> Result<Self::Encoded, Self::Error> {
+        to_string(val)
+    }
+}
+
+impl Decoder<ChannelMsg> for ChannelMsgCodec {
+    type Error = serde_json::Error;
+    type Encoded = str;
+
+    fn decode(val: &Self::Encoded) -> Result<ChannelMsg, Self::Error> {
+        serde_json::from_str(val)
+    }
+}
@@ -1,14 +1,54 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    borrow::Borrow,
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use leptos::prelude::*;
 use leptos_use::core::ConnectionReadyState;
 use serde::Serialize;
 use serde_json::Value;
+use uuid::Uuid;
 
-use crate::{ChannelMsg, SocketMsg};
+use crate::{
+    CanonicalKey, ChannelMsg, CloseFrame, MessageStream, Namespaced, PayloadCipher,
+    ReconnectBackoff, Refetch, SenderInfo, SocketError, SocketMsg, Topic, TypedChannel, codec,
+    trace::{Direction, trace_frame},
+};
+
+use super::dispatch::{HandlerFn, Subscription};
+#[cfg(not(feature = "ssr"))]
+use super::dispatch::{dispatch, subscriptions_of};
+#[cfg(not(feature = "ssr"))]
+use crate::type_key;
 
 type SendFn = StoredValue<Arc<dyn Fn(&ChannelMsg) + Send + Sync + 'static>>;
 type SimpleFn = StoredValue<Arc<dyn Fn() + Send + Sync + 'static>>;
+type GapFn = Arc<dyn Fn(u64, u64) + Send + Sync>;
+
+/// Defines what happens to incoming messages while the [`SocketContext`] is paused.
+///
+/// See [`SocketContext::pause_with`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PausePolicy {
+    /// Buffer all messages and call the handlers with them on resume.
+    #[default]
+    Buffer,
+    /// Only keep the latest message of each key and call the handlers with them on resume.
+    LatestPerKey,
+    /// Unsubscribe on the server so that it stops sending messages. On resume the keys are subscribed
+    /// again which delivers the latest message of stateful keys.
+    Drop,
+}
+
+/// A message that was received while paused.
+#[cfg_attr(feature = "ssr", allow(dead_code))]
+struct PausedMsg {
+    key: CanonicalKey,
+    path: Value,
+    msg: Value,
+    sender: Option<SenderInfo>,
+}
 
 /// The context to be used for sending and subscribing to messages in your component.
 /// You probably don't want to use this directly, but rather use the `expect_socket_context` hook.
@@ -20,75 +60,1744 @@ pub struct SocketContext {
     pub(crate) open: SimpleFn,
     pub(crate) close: SimpleFn,
     pub(crate) message: Signal<Option<ChannelMsg>>,
-    effect_stops: StoredValue<HashMap<Value, Box<dyn Fn() + Send + Sync + 'static>>>,
-    subscribers: StoredValue<HashMap<Value, Arc<dyn Fn() + Send + Sync>>>,
+    subscriptions: StoredValue<HashMap<CanonicalKey, Vec<Subscription>>>,
+    key_closed_handlers: StoredValue<HashMap<CanonicalKey, Arc<dyn Fn() + Send + Sync>>>,
+    cipher: StoredValue<Option<Arc<dyn PayloadCipher>>>,
+    pause_policy: RwSignal<Option<PausePolicy>>,
+    paused_msgs: StoredValue<Vec<PausedMsg>>,
+    /// The frames of optimistically sent messages whose echo hasn't been received yet. They are
+    /// sent again when the connection is reopened.
+    pending_echoes: StoredValue<HashMap<Uuid, ChannelMsg>>,
+    last_frame_cleared: RwSignal<bool>,
+    counters: RwSignal<Counters>,
+    client_id: RwSignal<Option<Uuid>>,
+    remapped_keys: StoredValue<HashMap<CanonicalKey, CanonicalKey>>,
+    server_filters: StoredValue<HashMap<CanonicalKey, Value>>,
+    last_seqs: StoredValue<HashMap<CanonicalKey, u64>>,
+    gap_handlers: StoredValue<HashMap<CanonicalKey, GapFn>>,
+    /// The keys whose subscription the server acknowledged on the current connection.
+    confirmed_keys: RwSignal<HashSet<CanonicalKey>>,
+    /// The last message of every key that patches are applied to.
+    #[cfg(feature = "patch")]
+    patch_bases: StoredValue<HashMap<CanonicalKey, Value>>,
+    failed: RwSignal<bool>,
+    last_error: RwSignal<Option<Arc<SocketError>>>,
+    last_close: RwSignal<Option<CloseFrame>>,
+    /// See [`SocketContext::with_namespace`].
+    namespace: Option<&'static str>,
+}
+
+/// The state of the connection of a [`SocketContext`].
+///
+/// Like [`ConnectionReadyState`] but with [`ConnectionState::Failed`]. See
+/// [`SocketContext::connection_state`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Open,
+    Closing,
+    Closed,
+    /// The connection wasn't opened within the connect deadline. It stays in this state until it
+    /// opens. See [`SocketOptions::connect_deadline`].
+    Failed,
+}
+
+/// Statistics about the connection of a [`SocketContext`]. Useful for debugging flaky connections.
+///
+/// See [`SocketContext::stats`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// Number of frames that were sent to the server, including subscriptions.
+    pub messages_sent: u64,
+    /// Number of frames that were received from the server.
+    pub messages_received: u64,
+    /// How often the connection was opened again after it had been open before.
+    pub reconnect_count: u64,
+    pub ready_state: ConnectionReadyState,
 }
 
-// #[cfg(not(feature = "ssr"))]
-impl SocketContext {
-    fn new(query: String) -> Self {
-        use crate::WEBSOCKET_CHANNEL_URL;
-        use leptos::server::codee::string::JsonSerdeCodec;
-        use leptos_use::{
-            ReconnectLimit, UseWebSocketOptions, UseWebSocketReturn, use_websocket_with_options,
+#[derive(Clone, Copy, Debug, Default)]
+struct Counters {
+    messages_sent: u64,
+    messages_received: u64,
+    reconnect_count: u64,
+}
+
+// #[cfg(not(feature = "ssr"))]
+impl SocketContext {
+    /// Connects to the server.
+    #[cfg(not(feature = "ssr"))]
+    fn new(options: SocketOptions) -> Self {
+        use crate::WEBSOCKET_CHANNEL_URL;
+        use crate::codec::ChannelMsgCodec;
+        use leptos_use::{
+            ReconnectLimit, UseWebSocketOptions, UseWebSocketReturn, use_websocket_with_options,
+        };
+
+        let SocketOptions {
+            query,
+            reconnect_backoff,
+            connect_deadline,
+            stop_after_deadline,
+        } = options;
+
+        let query = if query.is_empty() {
+            String::new()
+        } else {
+            format!("?{query}")
+        };
+        let url = format!("{WEBSOCKET_CHANNEL_URL}{query}");
+
+        let manually_closed = StoredValue::new(false);
+        let last_close = RwSignal::new(None);
+
+        let UseWebSocketReturn {
+            message,
+            send,
+            ready_state,
+            open,
+            close,
+            ..
+        } = use_websocket_with_options::<ChannelMsg, ChannelMsg, ChannelMsgCodec, _, _>(
+            &url,
+            UseWebSocketOptions::default()
+                // Reconnects are done with backoff below
+                .reconnect_limit(ReconnectLimit::Limited(0))
+                .on_error(|error| {
+                    leptos::logging::error!("WebSocket error: {}", error);
+                })
+                .on_close(move |event: leptos::web_sys::CloseEvent| {
+                    let frame = CloseFrame::new(event.code(), event.reason());
+
+                    // The server closed the connection on purpose, so let the app decide
+                    if frame.is_application_code() {
+                        manually_closed.set_value(true);
+                    }
+
+                    last_close.set(Some(frame));
+                }),
+        );
+
+        let open = move || {
+            manually_closed.set_value(false);
+            open();
+        };
+        let close = move || {
+            manually_closed.set_value(true);
+            close();
+        };
+
+        super::backoff::reconnect_with_backoff(
+            ready_state,
+            open.clone(),
+            manually_closed,
+            reconnect_backoff,
+        );
+
+        #[cfg(feature = "longpoll")]
+        let (message, send, ready_state) =
+            super::longpoll::with_fallback(message, send, ready_state, close.clone(), query);
+
+        let ctx = Self::with_transport(
+            message,
+            Arc::new(send),
+            ready_state,
+            Arc::new(open),
+            Arc::new(close),
+            last_close,
+        );
+
+        ctx.dispatch_effect();
+        ctx.subscribe_on_open_effect();
+        ctx.count_reconnects_effect();
+
+        if let Some(deadline) = connect_deadline {
+            ctx.connect_deadline_effect(deadline, stop_after_deadline);
+        }
+
+        ctx
+    }
+
+    /// There is no connection during SSR. Realtime communication only starts after hydration, so
+    /// this is an inert stub: it stays closed and sending and subscribing do nothing.
+    #[cfg(feature = "ssr")]
+    fn new(options: SocketOptions) -> Self {
+        let _ = options;
+
+        Self::with_transport(
+            Signal::stored(None),
+            Arc::new(|_: &ChannelMsg| ()),
+            Signal::stored(ConnectionReadyState::Closed),
+            Arc::new(|| ()),
+            Arc::new(|| ()),
+            RwSignal::new(None),
+        )
+    }
+
+    pub(crate) fn with_transport(
+        message: Signal<Option<ChannelMsg>>,
+        send: Arc<dyn Fn(&ChannelMsg) + Send + Sync>,
+        ready_state: Signal<ConnectionReadyState>,
+        open: Arc<dyn Fn() + Send + Sync>,
+        close: Arc<dyn Fn() + Send + Sync>,
+        last_close: RwSignal<Option<CloseFrame>>,
+    ) -> Self {
+        let counters = RwSignal::new(Counters::default());
+        let send = move |msg: &ChannelMsg| {
+            counters.update(|counters| counters.messages_sent += 1);
+            trace_frame(Direction::Out, msg);
+            send(msg);
+        };
+
+        Self {
+            message,
+            send: StoredValue::new(Arc::new(send)),
+            ready_state,
+            open: StoredValue::new(open),
+            close: StoredValue::new(close),
+            subscriptions: StoredValue::new(HashMap::new()),
+            key_closed_handlers: StoredValue::new(HashMap::new()),
+            cipher: StoredValue::new(None),
+            pause_policy: RwSignal::new(None),
+            paused_msgs: StoredValue::new(vec![]),
+            pending_echoes: StoredValue::new(HashMap::new()),
+            last_frame_cleared: RwSignal::new(false),
+            counters,
+            client_id: RwSignal::new(None),
+            remapped_keys: StoredValue::new(HashMap::new()),
+            server_filters: StoredValue::new(HashMap::new()),
+            last_seqs: StoredValue::new(HashMap::new()),
+            gap_handlers: StoredValue::new(HashMap::new()),
+            confirmed_keys: RwSignal::new(HashSet::new()),
+            #[cfg(feature = "patch")]
+            patch_bases: StoredValue::new(HashMap::new()),
+            failed: RwSignal::new(false),
+            last_error: RwSignal::new(None),
+            last_close,
+            namespace: None,
+        }
+    }
+
+    /// Returns a context that prefixes all keys with the `namespace`, so that a reusable component
+    /// library doesn't collide with the keys of the app it's embedded in. It shares the connection
+    /// with this context. Keys are sent as [`Namespaced`] keys and the server routes them as usual.
+    ///
+    /// Replaces the namespace if this context has one already. Type subscriptions, see
+    /// [`SocketContext::subscribe_type`], aren't namespaced.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let socket = expect_socket_context().with_namespace("mylib");
+    ///
+    /// // Subscribes to `{"namespace":"mylib","key":{"room_id":1}}`
+    /// socket.subscribe(ChatKey { room_id: 1 }, |msg: &ChatMsg| {
+    ///     leptos::logging::log!("message: {msg:#?}");
+    /// });
+    /// ```
+    pub fn with_namespace(self, namespace: &'static str) -> Self {
+        Self {
+            namespace: Some(namespace),
+            ..self
+        }
+    }
+
+    /// Dispatches every incoming message to the subscribers of its key.
+    #[cfg(not(feature = "ssr"))]
+    fn dispatch_effect(self) {
+        Effect::new(move || {
+            let message = self.message.read();
+
+            if self.last_frame_cleared.get_untracked() {
+                self.last_frame_cleared.set(false);
+            }
+
+            if let Some(msg) = message.as_ref() {
+                self.counters
+                    .update(|counters| counters.messages_received += 1);
+                trace_frame(Direction::In, msg);
+            }
+
+            match message.as_ref() {
+                Some(frame @ (ChannelMsg::Msg { .. } | ChannelMsg::PathMsg { .. })) => {
+                    self.receive_frame(frame)
+                }
+                #[cfg(feature = "patch")]
+                Some(frame @ ChannelMsg::Patch { .. }) => self.receive_frame(frame),
+                #[cfg(feature = "reliable")]
+                Some(ChannelMsg::Reliable { seq, msg }) => {
+                    self.send.get_value()(&ChannelMsg::Ack { seq: *seq });
+                    self.receive_frame(msg);
+                }
+                Some(
+                    result @ (ChannelMsg::SubscribeResult { .. }
+                    | ChannelMsg::SubscribeDenied { .. }),
+                ) => {
+                    let result = result.clone();
+                    drop(message);
+
+                    self.receive_subscribe_result(result);
+                }
+                Some(ChannelMsg::SubscribeManyResult { results }) => {
+                    let results = results.clone();
+                    drop(message);
+
+                    for result in results {
+                        self.receive_subscribe_result(result);
+                    }
+                }
+                Some(ChannelMsg::Invalid { key, reason }) => {
+                    leptos::logging::warn!(
+                        "Server dropped invalid message sent to {key}: {reason}"
+                    );
+                }
+                Some(ChannelMsg::TooLarge { size, max_size }) => {
+                    leptos::logging::warn!(
+                        "Server rejected a frame of {size} bytes. The maximum is {max_size} bytes."
+                    );
+                }
+                Some(ChannelMsg::Welcome { client_id }) => {
+                    self.client_id.set(Some(*client_id));
+                }
+                Some(ChannelMsg::KeyRemapped { old, new }) => {
+                    let (old, new) = (old.clone(), new.clone());
+                    drop(message);
+
+                    self.remap_key(old, new);
+                }
+                Some(ChannelMsg::KeyClosed { key }) => {
+                    let key = key.clone();
+                    drop(message);
+
+                    // The server already removed the subscription so no need to unsubscribe
+                    self.subscriptions.write_value().remove(&key);
+                    self.server_filters.write_value().remove(&key);
+                    self.last_seqs.write_value().remove(&key);
+                    self.gap_handlers.write_value().remove(&key);
+                    #[cfg(feature = "patch")]
+                    self.patch_bases.write_value().remove(&key);
+                    self.unconfirm(&key);
+
+                    let handler = self.key_closed_handlers.write_value().remove(&key);
+                    if let Some(handler) = handler {
+                        handler();
+                    }
+                }
+                _ => (),
+            }
+        });
+    }
+
+    /// Handles a `SubscribeResult` or `SubscribeDenied` frame.
+    #[cfg(not(feature = "ssr"))]
+    fn receive_subscribe_result(self, result: ChannelMsg) {
+        match result {
+            // The result of a key that was unsubscribed in the meantime is stale
+            ChannelMsg::SubscribeResult {
+                key,
+                pinned,
+                history,
+                retained,
+            } if self.has_subscriptions(&key) => {
+                self.confirmed_keys.update(|keys| {
+                    keys.insert(key.clone());
+                });
+
+                for msg in pinned.into_iter().chain(history).chain(retained) {
+                    self.receive_frame(&ChannelMsg::Msg {
+                        key: key.clone(),
+                        msg,
+                        id: None,
+                        sender: None,
+                        seq: None,
+                    });
+                }
+            }
+            ChannelMsg::SubscribeDenied { key, reason } => {
+                leptos::logging::warn!("Subscription to {key} denied: {reason}");
+                self.unconfirm(&key);
+            }
+            _ => (),
+        }
+    }
+
+    /// Moves the subscriptions of `old` to `new` after the server remapped the key. From now on `old`
+    /// is replaced by `new` whenever it's used with this context.
+    #[cfg(not(feature = "ssr"))]
+    fn remap_key(self, old: CanonicalKey, new: CanonicalKey) {
+        {
+            let mut subscriptions = self.subscriptions.write_value();
+            if let Some(moved) = subscriptions.remove(&old) {
+                subscriptions.entry(new.clone()).or_default().extend(moved);
+            }
+        }
+
+        {
+            let mut handlers = self.key_closed_handlers.write_value();
+            if let Some(handler) = handlers.remove(&old) {
+                handlers.entry(new.clone()).or_insert(handler);
+            }
+        }
+
+        {
+            let mut filters = self.server_filters.write_value();
+            if let Some(filter) = filters.remove(&old) {
+                filters.entry(new.clone()).or_insert(filter);
+            }
+        }
+
+        {
+            // The new key has its own sequence numbers and messages
+            self.last_seqs.write_value().remove(&old);
+            #[cfg(feature = "patch")]
+            self.patch_bases.write_value().remove(&old);
+
+            let mut handlers = self.gap_handlers.write_value();
+            if let Some(handler) = handlers.remove(&old) {
+                handlers.entry(new.clone()).or_insert(handler);
+            }
+        }
+
+        if self
+            .confirmed_keys
+            .with_untracked(|keys| keys.contains(&old))
+        {
+            self.confirmed_keys.update(|keys| {
+                keys.remove(&old);
+                keys.insert(new.clone());
+            });
+        }
+
+        let mut remapped_keys = self.remapped_keys.write_value();
+        for target in remapped_keys.values_mut() {
+            if *target == old {
+                *target = new.clone();
+            }
+        }
+        remapped_keys.insert(old, new);
+    }
+
+    /// Serializes the key and replaces it if it was remapped by the server.
+    fn to_key<K>(self, key: &K) -> serde_json::Result<CanonicalKey>
+    where
+        K: Serialize + ?Sized,
+    {
+        let key = match self.namespace {
+            Some(namespace) => codec::to_key(&Namespaced::new(namespace, key))?,
+            None => codec::to_key(key)?,
+        };
+
+        Ok(self
+            .remapped_keys
+            .with_value(|remapped_keys| remapped_keys.get(&key).cloned())
+            .unwrap_or(key))
+    }
+
+    /// Receives a `Msg` or `PathMsg` frame. The echo of an optimistically sent message is ignored.
+    #[cfg(not(feature = "ssr"))]
+    pub(crate) fn receive_frame(self, frame: &ChannelMsg) {
+        match frame {
+            ChannelMsg::Msg {
+                key,
+                msg,
+                id,
+                sender,
+                seq,
+            } => {
+                if let Some(seq) = seq {
+                    self.check_seq(key, *seq);
+                }
+
+                #[cfg(feature = "patch")]
+                self.patch_bases
+                    .write_value()
+                    .insert(key.clone(), msg.clone());
+
+                let own_echo =
+                    id.is_some_and(|id| self.pending_echoes.write_value().remove(&id).is_some());
+
+                if !own_echo {
+                    self.receive(key, key, msg, sender.as_ref());
+                }
+            }
+            #[cfg(feature = "patch")]
+            ChannelMsg::Patch { key, patch, seq } => {
+                if let Some(seq) = seq {
+                    self.check_seq(key, *seq);
+                }
+
+                let patched = {
+                    let mut bases = self.patch_bases.write_value();
+                    bases
+                        .get_mut(key)
+                        .ok_or(crate::PatchError::NoBase)
+                        .and_then(|base| {
+                            crate::apply_patch(base, patch)?;
+                            Ok(base.clone())
+                        })
+                };
+
+                match patched {
+                    Ok(msg) => self.receive(key, key, &msg, None),
+                    Err(err) => {
+                        leptos::logging::error!("Failed to apply patch to {key}: {err}");
+                    }
+                }
+            }
+            ChannelMsg::PathMsg { key, path, msg } => self.receive(key, path, msg, None),
+            _ => (),
+        }
+    }
+
+    /// Calls the gap handler of `key` if messages were skipped since the last one. A sequence number
+    /// that isn't larger than the last one means that the server restarted and isn't a gap.
+    #[cfg(not(feature = "ssr"))]
+    fn check_seq(self, key: &CanonicalKey, seq: u64) {
+        // Filtered subscriptions only receive some of the messages
+        if self
+            .server_filters
+            .with_value(|filters| filters.contains_key(key))
+        {
+            return;
+        }
+
+        let last = self.last_seqs.write_value().insert(key.clone(), seq);
+
+        if let Some(last) = last
+            && seq > last + 1
+            && let Some(handler) = self
+                .gap_handlers
+                .with_value(|handlers| handlers.get(key).cloned())
+        {
+            handler(last + 1, seq);
+        }
+    }
+
+    /// Dispatches the message or keeps it for later if the context is paused.
+    #[cfg(not(feature = "ssr"))]
+    fn receive(self, key: &CanonicalKey, path: &Value, msg: &Value, sender: Option<&SenderInfo>) {
+        let paused_msg = || PausedMsg {
+            key: key.clone(),
+            path: path.clone(),
+            msg: msg.clone(),
+            sender: sender.cloned(),
+        };
+
+        match self.pause_policy.get_untracked() {
+            None => self.dispatch(key, path, msg, sender),
+            Some(PausePolicy::Buffer) => self.paused_msgs.write_value().push(paused_msg()),
+            Some(PausePolicy::LatestPerKey) => {
+                let mut paused_msgs = self.paused_msgs.write_value();
+                paused_msgs.retain(|paused| &paused.key != key || &paused.path != path);
+                paused_msgs.push(paused_msg());
+            }
+            Some(PausePolicy::Drop) => (),
+        }
+    }
+
+    /// Calls the handlers that are subscribed to `key`. `path` is the full path of hierarchical keys and
+    /// equal to `key` otherwise.
+    ///
+    /// Messages are deserialized only once per message type and shared among all subscribers of the key.
+    #[cfg(not(feature = "ssr"))]
+    fn dispatch(self, key: &CanonicalKey, path: &Value, msg: &Value, sender: Option<&SenderInfo>) {
+        if !self.has_subscriptions(key) {
+            return;
+        }
+
+        match self.cipher.get_value() {
+            Some(cipher) => match cipher.decrypt(path, msg.clone()) {
+                Ok(msg) => self.dispatch_decrypted(key, path, &msg, sender),
+                Err(err) => {
+                    leptos::logging::error!("Failed to decrypt message: {}", err);
+                }
+            },
+            None => self.dispatch_decrypted(key, path, msg, sender),
+        }
+    }
+
+    #[cfg(not(feature = "ssr"))]
+    pub(crate) fn has_subscriptions(self, key: &CanonicalKey) -> bool {
+        self.subscriptions
+            .with_value(|subscriptions| subscriptions.contains_key(key))
+    }
+
+    /// Like [`SocketContext::dispatch`] for messages that are not encrypted.
+    #[cfg(not(feature = "ssr"))]
+    fn dispatch_decrypted(
+        self,
+        key: &CanonicalKey,
+        path: &Value,
+        msg: &Value,
+        sender: Option<&SenderInfo>,
+    ) {
+        // Cloned so that handlers can subscribe and unsubscribe
+        let subscriptions = self
+            .subscriptions
+            .with_value(|subscriptions| subscriptions_of(subscriptions, key));
+
+        dispatch(&subscriptions, path, msg, sender);
+    }
+
+    /// Removes the acknowledgement of the subscription to `key`.
+    #[cfg(not(feature = "ssr"))]
+    fn unconfirm(self, key: &CanonicalKey) {
+        if self
+            .confirmed_keys
+            .with_untracked(|keys| keys.contains(key))
+        {
+            self.confirmed_keys.update(|keys| {
+                keys.remove(key);
+            });
+        }
+    }
+
+    /// (Re-)subscribes to all keys whenever the connection is opened.
+    #[cfg(not(feature = "ssr"))]
+    fn subscribe_on_open_effect(self) {
+        Effect::new(move || {
+            let ready_state = self.ready_state.get();
+
+            // The server forgets the subscriptions of a closed connection
+            if ready_state != ConnectionReadyState::Open
+                && self.confirmed_keys.with_untracked(|keys| !keys.is_empty())
+            {
+                self.confirmed_keys.update(HashSet::clear);
+            }
+
+            if ready_state == ConnectionReadyState::Open
+                && self.pause_policy.get_untracked() != Some(PausePolicy::Drop)
+            {
+                for key in self.subscribed_keys() {
+                    self.send.get_value()(&self.subscribe_frame(key));
+                }
+            }
+
+            // Optimistic messages might have been lost with the previous connection. The server
+            // only echoes them back if it already received them.
+            if ready_state == ConnectionReadyState::Open {
+                let pending = self
+                    .pending_echoes
+                    .with_value(|pending| pending.values().cloned().collect::<Vec<_>>());
+
+                for frame in pending {
+                    self.send.get_value()(&frame);
+                }
+            }
+        });
+    }
+
+    /// Stops calling the handlers until [`SocketContext::resume`] is called. Incoming messages are
+    /// buffered in the meantime. This is useful to save CPU while the tab is in the background.
+    ///
+    /// See [`SocketContext::pause_with`] to choose what happens to the incoming messages.
+    pub fn pause(self) {
+        self.pause_with(PausePolicy::default());
+    }
+
+    /// Like [`SocketContext::pause`] but with the given policy for incoming messages.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let visibility = use_document_visibility();
+    ///
+    /// Effect::new(move || {
+    ///     if visibility.get() == web_sys::VisibilityState::Hidden {
+    ///         socket.pause_with(PausePolicy::LatestPerKey);
+    ///     } else {
+    ///         socket.resume();
+    ///     }
+    /// });
+    /// ```
+    pub fn pause_with(self, policy: PausePolicy) {
+        #[cfg(feature = "ssr")]
+        {
+            let _ = policy;
+        }
+
+        #[cfg(not(feature = "ssr"))]
+        {
+            let previous = self.pause_policy.get_untracked();
+            if previous == Some(policy) {
+                return;
+            }
+
+            self.pause_policy.set(Some(policy));
+
+            if policy == PausePolicy::Drop {
+                self.paused_msgs.write_value().clear();
+                self.confirmed_keys.update(HashSet::clear);
+
+                if self.ready_state.get_untracked() == ConnectionReadyState::Open {
+                    for key in self.subscribed_keys() {
+                        self.send.get_value()(&ChannelMsg::Unsubscribe { key });
+                    }
+                }
+            } else if previous == Some(PausePolicy::Drop) {
+                self.subscribe_all();
+            }
+        }
+    }
+
+    /// Calls the handlers with the messages that were kept while paused and continues to handle
+    /// incoming messages.
+    pub fn resume(self) {
+        #[cfg(not(feature = "ssr"))]
+        {
+            let Some(policy) = self.pause_policy.get_untracked() else {
+                return;
+            };
+
+            self.pause_policy.set(None);
+
+            if policy == PausePolicy::Drop {
+                self.subscribe_all();
+            }
+
+            let paused_msgs = std::mem::take(&mut *self.paused_msgs.write_value());
+            for PausedMsg {
+                key,
+                path,
+                msg,
+                sender,
+            } in paused_msgs
+            {
+                self.dispatch(&key, &path, &msg, sender.as_ref());
+            }
+        }
+    }
+
+    /// Returns a signal that is `true` while the context is paused.
+    pub fn is_paused(self) -> Signal<bool> {
+        let pause_policy = self.pause_policy;
+        Signal::derive(move || pause_policy.with(Option::is_some))
+    }
+
+    /// Returns a signal of the last frame that was received from the server. This is useful for debugging
+    /// and to build custom dispatch logic on top of the context.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// Effect::new(move || {
+    ///     if let Some(frame) = socket.last_frame().get() {
+    ///         leptos::logging::log!("received {frame:?}");
+    ///     }
+    /// });
+    /// ```
+    pub fn last_frame(self) -> Signal<Option<ChannelMsg>> {
+        let message = self.message;
+        let cleared = self.last_frame_cleared;
+
+        Signal::derive(move || if cleared.get() { None } else { message.get() })
+    }
+
+    /// Sets [`SocketContext::last_frame`] to `None` until the next frame is received.
+    pub fn clear_last_frame(self) {
+        self.last_frame_cleared.set(true);
+    }
+
+    #[cfg(not(feature = "ssr"))]
+    fn subscribed_keys(self) -> Vec<CanonicalKey> {
+        self.subscriptions
+            .with_value(|subscriptions| subscriptions.keys().cloned().collect())
+    }
+
+    /// Builds the `Subscribe` frame of the key with the server filter of the key if there is one.
+    #[cfg(not(feature = "ssr"))]
+    fn subscribe_frame(self, key: CanonicalKey) -> ChannelMsg {
+        let filter = self
+            .server_filters
+            .with_value(|filters| filters.get(&key).cloned())
+            .unwrap_or_default();
+
+        ChannelMsg::Subscribe { key, filter }
+    }
+
+    #[cfg(not(feature = "ssr"))]
+    fn subscribe_all(self) {
+        if self.ready_state.get_untracked() == ConnectionReadyState::Open {
+            for key in self.subscribed_keys() {
+                self.send.get_value()(&self.subscribe_frame(key));
+            }
+        }
+    }
+
+    /// Counts how often the connection is opened again after it had been open before.
+    #[cfg(not(feature = "ssr"))]
+    fn count_reconnects_effect(self) {
+        let was_open = StoredValue::new(false);
+
+        Effect::new(move || {
+            if self.ready_state.get() == ConnectionReadyState::Open {
+                if was_open.get_value() {
+                    self.counters
+                        .update(|counters| counters.reconnect_count += 1);
+                }
+
+                was_open.set_value(true);
+            }
+        });
+    }
+
+    /// Marks the connection as failed if it isn't open within `deadline` after it was created or lost.
+    #[cfg(not(feature = "ssr"))]
+    fn connect_deadline_effect(self, deadline: std::time::Duration, stop_after_deadline: bool) {
+        let timeout = StoredValue::new(None::<TimeoutHandle>);
+
+        Effect::new(move || {
+            if self.ready_state.get() == ConnectionReadyState::Open {
+                if let Some(timeout) = timeout.write_value().take() {
+                    timeout.clear();
+                }
+                self.failed.set(false);
+            } else if timeout.with_value(Option::is_none) {
+                let handle = set_timeout_with_handle(
+                    move || {
+                        timeout.set_value(None);
+
+                        if self.ready_state.get_untracked() == ConnectionReadyState::Open {
+                            return;
+                        }
+
+                        self.failed.set(true);
+                        self.last_error
+                            .set(Some(Arc::new(SocketError::ConnectDeadline(deadline))));
+
+                        if stop_after_deadline {
+                            self.close.get_value()();
+                        }
+                    },
+                    deadline,
+                );
+
+                timeout.set_value(handle.ok());
+            }
+        });
+    }
+
+    /// Returns the state of the connection. This is [`ConnectionState::Failed`] if a connect
+    /// deadline is set with [`SocketOptions::connect_deadline`] and the connection couldn't be
+    /// opened in time. Use this to show an error instead of a spinner that never ends.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let state = socket.connection_state();
+    ///
+    /// view! {
+    ///     <Show when=move || state.get() == ConnectionState::Failed>
+    ///         "The server is unreachable"
+    ///         <button on:click=move |_| socket.reconnect()>"Retry"</button>
+    ///     </Show>
+    /// }
+    /// ```
+    pub fn connection_state(self) -> Signal<ConnectionState> {
+        let ready_state = self.ready_state;
+        let failed = self.failed;
+
+        Signal::derive(move || match ready_state.get() {
+            ConnectionReadyState::Open => ConnectionState::Open,
+            _ if failed.get() => ConnectionState::Failed,
+            ConnectionReadyState::Connecting => ConnectionState::Connecting,
+            ConnectionReadyState::Closing => ConnectionState::Closing,
+            ConnectionReadyState::Closed => ConnectionState::Closed,
+        })
+    }
+
+    /// Returns `true` while the connection is open.
+    pub fn is_connected(self) -> Signal<bool> {
+        let ready_state = self.ready_state;
+
+        Signal::derive(move || ready_state.get() == ConnectionReadyState::Open)
+    }
+
+    /// Returns `true` once the server acknowledged the subscription to `key`. Unlike
+    /// [`SocketContext::is_connected`] this means that the messages of the key are received.
+    ///
+    /// It's `false` while connecting, if the server denied the subscription and after the key was
+    /// unsubscribed or closed. After a reconnect it turns `true` again when the server acknowledges
+    /// the new subscription.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// socket.subscribe(ChatKey(room), move |msg: &ChatMsg| { /* ... */ });
+    /// let listening = socket.is_subscribed(ChatKey(room));
+    ///
+    /// view! {
+    ///     <Show when=move || listening.get() fallback=|| "Connecting...">
+    ///         "Live"
+    ///     </Show>
+    /// }
+    /// ```
+    pub fn is_subscribed<Key>(self, key: Key) -> Signal<bool>
+    where
+        Key: serde::Serialize,
+    {
+        let key_value = self
+            .to_key(&key)
+            .map_err(|err| {
+                leptos::logging::error!("Failed to serialize key: {}", err);
+            })
+            .unwrap();
+        let confirmed_keys = self.confirmed_keys;
+
+        Signal::derive(move || confirmed_keys.with(|keys| keys.contains(&key_value)))
+    }
+
+    /// Returns the last error of the connection, for example [`SocketError::ConnectDeadline`].
+    pub fn last_error(self) -> Signal<Option<Arc<SocketError>>> {
+        self.last_error.into()
+    }
+
+    /// Returns the code and reason of the last time the connection was closed, for example by
+    /// `ServerSocketInner::close_client`.
+    ///
+    /// If the code is an application code from 4000 to 4999, the client doesn't reconnect
+    /// automatically. Call [`SocketContext::reconnect`] to connect again.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let navigate = use_navigate();
+    ///
+    /// Effect::new(move || {
+    ///     if socket.last_close().read().as_ref().is_some_and(|close| close.code == 4001) {
+    ///         navigate("/login", Default::default());
+    ///     }
+    /// });
+    /// ```
+    pub fn last_close(self) -> Signal<Option<CloseFrame>> {
+        self.last_close.into()
+    }
+
+    /// Returns a signal with statistics about the connection like the number of sent and received
+    /// messages. This can be rendered in a debug overlay.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let stats = socket.stats();
+    ///
+    /// view! {
+    ///     <pre>{move || format!("{:#?}", stats.get())}</pre>
+    /// }
+    /// ```
+    pub fn stats(self) -> Signal<ConnectionStats> {
+        let counters = self.counters;
+        let ready_state = self.ready_state;
+
+        Signal::derive(move || {
+            let Counters {
+                messages_sent,
+                messages_received,
+                reconnect_count,
+            } = counters.get();
+
+            ConnectionStats {
+                messages_sent,
+                messages_received,
+                reconnect_count,
+                ready_state: ready_state.get(),
+            }
+        })
+    }
+
+    /// Returns the ID that the server assigned to this client. It's `None` until the connection is
+    /// established and changes on every reconnect.
+    ///
+    /// Usually the server identifies the client by the `socket_client_id` cookie when you call
+    /// `send_to_self` from a server function. If cookies are disabled on the server (see
+    /// `ConnectionOptions::client_id_cookie`) send this ID in the [`CLIENT_ID_HEADER`](crate::CLIENT_ID_HEADER)
+    /// header of your server function requests instead.
+    pub fn client_id(self) -> Signal<Option<Uuid>> {
+        self.client_id.into()
+    }
+
+    /// Disconnects and re-connects the WebSocket. This helps if you want to reset the context on the server.
+    /// For example, you can use this method to update the websocket handler context when the user logs out or in.
+    pub fn reconnect(&self) {
+        #[cfg(not(feature = "ssr"))]
+        {
+            self.close.get_value()();
+            self.open.get_value()();
+        }
+    }
+
+    /// When someone sends a message with the given key, the handler will be called.
+    ///
+    /// The subscription is removed when the current reactive [`Owner`] is cleaned up, usually when
+    /// the component that subscribed is unmounted.
+    ///
+    /// Several components can subscribe to the same key. The key is only subscribed once on the
+    /// server, so every message is received once and passed to all handlers. It's unsubscribed when
+    /// the last of these subscriptions is removed. Inside `spawn_local`, after an `.await` or in an
+    /// effect the current owner may not be the one you expect. Use [`SocketContext::subscribe_in`]
+    /// to tie the subscription to a specific owner instead.
+    pub fn subscribe<Msg>(
+        self,
+        key_value: impl Borrow<Msg::Key>,
+        handler: impl Fn(&Msg) + Send + Sync + 'static,
+    ) where
+        Msg: SocketMsg + serde::Serialize + Clone + 'static,
+        for<'de> Msg: serde::Deserialize<'de>,
+        Msg::Key: serde::Serialize,
+        for<'de> Msg::Key: serde::Deserialize<'de>,
+    {
+        #[cfg(feature = "ssr")]
+        {
+            let _ = key_value;
+            let _ = handler;
+        }
+
+        #[cfg(not(feature = "ssr"))]
+        {
+            let key_value = self
+                .to_key(key_value.borrow())
+                .map_err(|err| {
+                    leptos::logging::error!("Failed to serialize key: {}", err);
+                })
+                .unwrap();
+
+            self.add_subscription(key_value, Subscription::new(handler));
+        }
+    }
+
+    /// Like [`SocketContext::subscribe`] but the messages are returned as a [`Stream`](futures_core::Stream)
+    /// instead of being passed to a handler. This fits processing loops in `spawn_local` and
+    /// composes with stream combinators.
+    ///
+    /// Dropping the stream removes only its own subscription. Other handlers of the key keep
+    /// receiving messages and the key is unsubscribed on the server once the last of them is
+    /// removed. During SSR the stream ends right away.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let mut messages = socket.messages_stream::<ChatMsg>(ChatKey { room_id });
+    ///
+    /// spawn_local(async move {
+    ///     while let Some(msg) = messages.next().await {
+    ///         store_in_indexed_db(&msg).await;
+    ///     }
+    /// });
+    /// ```
+    pub fn messages_stream<Msg>(self, key_value: impl Borrow<Msg::Key>) -> MessageStream<Msg>
+    where
+        Msg: SocketMsg + serde::Serialize + Clone + Send + 'static,
+        for<'de> Msg: serde::Deserialize<'de>,
+        Msg::Key: serde::Serialize,
+        for<'de> Msg::Key: serde::Deserialize<'de>,
+    {
+        let (tx, rx) = futures_channel::mpsc::unbounded();
+
+        let key = self
+            .to_key(key_value.borrow())
+            .map_err(|err| {
+                leptos::logging::error!("Failed to serialize key: {}", err);
+            })
+            .unwrap();
+
+        let subscription = Subscription::new(move |msg: &Msg| {
+            let _ = tx.unbounded_send(msg.clone());
+        });
+        let handler = Arc::clone(&subscription.handler);
+
+        #[cfg(feature = "ssr")]
+        {
+            let _ = subscription;
+        }
+
+        #[cfg(not(feature = "ssr"))]
+        self.add_subscription(key.clone(), subscription);
+
+        MessageStream::new(self, key, handler, rx)
+    }
+
+    /// Like [`SocketContext::subscribe`] for several keys at once. The handler is called with the
+    /// messages of all keys.
+    ///
+    /// The keys that aren't subscribed yet are sent to the server in a single frame, which saves
+    /// round trips and lets the server process them together. If the server denies some of them,
+    /// the others are subscribed nevertheless, see [`SocketContext::is_subscribed`].
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// socket.subscribe_many(
+    ///     [MetricKey::Cpu, MetricKey::Memory, MetricKey::Disk],
+    ///     move |msg: &MetricMsg| samples.write().push(msg.clone()),
+    /// );
+    /// ```
+    pub fn subscribe_many<Msg>(
+        self,
+        keys: impl IntoIterator<Item = impl Borrow<Msg::Key>>,
+        handler: impl Fn(&Msg) + Send + Sync + 'static,
+    ) where
+        Msg: SocketMsg + serde::Serialize + Clone + 'static,
+        for<'de> Msg: serde::Deserialize<'de>,
+        Msg::Key: serde::Serialize,
+        for<'de> Msg::Key: serde::Deserialize<'de>,
+    {
+        #[cfg(feature = "ssr")]
+        {
+            let _ = keys;
+            let _ = handler;
+        }
+
+        #[cfg(not(feature = "ssr"))]
+        {
+            let handler = Arc::new(handler);
+            let mut new_keys = Vec::new();
+
+            for key_value in keys {
+                let key_value = self
+                    .to_key(key_value.borrow())
+                    .map_err(|err| {
+                        leptos::logging::error!("Failed to serialize key: {}", err);
+                    })
+                    .unwrap();
+
+                let handler = Arc::clone(&handler);
+                let subscription = Subscription::new(move |msg: &Msg| handler(msg));
+
+                if self.insert_subscription(key_value.clone(), subscription) {
+                    new_keys.push(key_value);
+                }
+            }
+
+            if !new_keys.is_empty()
+                && self.ready_state.get_untracked() == ConnectionReadyState::Open
+            {
+                self.send.get_value()(&ChannelMsg::SubscribeMany { keys: new_keys });
+            }
+        }
+    }
+
+    /// Refetches the resource whenever a message is sent with the given key. This keeps data that
+    /// was loaded with a server function up to date when the server announces a change.
+    ///
+    /// The content of the message is ignored. Works with every resource type, see [`Refetch`].
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let todos = Resource::new(|| (), |_| load_todos());
+    ///
+    /// // The server sends `TodosChanged` after every mutation
+    /// socket.invalidate_on::<TodosChanged>(TodosKey, todos);
+    /// ```
+    pub fn invalidate_on<Msg>(
+        self,
+        key_value: impl Borrow<Msg::Key>,
+        resource: impl Refetch + Send + Sync + 'static,
+    ) where
+        Msg: SocketMsg + serde::Serialize + Clone + 'static,
+        for<'de> Msg: serde::Deserialize<'de>,
+        Msg::Key: serde::Serialize,
+        for<'de> Msg::Key: serde::Deserialize<'de>,
+    {
+        self.subscribe(key_value, move |_: &Msg| resource.refetch());
+    }
+
+    /// Like [`SocketContext::subscribe`] but the subscription is removed when `owner` is cleaned up
+    /// instead of the current owner.
+    ///
+    /// An effect re-runs with a fresh child owner every time, so subscribing in it with the current
+    /// owner unsubscribes on the next run. After an `.await` in `spawn_local` there is no current
+    /// owner at all, so the subscription would never be removed.
+    ///
+    /// Only this subscription is removed when `owner` is cleaned up. The key stays subscribed as
+    /// long as other handlers of it remain on this client.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let owner = Owner::current().unwrap();
+    ///
+    /// spawn_local(async move {
+    ///     let room_id = fetch_room_id().await;
+    ///
+    ///     // Removed when the component is unmounted
+    ///     socket.subscribe_in(&owner, ChatKey { room_id }, |msg: &ChatMsg| {
+    ///         leptos::logging::log!("message: {msg:#?}");
+    ///     });
+    /// });
+    /// ```
+    pub fn subscribe_in<Msg>(
+        self,
+        owner: &Owner,
+        key_value: impl Borrow<Msg::Key>,
+        handler: impl Fn(&Msg) + Send + Sync + 'static,
+    ) where
+        Msg: SocketMsg + serde::Serialize + Clone + 'static,
+        for<'de> Msg: serde::Deserialize<'de>,
+        Msg::Key: serde::Serialize,
+        for<'de> Msg::Key: serde::Deserialize<'de>,
+    {
+        owner.with(|| self.subscribe(key_value, handler));
+    }
+
+    /// Like [`SocketContext::subscribe`] but the messages are deserialized and processed in a Web
+    /// Worker instead of on the main thread. Requires the `worker` feature.
+    ///
+    /// The worker at `script_url` is started as a module worker and has to call
+    /// [`serve_in_worker`](crate::serve_in_worker) with the same message type. The handler is called
+    /// on the main thread with every result the worker posts back. The worker is terminated when the
+    /// subscription is removed.
+    ///
+    /// This is useful for large payloads whose decoding would otherwise block the UI.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let points = RwSignal::new(ChartPoints::default());
+    ///
+    /// // The worker calls `serve_in_worker(|msg: SamplesMsg| ChartPoints::downsample(...))`
+    /// socket.subscribe_in_worker::<SamplesMsg, ChartPoints>(
+    ///     SensorKey { sensor_id },
+    ///     "/pkg/chart_worker.js",
+    ///     move |chart_points| points.set(chart_points.clone()),
+    /// );
+    /// ```
+    #[cfg(feature = "worker")]
+    pub fn subscribe_in_worker<Msg, Out>(
+        self,
+        key_value: impl Borrow<Msg::Key>,
+        script_url: &str,
+        handler: impl Fn(&Out) + Send + Sync + 'static,
+    ) where
+        Msg: SocketMsg + 'static,
+        Msg::Key: serde::Serialize,
+        for<'de> Out: serde::Deserialize<'de>,
+    {
+        #[cfg(feature = "ssr")]
+        {
+            let _ = key_value;
+            let _ = script_url;
+            let _ = handler;
+        }
+
+        #[cfg(not(feature = "ssr"))]
+        {
+            use std::{any::TypeId, rc::Rc};
+
+            use send_wrapper::SendWrapper;
+
+            use super::worker::MessageWorker;
+
+            let key_value = self
+                .to_key(key_value.borrow())
+                .map_err(|err| {
+                    leptos::logging::error!("Failed to serialize key: {}", err);
+                })
+                .unwrap();
+
+            let worker = match MessageWorker::spawn(script_url, handler) {
+                Ok(worker) => SendWrapper::new(worker),
+                Err(err) => {
+                    leptos::logging::error!("Failed to start worker {}: {:?}", script_url, err);
+                    return;
+                }
+            };
+
+            // The message is only passed on, so it's not deserialized on the main thread
+            let subscription = Subscription {
+                type_id: TypeId::of::<Value>(),
+                decode: |msg| Ok(Rc::new(msg.clone())),
+                handler: Arc::new(move |_path: &Value, msg: &dyn std::any::Any, _sender| {
+                    if let Some(msg) = msg.downcast_ref::<Value>() {
+                        worker.post(msg);
+                    }
+                }),
+                on_error: None,
+            };
+
+            self.add_subscription(key_value, subscription);
+        }
+    }
+
+    /// Like [`SocketContext::subscribe`] but the handler is first called with the messages of the
+    /// snapshot that `fetch` resolves to, e.g. the result of a server function that loads the
+    /// current state.
+    ///
+    /// The key is subscribed before the snapshot is fetched, so no message is lost in between.
+    /// Messages that arrive in the meantime are buffered and passed to the handler in order after the
+    /// snapshot. A message can be part of both, so the handler should be able to deal with
+    /// duplicates.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let messages = RwSignal::new(vec![]);
+    ///
+    /// socket.subscribe_with_snapshot(
+    ///     ChatKey { room_id },
+    ///     async move { load_chat_history(room_id).await.unwrap_or_default() },
+    ///     move |msg: &ChatMsg| messages.write().push(msg.clone()),
+    /// );
+    /// ```
+    pub fn subscribe_with_snapshot<Msg, Fut>(
+        self,
+        key_value: impl Borrow<Msg::Key>,
+        fetch: Fut,
+        handler: impl Fn(&Msg) + Send + Sync + 'static,
+    ) where
+        Msg: SocketMsg + serde::Serialize + Clone + Send + Sync + 'static,
+        for<'de> Msg: serde::Deserialize<'de>,
+        Msg::Key: serde::Serialize,
+        for<'de> Msg::Key: serde::Deserialize<'de>,
+        Fut: Future<Output = Vec<Msg>> + 'static,
+    {
+        #[cfg(feature = "ssr")]
+        {
+            let _ = key_value;
+            drop(fetch);
+            let _ = handler;
+        }
+
+        #[cfg(not(feature = "ssr"))]
+        {
+            let handler = Arc::new(handler);
+            // `None` once the snapshot was passed to the handler
+            let buffered = StoredValue::new(Some(Vec::<Msg>::new()));
+
+            self.subscribe(key_value, {
+                let handler = Arc::clone(&handler);
+
+                move |msg: &Msg| {
+                    let is_buffered = buffered
+                        .try_update_value(|buffered| match buffered {
+                            Some(buffered) => {
+                                buffered.push(msg.clone());
+                                true
+                            }
+                            None => false,
+                        })
+                        .unwrap_or_default();
+
+                    if !is_buffered {
+                        handler(msg);
+                    }
+                }
+            });
+
+            leptos::task::spawn_local(async move {
+                let snapshot = fetch.await;
+
+                // Disposed if the subscription was cleaned up in the meantime
+                let Some(Some(buffered)) = buffered.try_update_value(Option::take) else {
+                    return;
+                };
+
+                for msg in snapshot.iter().chain(&buffered) {
+                    handler(msg);
+                }
+            });
+        }
+    }
+
+    /// Like [`SocketContext::subscribe`] but the key is read from a signal. Whenever the key
+    /// changes, the old key is unsubscribed and the new one subscribed, for example when navigating
+    /// between chat rooms.
+    ///
+    /// The subscription to the latest key is removed when the current reactive [`Owner`] is cleaned
+    /// up. Other subscriptions of the old key aren't affected when the key changes.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let params = use_params_map();
+    /// let chat_key = Signal::derive(move || ChatKey {
+    ///     room_id: params.read().get("room_id").unwrap_or_default(),
+    /// });
+    ///
+    /// socket.subscribe_dynamic(chat_key, |msg: &ChatMsg| {
+    ///     leptos::logging::log!("message: {msg:#?}");
+    /// });
+    /// ```
+    pub fn subscribe_dynamic<Msg>(
+        self,
+        key_value: impl Into<Signal<Msg::Key>>,
+        handler: impl Fn(&Msg) + Send + Sync + 'static,
+    ) where
+        Msg: SocketMsg + serde::Serialize + Clone + 'static,
+        for<'de> Msg: serde::Deserialize<'de>,
+        Msg::Key: serde::Serialize + PartialEq + Clone + Send + Sync + 'static,
+        for<'de> Msg::Key: serde::Deserialize<'de>,
+    {
+        #[cfg(feature = "ssr")]
+        {
+            let _ = key_value;
+            let _ = handler;
+        }
+
+        #[cfg(not(feature = "ssr"))]
+        {
+            let key_value = key_value.into();
+            // Only re-subscribe if the key actually changed
+            let key_value = Memo::new(move |_| key_value.get());
+            let handler = Arc::new(handler);
+
+            // Every run of the effect has its own owner which is cleaned up before the next run
+            // and removes the subscription to the previous key.
+            Effect::new(move || {
+                let handler = Arc::clone(&handler);
+                self.subscribe(key_value.get(), move |msg: &Msg| handler(msg));
+            });
+        }
+    }
+
+    /// Returns a handle that is bound to the message type `Msg`. This saves the turbofish in
+    /// components that send and subscribe to the same message type over and over again.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let chat = socket.channel::<ChatMsg>();
+    ///
+    /// chat.subscribe(ChatKey { room_id }, move |msg| messages.write().push(msg.clone()));
+    /// chat.send(ChatKey { room_id }, ChatMsg::new("Hi"));
+    /// chat.unsubscribe(ChatKey { room_id });
+    /// ```
+    pub fn channel<Msg: SocketMsg>(self) -> TypedChannel<Msg> {
+        TypedChannel::new(self)
+    }
+
+    /// Like [`SocketContext::subscribe`] but the message type is fixed by the [`Topic`] so the
+    /// handler can't be for a different message type that happens to use the same key type.
+    pub fn subscribe_topic<Msg>(
+        self,
+        topic: &Topic<Msg>,
+        handler: impl Fn(&Msg) + Send + Sync + 'static,
+    ) where
+        Msg: SocketMsg + serde::Serialize + Clone + 'static,
+        for<'de> Msg: serde::Deserialize<'de>,
+        Msg::Key: serde::Serialize,
+        for<'de> Msg::Key: serde::Deserialize<'de>,
+    {
+        self.subscribe::<Msg>(topic.key(), handler);
+    }
+
+    /// Like [`SocketContext::subscribe`] but the handler also receives the sender of the message.
+    ///
+    /// The sender is only set if the server stamps messages with it, see
+    /// `ServerSocketInner::stamp_sender`, and only for messages that another client sent. It's
+    /// `None` for messages that were sent from the server, for the latest message of a stateful key
+    /// and for messages that this client sent with [`SocketContext::send_optimistic`].
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// socket.subscribe_with_sender(ChatKey { room_id }, |msg: &ChatMsg, sender| {
+    ///     let author = sender
+    ///         .and_then(|sender| sender.meta.as_str())
+    ///         .unwrap_or("anonymous");
+    ///
+    ///     leptos::logging::log!("{author}: {}", msg.text);
+    /// });
+    /// ```
+    pub fn subscribe_with_sender<Msg>(
+        self,
+        key_value: impl Borrow<Msg::Key>,
+        handler: impl Fn(&Msg, Option<&SenderInfo>) + Send + Sync + 'static,
+    ) where
+        Msg: SocketMsg + serde::Serialize + Clone + 'static,
+        for<'de> Msg: serde::Deserialize<'de>,
+        Msg::Key: serde::Serialize,
+        for<'de> Msg::Key: serde::Deserialize<'de>,
+    {
+        #[cfg(feature = "ssr")]
+        {
+            let _ = key_value;
+            let _ = handler;
+        }
+
+        #[cfg(not(feature = "ssr"))]
+        {
+            let key_value = self
+                .to_key(key_value.borrow())
+                .map_err(|err| {
+                    leptos::logging::error!("Failed to serialize key: {}", err);
+                })
+                .unwrap();
+
+            self.add_subscription(key_value, Subscription::with_sender(handler));
+        }
+    }
+
+    /// Like [`SocketContext::subscribe`] but `on_error` is called instead of logging an error if a
+    /// message of the key can't be deserialized into `Msg`.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let (decode_error, set_decode_error) = signal(None::<String>);
+    ///
+    /// socket.subscribe_with_error(
+    ///     ChatKey { room_id },
+    ///     |msg: &ChatMsg| {
+    ///         leptos::logging::log!("message: {msg:#?}");
+    ///     },
+    ///     move |err| set_decode_error.set(Some(err.to_string())),
+    /// );
+    /// ```
+    pub fn subscribe_with_error<Msg>(
+        self,
+        key_value: impl Borrow<Msg::Key>,
+        handler: impl Fn(&Msg) + Send + Sync + 'static,
+        on_error: impl Fn(&serde_json::Error) + Send + Sync + 'static,
+    ) where
+        Msg: SocketMsg + serde::Serialize + Clone + 'static,
+        for<'de> Msg: serde::Deserialize<'de>,
+        Msg::Key: serde::Serialize,
+        for<'de> Msg::Key: serde::Deserialize<'de>,
+    {
+        #[cfg(feature = "ssr")]
+        {
+            let _ = key_value;
+            let _ = handler;
+            let _ = on_error;
+        }
+
+        #[cfg(not(feature = "ssr"))]
+        {
+            let key_value = self
+                .to_key(key_value.borrow())
+                .map_err(|err| {
+                    leptos::logging::error!("Failed to serialize key: {}", err);
+                })
+                .unwrap();
+
+            let subscription = Subscription {
+                on_error: Some(Arc::new(on_error)),
+                ..Subscription::new(handler)
+            };
+
+            self.add_subscription(key_value, subscription);
+        }
+    }
+
+    /// Like [`SocketContext::subscribe`] for hierarchical keys. The handler receives the full path the
+    /// message was sent with, which can be a descendant of the subscribed key.
+    ///
+    /// See `ServerSocketInner::set_hierarchical` for how to enable hierarchical keys on the server.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// socket.subscribe_path(
+    ///     OrgPath(vec!["org".to_string(), "1".to_string()]),
+    ///     |path: &OrgPath, msg: &OrgMsg| {
+    ///         leptos::logging::log!("message in {path:?}: {msg:#?}");
+    ///     },
+    /// );
+    /// ```
+    pub fn subscribe_path<Msg>(
+        self,
+        key_value: impl Borrow<Msg::Key>,
+        handler: impl Fn(&Msg::Key, &Msg) + Send + Sync + 'static,
+    ) where
+        Msg: SocketMsg + serde::Serialize + Clone + 'static,
+        for<'de> Msg: serde::Deserialize<'de>,
+        Msg::Key: serde::Serialize + 'static,
+        for<'de> Msg::Key: serde::Deserialize<'de>,
+    {
+        #[cfg(feature = "ssr")]
+        {
+            let _ = key_value;
+            let _ = handler;
+        }
+
+        #[cfg(not(feature = "ssr"))]
+        {
+            let key_value = self
+                .to_key(key_value.borrow())
+                .map_err(|err| {
+                    leptos::logging::error!("Failed to serialize key: {}", err);
+                })
+                .unwrap();
+
+            let subscription = match self.namespace {
+                Some(_) => Subscription::with_path(move |key: &Namespaced<Msg::Key>, msg| {
+                    handler(&key.key, msg)
+                }),
+                None => Subscription::with_path(handler),
+            };
+
+            self.add_subscription(key_value, subscription);
+        }
+    }
+
+    /// Subscribe to every message of type `Msg` regardless of the key it was sent with. The handler
+    /// receives the key of each message.
+    ///
+    /// The server has to allow this with `ServerSocketInner::register_type::<Msg>(name)`, otherwise
+    /// the subscription is denied. The type is identified by `name` on the wire, so it has to be the
+    /// same on the client and the server.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// socket.subscribe_type("room_msg", |key: &RoomKey, msg: &RoomMsg| {
+    ///     leptos::logging::log!("message in room {key:?}: {msg:#?}");
+    /// });
+    /// ```
+    pub fn subscribe_type<Msg>(
+        self,
+        name: &str,
+        handler: impl Fn(&Msg::Key, &Msg) + Send + Sync + 'static,
+    ) where
+        Msg: SocketMsg + serde::Serialize + Clone + 'static,
+        for<'de> Msg: serde::Deserialize<'de>,
+        Msg::Key: serde::Serialize + 'static,
+        for<'de> Msg::Key: serde::Deserialize<'de>,
+    {
+        #[cfg(feature = "ssr")]
+        {
+            let _ = (name, handler);
+        }
+
+        #[cfg(not(feature = "ssr"))]
+        {
+            let key_value = type_key(name);
+            self.add_subscription(key_value, Subscription::with_path(handler));
+        }
+    }
+
+    /// Stop listening for the messages of the type with the `name` that was passed to
+    /// [`SocketContext::subscribe_type`].
+    pub fn unsubscribe_type(self, name: &str) {
+        #[cfg(feature = "ssr")]
+        {
+            let _ = name;
+        }
+
+        #[cfg(not(feature = "ssr"))]
+        self.unsubscribe_key(type_key(name));
+    }
+
+    #[cfg(not(feature = "ssr"))]
+    fn add_subscription(self, key_value: CanonicalKey, subscription: Subscription) {
+        if self.insert_subscription(key_value.clone(), subscription)
+            && self.ready_state.get_untracked() == ConnectionReadyState::Open
+        {
+            self.send.get_value()(&self.subscribe_frame(key_value));
+        }
+    }
+
+    /// Adds the subscription without subscribing on the server and removes it when the current
+    /// owner is cleaned up. Returns `true` if it's the first subscription of the key.
+    #[cfg(not(feature = "ssr"))]
+    fn insert_subscription(self, key_value: CanonicalKey, subscription: Subscription) -> bool {
+        let handler = Arc::clone(&subscription.handler);
+
+        let is_new_key = {
+            let mut subscriptions = self.subscriptions.write_value();
+            let subscriptions = subscriptions.entry(key_value.clone()).or_default();
+            subscriptions.push(subscription);
+            subscriptions.len() == 1
         };
 
-        let query = if query.is_empty() {
-            String::new()
-        } else {
-            format!("?{query}")
-        };
-        let url = format!("{WEBSOCKET_CHANNEL_URL}{query}");
+        on_cleanup(move || {
+            self.remove_subscription(&key_value, &handler);
+        });
 
-        let UseWebSocketReturn {
-            message,
-            send,
-            ready_state,
-            open,
-            close,
-            ..
-        } = use_websocket_with_options::<ChannelMsg, ChannelMsg, JsonSerdeCodec, _, _>(
-            &url,
-            UseWebSocketOptions::default()
-                .reconnect_limit(ReconnectLimit::Infinite)
-                .on_error(|error| {
-                    leptos::logging::error!("WebSocket error: {}", error);
-                }),
-        );
+        is_new_key
+    }
 
-        Self {
-            message,
-            send: StoredValue::new(Arc::new(send)),
-            ready_state,
-            open: StoredValue::new(Arc::new(open)),
-            close: StoredValue::new(Arc::new(close)),
-            effect_stops: StoredValue::new(HashMap::new()),
-            subscribers: StoredValue::new(HashMap::new()),
+    /// Removes the subscription with this `handler`. The key is only unsubscribed if it was the
+    /// last subscription of the key, so other components that subscribed to the same key keep
+    /// receiving its messages.
+    pub(crate) fn remove_subscription(self, key_value: &CanonicalKey, handler: &HandlerFn) {
+        #[cfg(feature = "ssr")]
+        {
+            let _ = key_value;
+            let _ = handler;
         }
-    }
 
-    /// Disconnects and re-connects the WebSocket. This helps if you want to reset the context on the server.
-    /// For example, you can use this method to update the websocket handler context when the user logs out or in.
-    pub fn reconnect(&self) {
         #[cfg(not(feature = "ssr"))]
         {
-            for (_, stop) in self.effect_stops.write_value().drain() {
-                stop();
-            }
-
-            self.close.get_value()();
-            self.open.get_value()();
+            let is_last = self.subscriptions.with_value(|subscriptions| {
+                subscriptions.get(key_value).is_some_and(|subscriptions| {
+                    subscriptions
+                        .iter()
+                        .all(|subscription| Arc::ptr_eq(&subscription.handler, handler))
+                })
+            });
 
-            for (key, subscriber) in &*self.subscribers.read_value() {
-                self.subscribe_effect(key.clone(), Arc::clone(subscriber));
+            if is_last {
+                self.unsubscribe_key(key_value.clone());
+            } else if let Some(subscriptions) = self.subscriptions.write_value().get_mut(key_value)
+            {
+                subscriptions.retain(|subscription| !Arc::ptr_eq(&subscription.handler, handler));
             }
         }
     }
 
-    /// When someone sends a message with the given key, the handler will be called.
-    pub fn subscribe<Msg>(self, key_value: Msg::Key, handler: impl Fn(&Msg) + Send + Sync + 'static)
-    where
-        Msg: SocketMsg + serde::Serialize + Clone,
+    /// Like [`SocketContext::subscribe`] but the handler is only called for messages that pass the `filter`.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// // Skip my own messages
+    /// socket.subscribe_filtered(
+    ///     ChatKey { room_id },
+    ///     move |msg: &ChatMsg| msg.author_uuid != my_uuid,
+    ///     |msg: &ChatMsg| {
+    ///         leptos::logging::log!("message: {msg:#?}");
+    ///     },
+    /// );
+    /// ```
+    pub fn subscribe_filtered<Msg>(
+        self,
+        key_value: impl Borrow<Msg::Key>,
+        filter: impl Fn(&Msg) -> bool + Send + Sync + 'static,
+        handler: impl Fn(&Msg) + Send + Sync + 'static,
+    ) where
+        Msg: SocketMsg + serde::Serialize + Clone + 'static,
+        for<'de> Msg: serde::Deserialize<'de>,
+        Msg::Key: serde::Serialize,
+        for<'de> Msg::Key: serde::Deserialize<'de>,
+    {
+        self.subscribe(key_value, move |msg: &Msg| {
+            if filter(msg) {
+                handler(msg);
+            }
+        });
+    }
+
+    /// Like [`SocketContext::subscribe`] but the server only sends the messages that pass `filter`.
+    /// Unlike [`SocketContext::subscribe_filtered`] the filtered messages aren't sent over the wire at
+    /// all.
+    ///
+    /// The server evaluates the filter with the evaluator that was registered for the message type
+    /// with `ServerSocketInner::add_subscription_filter_evaluator`. If there is none or it can't
+    /// deserialize the filter, the subscription is denied.
+    ///
+    /// The server keeps one subscription per key and client, so the filter applies to all handlers
+    /// of the key on this client. Subscribing again with another filter replaces it.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// socket.subscribe_with_server_filter(
+    ///     LogKey,
+    ///     MinSeverity { min_severity: WARN },
+    ///     |msg: &LogMsg| {
+    ///         leptos::logging::log!("{}", msg.text);
+    ///     },
+    /// );
+    /// ```
+    pub fn subscribe_with_server_filter<Msg>(
+        self,
+        key_value: impl Borrow<Msg::Key>,
+        filter: impl Serialize,
+        handler: impl Fn(&Msg) + Send + Sync + 'static,
+    ) where
+        Msg: SocketMsg + serde::Serialize + Clone + 'static,
         for<'de> Msg: serde::Deserialize<'de>,
         Msg::Key: serde::Serialize,
         for<'de> Msg::Key: serde::Deserialize<'de>,
@@ -96,76 +1805,113 @@ impl SocketContext {
         #[cfg(feature = "ssr")]
         {
             let _ = key_value;
+            let _ = filter;
             let _ = handler;
         }
 
         #[cfg(not(feature = "ssr"))]
         {
-            let key_value = serde_json::to_value(key_value)
+            let key_value = self
+                .to_key(key_value.borrow())
                 .map_err(|err| {
                     leptos::logging::error!("Failed to serialize key: {}", err);
                 })
                 .unwrap();
 
-            let handler = {
-                let key_value = key_value.clone();
-
-                Arc::new(move || {
-                    if let Some(msg) = self.message.read().as_ref() {
-                        match msg {
-                            ChannelMsg::Msg { msg, key } if &key_value == key => {
-                                match serde_json::from_value(msg.clone()) {
-                                    Err(err) => {
-                                        leptos::logging::error!(
-                                            "Failed to deserialize message: {}",
-                                            err
-                                        );
-                                    }
-                                    Ok(msg) => {
-                                        handler(&msg);
-                                    }
-                                }
-                            }
-                            _ => (),
-                        }
-                    }
-                }) as Arc<dyn Fn() + Send + Sync>
-            };
+            let filter = codec::to_value(&filter)
+                .map_err(|err| {
+                    leptos::logging::error!("Failed to serialize filter: {}", err);
+                })
+                .unwrap();
 
-            self.subscribers
+            let is_new_key = !self.has_subscriptions(&key_value);
+            let changed = self
+                .server_filters
                 .write_value()
-                .insert(key_value.clone(), Arc::clone(&handler));
-            self.subscribe_effect(key_value, handler);
+                .insert(key_value.clone(), filter.clone())
+                .is_none_or(|old_filter| old_filter != filter);
+
+            // A new key is subscribed with the filter by `add_subscription`
+            if changed
+                && !is_new_key
+                && self.ready_state.get_untracked() == ConnectionReadyState::Open
+            {
+                self.send.get_value()(&self.subscribe_frame(key_value.clone()));
+            }
+
+            self.add_subscription(key_value, Subscription::new(handler));
         }
     }
 
-    #[cfg(not(feature = "ssr"))]
-    fn subscribe_effect(self, key_value: Value, handler: Arc<dyn Fn() + Send + Sync>) {
-        Effect::new({
-            let key_value = key_value.clone();
-
-            move || {
-                if self.ready_state.get() == ConnectionReadyState::Open {
-                    self.send.get_value()(&ChannelMsg::Subscribe {
-                        key: key_value.clone(),
-                    });
-                }
-            }
-        });
+    /// Called when the server closes the given key, for example because a chat room was deleted.
+    ///
+    /// The subscription to the key is removed before the handler is called.
+    /// See `ServerSocketInner::drain_key`.
+    pub fn on_key_closed<Key>(self, key: Key, handler: impl Fn() + Send + Sync + 'static)
+    where
+        Key: serde::Serialize,
+    {
+        #[cfg(feature = "ssr")]
+        {
+            let _ = key;
+            let _ = handler;
+        }
+
+        #[cfg(not(feature = "ssr"))]
+        {
+            let key_value = self
+                .to_key(&key)
+                .map_err(|err| {
+                    leptos::logging::error!("Failed to serialize key: {}", err);
+                })
+                .unwrap();
 
-        on_cleanup({
-            let key_value = key_value.clone();
+            self.key_closed_handlers
+                .write_value()
+                .insert(key_value, Arc::new(handler));
+        }
+    }
 
-            move || {
-                self.unsubscribe(key_value);
-            }
-        });
+    /// Called when messages of the given key were missed, for example because the connection was
+    /// lost or the client couldn't keep up with the server. `handler` receives the sequence number
+    /// that was expected and the one that was received instead, so `got - expected` messages are
+    /// missing. Use it to refetch the state of the key.
+    ///
+    /// Gaps are detected among the messages that the server sends to all subscribers of a key.
+    /// Subscriptions with a server-side filter don't report gaps because they don't receive every
+    /// message.
+    ///
+    /// ```ignore
+    /// socket.subscribe(ChatKey(room), move |msg: &ChatMsg| { /* ... */ });
+    ///
+    /// socket.on_gap(ChatKey(room), move |expected, got| {
+    ///     leptos::logging::warn!("Missed {} messages", got - expected);
+    ///     refetch_history.dispatch(room);
+    /// });
+    /// ```
+    pub fn on_gap<Key>(self, key: Key, handler: impl Fn(u64, u64) + Send + Sync + 'static)
+    where
+        Key: serde::Serialize,
+    {
+        #[cfg(feature = "ssr")]
+        {
+            let _ = key;
+            let _ = handler;
+        }
 
-        let effect = Effect::new(move || handler());
+        #[cfg(not(feature = "ssr"))]
+        {
+            let key_value = self
+                .to_key(&key)
+                .map_err(|err| {
+                    leptos::logging::error!("Failed to serialize key: {}", err);
+                })
+                .unwrap();
 
-        self.effect_stops
-            .write_value()
-            .insert(key_value, Box::new(move || effect.stop()));
+            self.gap_handlers
+                .write_value()
+                .insert(key_value, Arc::new(handler));
+        }
     }
 
     /// Stop listening for messages with the given key.
@@ -180,21 +1926,173 @@ impl SocketContext {
 
         #[cfg(not(feature = "ssr"))]
         {
-            let key_value = serde_json::to_value(key)
+            let key_value = self
+                .to_key(&key)
                 .map_err(|err| {
                     leptos::logging::error!("Failed to serialize key: {}", err);
                 })
                 .unwrap();
 
-            self.effect_stops.write_value().remove(&key_value);
-            self.subscribers.write_value().remove(&key_value);
-
-            self.send.get_value()(&ChannelMsg::Unsubscribe { key: key_value });
+            self.unsubscribe_key(key_value);
         }
     }
 
+    #[cfg(not(feature = "ssr"))]
+    fn unsubscribe_key(self, key_value: CanonicalKey) {
+        self.subscriptions.write_value().remove(&key_value);
+        self.key_closed_handlers.write_value().remove(&key_value);
+        self.server_filters.write_value().remove(&key_value);
+        self.last_seqs.write_value().remove(&key_value);
+        self.gap_handlers.write_value().remove(&key_value);
+        #[cfg(feature = "patch")]
+        self.patch_bases.write_value().remove(&key_value);
+        self.unconfirm(&key_value);
+
+        self.send.get_value()(&ChannelMsg::Unsubscribe { key: key_value });
+    }
+
+    /// Like [`SocketContext::unsubscribe`] but with the same types as [`SocketContext::subscribe`].
+    /// The key has to be a `Msg::Key`, so it's serialized exactly like when it was subscribed.
+    ///
+    /// Like `unsubscribe` this removes every handler of the key, including those of other message
+    /// types.
+    ///
+    /// ```ignore
+    /// socket.subscribe(ChatKey { room_id }, move |msg: &ChatMsg| { /* ... */ });
+    ///
+    /// socket.unsubscribe_msg::<ChatMsg>(ChatKey { room_id });
+    /// ```
+    pub fn unsubscribe_msg<Msg>(self, key_value: impl Borrow<Msg::Key>)
+    where
+        Msg: SocketMsg,
+        Msg::Key: serde::Serialize,
+    {
+        self.unsubscribe(key_value.borrow());
+    }
+
     /// Broadcast a message to all subscribers of the given key.
-    pub fn send<Msg>(self, key: Msg::Key, msg: Msg)
+    pub fn send<Msg>(self, key: impl Borrow<Msg::Key>, msg: Msg)
+    where
+        Msg: SocketMsg + serde::Serialize + Clone,
+        for<'de> Msg: serde::Deserialize<'de>,
+        Msg::Key: serde::Serialize,
+        for<'de> Msg::Key: serde::Deserialize<'de>,
+    {
+        self.send_ref(key.borrow(), &msg);
+    }
+
+    /// Sends a JSON patch (RFC 6902) that changes the retained message of the key. Requires the
+    /// `patch` feature.
+    ///
+    /// The server applies the patch and forwards it to all subscribers of the key, including this
+    /// client. If the key has no retained message, the patch doesn't apply cleanly or the patched
+    /// message doesn't pass validation, the server drops it. See `ServerSocketInner::send_patch`.
+    ///
+    /// ```ignore
+    /// socket.send_patch::<Doc>(
+    ///     DocKey(1),
+    ///     json!([{ "op": "replace", "path": "/title", "value": "Final" }]),
+    /// );
+    /// ```
+    #[cfg(feature = "patch")]
+    pub fn send_patch<Msg>(self, key: impl Borrow<Msg::Key>, patch: Value)
+    where
+        Msg: SocketMsg,
+        Msg::Key: serde::Serialize,
+    {
+        #[cfg(feature = "ssr")]
+        {
+            let _ = key;
+            let _ = patch;
+        }
+
+        #[cfg(not(feature = "ssr"))]
+        {
+            let key_value = self
+                .to_key(key.borrow())
+                .map_err(|err| {
+                    leptos::logging::error!("Failed to serialize key: {}", err);
+                })
+                .unwrap();
+
+            self.send.get_value()(&ChannelMsg::Patch {
+                key: key_value,
+                patch,
+                seq: None,
+            });
+        }
+    }
+
+    /// Like [`SocketContext::send`] but only accepts messages of the type the [`Topic`] was created for.
+    pub fn send_topic<Msg>(self, topic: &Topic<Msg>, msg: Msg)
+    where
+        Msg: SocketMsg + serde::Serialize,
+        Msg::Key: serde::Serialize,
+    {
+        self.send_ref(topic.key(), &msg);
+    }
+
+    /// Like [`SocketContext::send`] but takes the key and the message by reference. This avoids
+    /// cloning large messages, for example ones that are held in a signal.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let document = RwSignal::new(Document::default());
+    ///
+    /// document.with(|document| socket.send_ref(&DocumentKey { id: 1 }, document));
+    /// ```
+    pub fn send_ref<Msg>(self, key: &Msg::Key, msg: &Msg)
+    where
+        Msg: SocketMsg + serde::Serialize,
+        Msg::Key: serde::Serialize,
+    {
+        #[cfg(feature = "ssr")]
+        {
+            let _ = key;
+            let _ = msg;
+        }
+
+        #[cfg(not(feature = "ssr"))]
+        {
+            let key_value = self
+                .to_key(key)
+                .map_err(|err| {
+                    leptos::logging::error!("Failed to serialize key: {}", err);
+                })
+                .unwrap();
+
+            let msg_value = codec::to_value(msg)
+                .map_err(|err| {
+                    leptos::logging::error!("Failed to serialize message: {}", err);
+                })
+                .unwrap();
+
+            match self.msg_frame(key_value, msg_value, None) {
+                Ok(frame) => self.send.get_value()(&frame),
+                Err(err) => leptos::logging::error!("{}", err),
+            }
+        }
+    }
+
+    /// Like [`SocketContext::send`] but immediately calls the handlers that are subscribed to the key
+    /// on this client instead of waiting for the server to send the message back. The echo of the
+    /// message from the server is ignored so the handlers are only called once.
+    ///
+    /// Send mappers on the server can't change what the handlers of this client see.
+    ///
+    /// If the connection is lost before the echo arrives, the message is sent again when the
+    /// connection is reopened. The server recognizes messages it has already received from this
+    /// client and only echoes them back, so the other clients receive them once. This relies on the
+    /// client keeping its ID across the reconnect, see `ConnectionOptions::resume_client_id`.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// // The message shows up in the chat right away
+    /// socket.send_optimistic(ChatKey { room: 1 }, ChatMsg { text: "Hi".to_string() });
+    /// ```
+    pub fn send_optimistic<Msg>(self, key: impl Borrow<Msg::Key>, msg: Msg)
     where
         Msg: SocketMsg + serde::Serialize + Clone,
         for<'de> Msg: serde::Deserialize<'de>,
@@ -209,33 +2107,189 @@ impl SocketContext {
 
         #[cfg(not(feature = "ssr"))]
         {
-            let key_value = serde_json::to_value(key)
+            let key_value = self
+                .to_key(key.borrow())
                 .map_err(|err| {
                     leptos::logging::error!("Failed to serialize key: {}", err);
                 })
                 .unwrap();
 
-            let msg_value = serde_json::to_value(msg)
+            let msg_value = codec::to_value(&msg)
                 .map_err(|err| {
                     leptos::logging::error!("Failed to serialize message: {}", err);
                 })
                 .unwrap();
 
-            self.send.get_value()(&ChannelMsg::Msg {
-                msg: msg_value,
-                key: key_value,
+            // Only subscribed keys are echoed by the server
+            let id = self.has_subscriptions(&key_value).then(Uuid::new_v4);
+
+            match self.msg_frame(key_value.clone(), msg_value.clone(), id) {
+                Ok(frame) => {
+                    if let Some(id) = id {
+                        self.pending_echoes.write_value().insert(id, frame.clone());
+                    }
+
+                    self.send.get_value()(&frame);
+                    self.dispatch_decrypted(&key_value, &key_value, &msg_value, None);
+                }
+                Err(err) => leptos::logging::error!("{}", err),
+            }
+        }
+    }
+
+    /// Like [`SocketContext::send`] but waits until the connection is open and returns once the
+    /// message has been handed to the underlying WebSocket.
+    ///
+    /// This is useful for flows that need to make sure a message has been sent before navigating
+    /// away and for deterministic end-to-end tests.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// spawn_local(async move {
+    ///     socket.send_flushed(MyKey { bla: "bla".to_string() }, msg).await.unwrap();
+    ///     navigate("/somewhere", Default::default());
+    /// });
+    /// ```
+    pub async fn send_flushed<Msg>(
+        self,
+        key: impl Borrow<Msg::Key>,
+        msg: Msg,
+    ) -> Result<(), SocketError>
+    where
+        Msg: SocketMsg + serde::Serialize + Clone,
+        for<'de> Msg: serde::Deserialize<'de>,
+        Msg::Key: serde::Serialize,
+        for<'de> Msg::Key: serde::Deserialize<'de>,
+    {
+        let key_value = self.to_key(key.borrow())?;
+        let msg_value = codec::to_value(&msg)?;
+
+        let frame = self.msg_frame(key_value, msg_value, None)?;
+
+        self.wait_until_open().await?;
+
+        self.send.get_value()(&frame);
+
+        Ok(())
+    }
+
+    /// Builds the frame for sending a message. Encrypts the message if a cipher is set.
+    fn msg_frame(
+        self,
+        key: CanonicalKey,
+        msg: Value,
+        id: Option<Uuid>,
+    ) -> Result<ChannelMsg, SocketError> {
+        let msg = match self.cipher.get_value() {
+            Some(cipher) => cipher.encrypt(&key, msg).map_err(SocketError::Cipher)?,
+            None => msg,
+        };
+
+        Ok(ChannelMsg::Msg {
+            key,
+            msg,
+            id,
+            sender: None,
+            seq: None,
+        })
+    }
+
+    /// Sets the cipher that is used to encrypt the payload of all sent messages and to decrypt the
+    /// payload of all received messages. Keys are not encrypted because the server needs them for routing.
+    ///
+    /// See [`PayloadCipher`] for details.
+    pub fn set_cipher(self, cipher: impl PayloadCipher) {
+        self.cipher.set_value(Some(Arc::new(cipher)));
+    }
+
+    async fn wait_until_open(self) -> Result<(), SocketError> {
+        #[cfg(feature = "ssr")]
+        {
+            Err(SocketError::Disconnected)
+        }
+
+        #[cfg(not(feature = "ssr"))]
+        {
+            use futures_channel::oneshot;
+            use std::sync::Mutex;
+
+            if self.ready_state.get_untracked() == ConnectionReadyState::Open {
+                return Ok(());
+            }
+
+            let (tx, rx) = oneshot::channel();
+            let tx = Mutex::new(Some(tx));
+
+            let effect = Effect::new(move || {
+                if self.ready_state.get() == ConnectionReadyState::Open
+                    && let Some(tx) = tx.lock().unwrap().take()
+                {
+                    let _ = tx.send(());
+                }
             });
+
+            let result = rx.await.map_err(|_| SocketError::Disconnected);
+            effect.stop();
+
+            result
         }
     }
 }
 
+/// Options for [`provide_socket_context_with_options`].
+#[derive(Clone, Debug, Default)]
+pub struct SocketOptions {
+    query: String,
+    reconnect_backoff: ReconnectBackoff,
+    connect_deadline: Option<std::time::Duration>,
+    stop_after_deadline: bool,
+}
+
+impl SocketOptions {
+    /// Query parameters that are appended to the URL of the socket. See [`provide_socket_context_with_query`].
+    pub fn query<T: Serialize + ?Sized>(mut self, query: &T) -> Self {
+        self.query = serde_urlencoded::to_string(query).expect("Failed to serialize query");
+        self
+    }
+
+    /// How long to wait before reconnecting after the connection was lost. Defaults to
+    /// [`ReconnectBackoff::default`].
+    pub fn reconnect_backoff(mut self, backoff: ReconnectBackoff) -> Self {
+        self.reconnect_backoff = backoff;
+        self
+    }
+
+    /// If the connection isn't open within `deadline` after the context was created or the
+    /// connection was lost, [`SocketContext::connection_state`] becomes [`ConnectionState::Failed`]
+    /// and [`SocketContext::last_error`] is set to [`SocketError::ConnectDeadline`]. Disabled by
+    /// default.
+    ///
+    /// The client keeps trying to reconnect after the deadline unless this is disabled with
+    /// [`SocketOptions::retry_after_deadline`].
+    pub fn connect_deadline(mut self, deadline: std::time::Duration) -> Self {
+        self.connect_deadline = Some(deadline);
+        self
+    }
+
+    /// Whether the client keeps trying to reconnect after the connect deadline passed. Defaults to
+    /// `true`. If disabled, call [`SocketContext::reconnect`] to try again.
+    pub fn retry_after_deadline(mut self, retry: bool) -> Self {
+        self.stop_after_deadline = !retry;
+        self
+    }
+}
+
 /// Call this in your root component to provide the socket context.
+///
+/// During SSR this provides an inert context that doesn't connect. The connection is established
+/// after hydration.
 #[inline(always)]
 pub fn provide_socket_context() -> SocketContext {
     if let Some(ctx) = use_context::<SocketContext>() {
         ctx
     } else {
-        let ctx = SocketContext::new("".to_string());
+        let ctx = SocketContext::new(SocketOptions::default());
         provide_context(ctx);
         ctx
     }
@@ -250,19 +2304,101 @@ pub fn provide_socket_context() -> SocketContext {
 /// ```
 #[inline(always)]
 pub fn provide_socket_context_with_query<T: Serialize + ?Sized>(query: &T) -> SocketContext {
-    let query_string = serde_urlencoded::to_string(query).expect("Failed to serialize query");
-    let ctx = SocketContext::new(query_string);
+    provide_socket_context_with_options(SocketOptions::default().query(query))
+}
+
+/// Call this in your root component to provide the socket context with [`SocketOptions`].
+///
+/// ## Example
+///
+/// ```ignore
+/// provide_socket_context_with_options(
+///     SocketOptions::default()
+///         .reconnect_backoff(ReconnectBackoff::new(Duration::from_secs(2), Duration::from_secs(60))),
+/// );
+/// ```
+#[inline(always)]
+pub fn provide_socket_context_with_options(options: SocketOptions) -> SocketContext {
+    let ctx = SocketContext::new(options);
+    provide_context(ctx);
+    ctx
+}
+
+/// A connection to the server that is used instead of a WebSocket, for example WebTransport. See
+/// [`provide_socket_context_with_transport`].
+///
+/// The frames are serialized the same way as over a WebSocket, so that the server can serve the
+/// transport with `handlers::handle_connection`.
+pub struct SocketTransport {
+    /// The latest frame that was received from the server.
+    pub message: Signal<Option<ChannelMsg>>,
+    /// Sends a frame to the server.
+    pub send: Arc<dyn Fn(&ChannelMsg) + Send + Sync>,
+    /// The state of the connection. The keys are subscribed again whenever it becomes `Open`.
+    pub ready_state: Signal<ConnectionReadyState>,
+    /// Opens the connection again. See [`SocketContext::reconnect`].
+    pub open: Arc<dyn Fn() + Send + Sync>,
+    /// Closes the connection.
+    pub close: Arc<dyn Fn() + Send + Sync>,
+}
+
+/// Call this in your root component to provide a socket context that talks to the server over
+/// the given [`SocketTransport`] instead of a WebSocket.
+///
+/// Reconnecting is up to the transport. [`SocketOptions`] don't apply.
+///
+/// ## Example
+///
+/// ```ignore
+/// let (message, set_message) = signal(None);
+/// let (ready_state, set_ready_state) = signal(ConnectionReadyState::Connecting);
+/// let transport = MyWebTransport::connect("/socket", set_message, set_ready_state);
+///
+/// provide_socket_context_with_transport(SocketTransport {
+///     message: message.into(),
+///     send: Arc::new(move |msg| transport.send(serde_json::to_string(msg).unwrap())),
+///     ready_state: ready_state.into(),
+///     open: Arc::new(move || transport.open()),
+///     close: Arc::new(move || transport.close()),
+/// });
+/// ```
+pub fn provide_socket_context_with_transport(transport: SocketTransport) -> SocketContext {
+    let ctx = SocketContext::with_transport(
+        transport.message,
+        transport.send,
+        transport.ready_state,
+        transport.open,
+        transport.close,
+        RwSignal::new(None),
+    );
+
+    #[cfg(not(feature = "ssr"))]
+    {
+        ctx.dispatch_effect();
+        ctx.subscribe_on_open_effect();
+        ctx.count_reconnects_effect();
+    }
+
     provide_context(ctx);
     ctx
 }
 
 /// Call this when you want to subscribe or send a message in your component.
+///
+/// Panics if [`provide_socket_context`] hasn't been called in a parent component.
+/// Use [`use_socket_context`] if you want to handle that case yourself.
 #[inline(always)]
+#[track_caller]
 pub fn expect_socket_context() -> SocketContext {
-    expect_context()
+    use_context().expect(
+        "SocketContext not found. Did you forget to call `provide_socket_context()` in your root component?",
+    )
 }
 
 /// Call this when you want to subscribe or send a message in your component.
+///
+/// Returns `None` instead of panicking if [`provide_socket_context`] hasn't been called in a parent
+/// component. This way you can render a fallback when realtime communication isn't available.
 #[inline(always)]
 pub fn use_socket_context() -> Option<SocketContext> {
     use_context()
@@ -0,0 +1,93 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt::{Debug, Formatter},
+    sync::Arc,
+};
+
+/// Several independently typed context values of a connection. Pass it as the context to
+/// [`upgrade_websocket`](crate::handlers::upgrade_websocket) and every filter, send mapper and
+/// sender stamp can request just the type it needs instead of one combined context struct.
+///
+/// ```
+/// # use axum::{extract::{State, WebSocketUpgrade}, response::Response};
+/// # use leptos_axum_socket::{ContextMap, ServerSocket, handlers::upgrade_websocket};
+/// # use serde::{Serialize, Deserialize};
+/// #
+/// #[derive(Clone)]
+/// pub struct UserId(u64);
+///
+/// #[derive(Clone)]
+/// pub struct AllowedRooms(Vec<u64>);
+///
+/// #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+/// pub struct RoomKey(u64);
+///
+/// # async fn init(socket: ServerSocket) {
+/// socket
+///     .lock()
+///     .await
+///     .add_subscribe_filter(|key: RoomKey, allowed: AllowedRooms| async move {
+///         allowed.0.contains(&key.0)
+///     });
+/// # }
+///
+/// pub async fn connect_to_websocket(
+///     ws: WebSocketUpgrade,
+///     State(socket): State<ServerSocket>,
+/// ) -> Response {
+///     let ctx = ContextMap::new()
+///         .with(UserId(1))
+///         .with(AllowedRooms(vec![1, 2]));
+///
+///     upgrade_websocket(ws, socket, ctx)
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct ContextMap(HashMap<TypeId, Arc<dyn Any + Send + Sync>>);
+
+impl ContextMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the value. A value of the same type that was added before is replaced.
+    pub fn with<T>(mut self, value: T) -> Self
+    where
+        T: Send + Sync + 'static,
+    {
+        self.insert(value);
+        self
+    }
+
+    /// Adds the value. A value of the same type that was added before is replaced.
+    pub fn insert<T>(&mut self, value: T)
+    where
+        T: Send + Sync + 'static,
+    {
+        self.0.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// Returns the value of type `T` if one was added.
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.0
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+}
+
+impl Debug for ContextMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ContextMap")
+            .field("len", &self.0.len())
+            .finish()
+    }
+}
+
+/// Returns the context of type `C`. It's either the context of the connection itself or a value of
+/// the [`ContextMap`] that was passed as the context.
+pub(crate) fn context_of<C: 'static>(ctx: &dyn Any) -> &C {
+    ctx.downcast_ref()
+        .or_else(|| ctx.downcast_ref::<ContextMap>()?.get())
+        .expect("Invalid context type")
+}
@@ -0,0 +1,238 @@
+//! The transport independent part of the client side message dispatch.
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    rc::Rc,
+    sync::Arc,
+};
+
+use serde_json::Value;
+
+use crate::{CanonicalKey, SenderInfo};
+
+pub(crate) type DecodeFn = fn(&Value) -> serde_json::Result<Rc<dyn Any>>;
+pub(crate) type HandlerFn = Arc<dyn Fn(&Value, &dyn Any, Option<&SenderInfo>) + Send + Sync>;
+pub(crate) type ErrorFn = Arc<dyn Fn(&serde_json::Error) + Send + Sync>;
+
+/// A handler that was registered with [`SocketContext::subscribe`](crate::SocketContext::subscribe) together with the means to
+/// deserialize the message type it expects.
+#[derive(Clone)]
+pub(crate) struct Subscription {
+    pub(crate) type_id: TypeId,
+    pub(crate) decode: DecodeFn,
+    pub(crate) handler: HandlerFn,
+    /// Called instead of logging if the message can't be deserialized.
+    pub(crate) on_error: Option<ErrorFn>,
+}
+
+impl Subscription {
+    pub(crate) fn new<Msg>(handler: impl Fn(&Msg) + Send + Sync + 'static) -> Self
+    where
+        for<'de> Msg: serde::Deserialize<'de>,
+        Msg: 'static,
+    {
+        Self {
+            type_id: TypeId::of::<Msg>(),
+            decode: |msg| Msg::deserialize(msg).map(|msg| Rc::new(msg) as Rc<dyn Any>),
+            handler: Arc::new(move |_path: &Value, msg: &dyn Any, _sender| {
+                if let Some(msg) = msg.downcast_ref::<Msg>() {
+                    handler(msg);
+                }
+            }),
+            on_error: None,
+        }
+    }
+
+    /// Like [`Subscription::new`] but the handler also receives the sender the server stamped the
+    /// message with.
+    pub(crate) fn with_sender<Msg>(
+        handler: impl Fn(&Msg, Option<&SenderInfo>) + Send + Sync + 'static,
+    ) -> Self
+    where
+        for<'de> Msg: serde::Deserialize<'de>,
+        Msg: 'static,
+    {
+        Self {
+            type_id: TypeId::of::<Msg>(),
+            decode: |msg| Msg::deserialize(msg).map(|msg| Rc::new(msg) as Rc<dyn Any>),
+            handler: Arc::new(move |_path: &Value, msg: &dyn Any, sender| {
+                if let Some(msg) = msg.downcast_ref::<Msg>() {
+                    handler(msg, sender);
+                }
+            }),
+            on_error: None,
+        }
+    }
+
+    /// Like [`Subscription::new`] but the handler also receives the full path the message was sent with.
+    pub(crate) fn with_path<Msg, Key>(handler: impl Fn(&Key, &Msg) + Send + Sync + 'static) -> Self
+    where
+        for<'de> Msg: serde::Deserialize<'de>,
+        for<'de> Key: serde::Deserialize<'de>,
+        Msg: 'static,
+    {
+        Self {
+            type_id: TypeId::of::<Msg>(),
+            decode: |msg| Msg::deserialize(msg).map(|msg| Rc::new(msg) as Rc<dyn Any>),
+            handler: Arc::new(move |path: &Value, msg: &dyn Any, _sender| {
+                let Some(msg) = msg.downcast_ref::<Msg>() else {
+                    return;
+                };
+
+                match Key::deserialize(path) {
+                    Ok(path) => handler(&path, msg),
+                    Err(err) => {
+                        leptos::logging::error!("Failed to deserialize key path: {}", err);
+                    }
+                }
+            }),
+            on_error: None,
+        }
+    }
+}
+
+/// Returns the subscriptions of `key`. They are cloned so that the handlers can subscribe and
+/// unsubscribe while they are called.
+pub(crate) fn subscriptions_of(
+    subscriptions: &HashMap<CanonicalKey, Vec<Subscription>>,
+    key: &CanonicalKey,
+) -> Vec<Subscription> {
+    subscriptions.get(key).cloned().unwrap_or_default()
+}
+
+/// Calls every subscription with the message. The message is deserialized once per message type.
+/// Subscriptions whose type doesn't match get the decode error instead.
+pub(crate) fn dispatch(
+    subscriptions: &[Subscription],
+    path: &Value,
+    msg: &Value,
+    sender: Option<&SenderInfo>,
+) {
+    let mut decoded = HashMap::<TypeId, Result<Rc<dyn Any>, Rc<serde_json::Error>>>::new();
+
+    for subscription in subscriptions {
+        let msg = decoded
+            .entry(subscription.type_id)
+            .or_insert_with(|| (subscription.decode)(msg).map_err(Rc::new))
+            .clone();
+
+        match msg {
+            Ok(msg) => (subscription.handler)(path, &*msg, sender),
+            Err(err) => match &subscription.on_error {
+                Some(on_error) => on_error(&err),
+                None => leptos::logging::error!("Failed to deserialize message: {}", err),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Chat {
+        text: String,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Count(u32);
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    fn chat(log: &Log, name: &'static str) -> Subscription {
+        let log = Arc::clone(log);
+        Subscription::new(move |msg: &Chat| {
+            log.lock().unwrap().push(format!("{name}: {}", msg.text))
+        })
+    }
+
+    fn dispatch_to(
+        subscriptions: &HashMap<CanonicalKey, Vec<Subscription>>,
+        key: &CanonicalKey,
+        msg: Value,
+    ) {
+        dispatch(
+            &subscriptions_of(subscriptions, key),
+            key.as_value(),
+            &msg,
+            None,
+        );
+    }
+
+    #[test]
+    fn only_the_handlers_of_the_matching_key_are_called() {
+        let log = Log::default();
+        let lobby = CanonicalKey::from(json!("lobby"));
+        let support = CanonicalKey::from(json!("support"));
+
+        let subscriptions = HashMap::from([
+            (lobby.clone(), vec![chat(&log, "lobby")]),
+            (support.clone(), vec![chat(&log, "support")]),
+        ]);
+
+        dispatch_to(&subscriptions, &lobby, json!({ "text": "hi" }));
+        dispatch_to(
+            &subscriptions,
+            &CanonicalKey::from(json!("other")),
+            json!({ "text": "lost" }),
+        );
+
+        assert_eq!(*log.lock().unwrap(), ["lobby: hi"]);
+    }
+
+    #[test]
+    fn type_mismatches_are_reported_to_the_error_handler() {
+        let log = Log::default();
+        let key = CanonicalKey::from(json!("lobby"));
+
+        let on_error = {
+            let log = Arc::clone(&log);
+            move |err: &serde_json::Error| log.lock().unwrap().push(format!("error: {err}"))
+        };
+        let subscriptions = HashMap::from([(
+            key.clone(),
+            vec![Subscription {
+                on_error: Some(Arc::new(on_error)),
+                ..chat(&log, "chat")
+            }],
+        )]);
+
+        dispatch_to(&subscriptions, &key, json!(42));
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 1);
+        assert!(log[0].starts_with("error: "), "{log:?}");
+    }
+
+    #[test]
+    fn every_handler_of_a_key_is_called() {
+        let log = Log::default();
+        let key = CanonicalKey::from(json!("lobby"));
+
+        let count = {
+            let log = Arc::clone(&log);
+            Subscription::new(move |msg: &Count| {
+                log.lock().unwrap().push(format!("count: {}", msg.0))
+            })
+        };
+        let subscriptions = HashMap::from([(
+            key.clone(),
+            vec![chat(&log, "first"), count, chat(&log, "second")],
+        )]);
+
+        dispatch_to(&subscriptions, &key, json!({ "text": "hi" }));
+        dispatch_to(&subscriptions, &key, json!(7));
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["first: hi", "second: hi", "count: 7"]
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use std::{
+    fmt::{Display, Formatter},
+    time::Duration,
+};
+
+/// Errors that can occur when sending messages from the client.
+#[derive(Debug)]
+pub enum SocketError {
+    /// The key or the message couldn't be serialized.
+    Serialize(serde_json::Error),
+    /// The connection was closed before the message could be sent.
+    Disconnected,
+    /// The [`PayloadCipher`](crate::PayloadCipher) failed to encrypt the message.
+    Cipher(String),
+    /// The connection wasn't opened within the deadline. See
+    /// [`SocketOptions::connect_deadline`](crate::SocketOptions::connect_deadline).
+    ConnectDeadline(Duration),
+}
+
+impl Display for SocketError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Serialize(err) => write!(f, "Failed to serialize: {err}"),
+            Self::Disconnected => write!(f, "The socket is disconnected"),
+            Self::Cipher(err) => write!(f, "Failed to encrypt: {err}"),
+            Self::ConnectDeadline(deadline) => {
+                write!(f, "Failed to connect within {}s", deadline.as_secs_f64())
+            }
+        }
+    }
+}
+
+impl std::error::Error for SocketError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Serialize(err) => Some(err),
+            Self::Disconnected | Self::Cipher(_) | Self::ConnectDeadline(_) => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for SocketError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Serialize(err)
+    }
+}
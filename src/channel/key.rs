@@ -0,0 +1,172 @@
+use std::{borrow::Cow, fmt::Display, ops::Deref};
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Number, Value};
+
+/// A serialized key in a canonical form. All keys are compared in this form on the client and
+/// the server.
+///
+/// Two keys that are semantically equal can serialize differently, for example `1.0` and `1` or
+/// objects whose fields are in a different order. Such keys would never match so that a client
+/// would be subscribed but never receive anything. To prevent this, numbers that are integers are
+/// stored as integers, `-0.0` is stored as `0`, object fields that are `null` are left out and the
+/// fields of objects are sorted.
+///
+/// Keys have their own codec that is independent of how messages are serialized. The
+/// `json-skip-nulls` and `json-pretty` features only apply to messages, so keys match even if the
+/// client and the server are built with different features.
+///
+/// ```
+/// # use leptos_axum_socket::CanonicalKey;
+/// # use serde_json::json;
+/// #
+/// assert_eq!(
+///     CanonicalKey::new(json!({ "b": 2.0, "a": 1, "c": null })),
+///     CanonicalKey::new(json!({ "a": 1, "b": 2 })),
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(transparent)]
+pub struct CanonicalKey(Value);
+
+impl CanonicalKey {
+    pub fn new(value: Value) -> Self {
+        Self(canonicalize(value))
+    }
+
+    /// Serializes a key into its canonical form. This is how the client and the server serialize
+    /// all keys.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::CanonicalKey;
+    /// # use serde::Serialize;
+    /// #
+    /// #[derive(Serialize)]
+    /// struct RoomKey {
+    ///     id: f64,
+    ///     topic: Option<String>,
+    /// }
+    ///
+    /// let key = CanonicalKey::from_key(&RoomKey { id: 1.0, topic: None }).unwrap();
+    /// assert_eq!(key.to_string(), r#"{"id":1}"#);
+    /// ```
+    pub fn from_key<K>(key: &K) -> serde_json::Result<Self>
+    where
+        K: Serialize + ?Sized,
+    {
+        serde_json::to_value(key).map(Self::new)
+    }
+
+    /// Returns the canonical form of the key as JSON value.
+    pub fn as_value(&self) -> &Value {
+        &self.0
+    }
+
+    pub fn into_value(self) -> Value {
+        self.0
+    }
+}
+
+impl From<Value> for CanonicalKey {
+    fn from(value: Value) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<CanonicalKey> for Value {
+    fn from(key: CanonicalKey) -> Self {
+        key.0
+    }
+}
+
+impl Deref for CanonicalKey {
+    type Target = Value;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Display for CanonicalKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<'de> Deserialize<'de> for CanonicalKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Value::deserialize(deserializer).map(Self::new)
+    }
+}
+
+/// A key that is scoped to a namespace. This is how the keys of a
+/// [`SocketContext::with_namespace`](crate::SocketContext::with_namespace) are sent over the wire.
+///
+/// The server routes namespaced keys like any other key. To send to the subscribers of a
+/// namespaced key from the server, use a message type whose key is a `Namespaced` key.
+///
+/// ```
+/// # use leptos_axum_socket::Namespaced;
+/// # use serde_json::json;
+/// #
+/// assert_eq!(
+///     serde_json::to_value(Namespaced::new("mylib", "room")).unwrap(),
+///     json!({ "namespace": "mylib", "key": "room" }),
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Namespaced<K> {
+    pub namespace: Cow<'static, str>,
+    pub key: K,
+}
+
+impl<K> Namespaced<K> {
+    pub fn new(namespace: impl Into<Cow<'static, str>>, key: K) -> Self {
+        Self {
+            namespace: namespace.into(),
+            key,
+        }
+    }
+}
+
+fn canonicalize(value: Value) -> Value {
+    match value {
+        Value::Number(number) => Value::Number(canonicalize_number(number)),
+        Value::Array(values) => Value::Array(values.into_iter().map(canonicalize).collect()),
+        Value::Object(map) => {
+            let mut entries = map
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, canonicalize(value)))
+                    .collect::<Map<_, _>>(),
+            )
+        }
+        value => value,
+    }
+}
+
+fn canonicalize_number(number: Number) -> Number {
+    match number.as_f64() {
+        Some(float) if number.is_f64() && float.fract() == 0.0 => {
+            if float == 0.0 {
+                Number::from(0)
+            } else if float >= i64::MIN as f64 && float < i64::MAX as f64 {
+                Number::from(float as i64)
+            } else if float >= 0.0 && float < u64::MAX as f64 {
+                Number::from(float as u64)
+            } else {
+                number
+            }
+        }
+        _ => number,
+    }
+}
@@ -0,0 +1,190 @@
+//! Client side of the HTTP long-polling fallback that is used when the WebSocket can't connect.
+
+use leptos::prelude::*;
+use leptos_use::core::ConnectionReadyState;
+
+use crate::ChannelMsg;
+
+/// Wraps the WebSocket transport so that it switches to long-polling if the WebSocket fails to
+/// connect in the first place. Returns the `message` signal, `send` function and `ready_state` signal
+/// of whatever transport is currently active.
+pub(crate) fn with_fallback<S, C>(
+    ws_message: Signal<Option<ChannelMsg>>,
+    ws_send: S,
+    ws_ready_state: Signal<ConnectionReadyState>,
+    ws_close: C,
+    query: String,
+) -> (
+    Signal<Option<ChannelMsg>>,
+    impl Fn(&ChannelMsg) + Clone + Send + Sync + 'static,
+    Signal<ConnectionReadyState>,
+)
+where
+    S: Fn(&ChannelMsg) + Clone + Send + Sync + 'static,
+    C: Fn() + Clone + Send + Sync + 'static,
+{
+    let active = RwSignal::new(false);
+    let lp_message = RwSignal::new(None::<ChannelMsg>);
+    let lp_ready_state = RwSignal::new(ConnectionReadyState::Closed);
+
+    #[cfg(feature = "ssr")]
+    {
+        let _ = ws_close;
+        let _ = query;
+    }
+
+    #[cfg(not(feature = "ssr"))]
+    {
+        let ws_opened = StoredValue::new(false);
+        let prev_ws_ready_state = StoredValue::new(ConnectionReadyState::Closed);
+        let query = query.clone();
+
+        Effect::new(move || {
+            let ready_state = ws_ready_state.get();
+            let prev_ready_state = prev_ws_ready_state.get_value();
+            prev_ws_ready_state.set_value(ready_state);
+
+            match ready_state {
+                ConnectionReadyState::Open => ws_opened.set_value(true),
+                ConnectionReadyState::Closed
+                    if prev_ready_state == ConnectionReadyState::Connecting
+                        && !ws_opened.get_value()
+                        && !active.get_untracked() =>
+                {
+                    leptos::logging::warn!(
+                        "WebSocket failed to connect. Falling back to long-polling."
+                    );
+
+                    ws_close();
+                    active.set(true);
+
+                    leptos::task::spawn_local(client::poll_loop(
+                        query.clone(),
+                        lp_ready_state,
+                        lp_message,
+                    ));
+                }
+                _ => (),
+            }
+        });
+    }
+
+    let message = Signal::derive(move || {
+        if active.get() {
+            lp_message.get()
+        } else {
+            ws_message.get()
+        }
+    });
+
+    let ready_state = Signal::derive(move || {
+        if active.get() {
+            lp_ready_state.get()
+        } else {
+            ws_ready_state.get()
+        }
+    });
+
+    let send = move |msg: &ChannelMsg| {
+        if active.get_untracked() {
+            #[cfg(not(feature = "ssr"))]
+            leptos::task::spawn_local(client::post(query.clone(), msg.clone()));
+        } else {
+            ws_send(msg);
+        }
+    };
+
+    (message, send, ready_state)
+}
+
+#[cfg(not(feature = "ssr"))]
+mod client {
+    use std::time::Duration;
+
+    use gloo_net::http::Request;
+    use leptos::prelude::*;
+    use leptos_use::core::ConnectionReadyState;
+
+    use crate::{ChannelMsg, LONGPOLL_POLL_URL, LONGPOLL_SEND_URL};
+
+    /// How long to wait before polling again after a failed poll.
+    const RETRY_INTERVAL: Duration = Duration::from_secs(3);
+
+    pub(super) async fn poll_loop(
+        query: String,
+        ready_state: RwSignal<ConnectionReadyState>,
+        message: RwSignal<Option<ChannelMsg>>,
+    ) {
+        let url = format!("{LONGPOLL_POLL_URL}{query}");
+
+        set_ready_state(ready_state, ConnectionReadyState::Connecting);
+
+        while !ready_state.is_disposed() {
+            match Request::get(&url).send().await {
+                Ok(response) if response.ok() => {
+                    // A new session has been created so subscriptions have to be renewed
+                    if response.status() == 201 {
+                        set_ready_state(ready_state, ConnectionReadyState::Connecting);
+                        leptos::task::tick().await;
+                    }
+
+                    match response.json::<Vec<ChannelMsg>>().await {
+                        Ok(msgs) => {
+                            set_ready_state(ready_state, ConnectionReadyState::Open);
+
+                            for msg in msgs {
+                                message.set(Some(msg));
+                                // give the subscribers a chance to handle each message
+                                leptos::task::tick().await;
+                            }
+                        }
+                        Err(err) => {
+                            leptos::logging::error!(
+                                "Failed to parse long-polling response: {}",
+                                err
+                            );
+                        }
+                    }
+                }
+                Ok(response) => {
+                    leptos::logging::error!(
+                        "Long-polling failed with status {}",
+                        response.status()
+                    );
+                    set_ready_state(ready_state, ConnectionReadyState::Closed);
+                    gloo_timers::future::sleep(RETRY_INTERVAL).await;
+                }
+                Err(err) => {
+                    leptos::logging::error!("Long-polling failed: {}", err);
+                    set_ready_state(ready_state, ConnectionReadyState::Closed);
+                    gloo_timers::future::sleep(RETRY_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    pub(super) async fn post(query: String, msg: ChannelMsg) {
+        let url = format!("{LONGPOLL_SEND_URL}{query}");
+
+        let result = match Request::post(&url).json(&msg) {
+            Ok(request) => request.send().await,
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(response) if !response.ok() => {
+                leptos::logging::error!("Failed to send message with status {}", response.status());
+            }
+            Ok(_) => (),
+            Err(err) => {
+                leptos::logging::error!("Failed to send message: {}", err);
+            }
+        }
+    }
+
+    fn set_ready_state(signal: RwSignal<ConnectionReadyState>, ready_state: ConnectionReadyState) {
+        if signal.get_untracked() != ready_state {
+            signal.set(ready_state);
+        }
+    }
+}
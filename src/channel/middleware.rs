@@ -0,0 +1,340 @@
+use std::{
+    any::Any,
+    pin::Pin,
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use futures_util::future;
+use uuid::Uuid;
+
+use crate::{
+    CanonicalKey, ChannelMsg, DeadLetterReason,
+    channel::server::{AsyncSendMapFn, DeadLetters, SendMapFn, SubscribeFilterFn},
+    type_name_of_key,
+};
+
+/// The future that is returned by the methods of [`SocketMiddleware`]. Resolves to `None` to drop
+/// the frame.
+pub type MiddlewareFuture<'a> = Pin<Box<dyn Future<Output = Option<ChannelMsg>> + Send + 'a>>;
+
+/// Intercepts the frames of every connection. Add it with
+/// [`ServerSocketInner::add_middleware`](crate::ServerSocketInner::add_middleware).
+///
+/// Middleware can inspect, change or drop frames, which is useful for logging, metrics or rate
+/// limiting. Inbound frames pass the middleware before they are handled and validated. Outbound
+/// frames pass it right before they are sent to the client. Both methods pass the frame through
+/// unchanged by default.
+///
+/// Subscribe filters and send mappers are middleware as well. They are part of the same chain and
+/// only look at inbound `Subscribe`, `SubscribeMany` and `Msg` frames.
+///
+/// Middleware is called in the order it was added for both directions. The socket is not locked
+/// while it runs.
+///
+/// ```
+/// # use leptos_axum_socket::{ChannelMsg, MiddlewareFuture, ServerSocket, SocketMiddleware};
+/// # use uuid::Uuid;
+/// #
+/// /// Drops everything the client sends except subscriptions.
+/// struct ReadOnly;
+///
+/// impl SocketMiddleware for ReadOnly {
+///     fn on_inbound(&self, client_id: Uuid, frame: ChannelMsg) -> MiddlewareFuture<'_> {
+///         Box::pin(async move {
+///             match frame {
+///                 ChannelMsg::Msg { .. } => {
+///                     tracing::info!("Dropped message of read-only client {client_id}");
+///                     None
+///                 }
+///                 frame => Some(frame),
+///             }
+///         })
+///     }
+/// }
+///
+/// async fn setup(socket: ServerSocket) {
+///     socket.lock().await.add_middleware(ReadOnly);
+/// }
+/// ```
+pub trait SocketMiddleware: Send + Sync + 'static {
+    /// Called with every frame that the client sent.
+    fn on_inbound(&self, client_id: Uuid, frame: ChannelMsg) -> MiddlewareFuture<'_> {
+        let _ = client_id;
+        Box::pin(future::ready(Some(frame)))
+    }
+
+    /// Called with every frame that is sent to the client.
+    fn on_outbound(&self, client_id: Uuid, frame: ChannelMsg) -> MiddlewareFuture<'_> {
+        let _ = client_id;
+        Box::pin(future::ready(Some(frame)))
+    }
+}
+
+/// The context of the connection that a frame belongs to. It's only passed to the subscribe filters
+/// and send mappers.
+pub(crate) type ConnectionContext = dyn Any + Send + Sync;
+
+/// What a [`Layer`] of the chain was added as.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LayerKind {
+    Middleware,
+    SubscribeFilter,
+    SendMapper,
+    AsyncSendMapper,
+}
+
+/// An entry of the middleware chain. [`SocketMiddleware`] as well as the adapters of the subscribe
+/// filters and send mappers implement it.
+pub(crate) trait Layer: Send + Sync + 'static {
+    fn kind(&self) -> LayerKind;
+
+    fn on_inbound<'a>(
+        &'a self,
+        client_id: Uuid,
+        context: &'a ConnectionContext,
+        frame: ChannelMsg,
+    ) -> MiddlewareFuture<'a>;
+
+    fn on_outbound(&self, client_id: Uuid, frame: ChannelMsg) -> MiddlewareFuture<'_> {
+        let _ = client_id;
+        Box::pin(future::ready(Some(frame)))
+    }
+}
+
+impl<M: SocketMiddleware> Layer for M {
+    fn kind(&self) -> LayerKind {
+        LayerKind::Middleware
+    }
+
+    fn on_inbound<'a>(
+        &'a self,
+        client_id: Uuid,
+        _context: &'a ConnectionContext,
+        frame: ChannelMsg,
+    ) -> MiddlewareFuture<'a> {
+        SocketMiddleware::on_inbound(self, client_id, frame)
+    }
+
+    fn on_outbound(&self, client_id: Uuid, frame: ChannelMsg) -> MiddlewareFuture<'_> {
+        SocketMiddleware::on_outbound(self, client_id, frame)
+    }
+}
+
+/// Adapts a subscribe filter. Drops subscriptions to the keys that it denies.
+pub(crate) struct SubscribeFilterLayer(pub(crate) SubscribeFilterFn);
+
+impl SubscribeFilterLayer {
+    async fn allows(&self, key: &CanonicalKey, context: &ConnectionContext) -> bool {
+        // Type subscriptions are checked against the registered types instead
+        type_name_of_key(key).is_some() || (self.0)(key.as_value().clone(), context).await
+    }
+}
+
+impl Layer for SubscribeFilterLayer {
+    fn kind(&self) -> LayerKind {
+        LayerKind::SubscribeFilter
+    }
+
+    fn on_inbound<'a>(
+        &'a self,
+        _client_id: Uuid,
+        context: &'a ConnectionContext,
+        frame: ChannelMsg,
+    ) -> MiddlewareFuture<'a> {
+        Box::pin(async move {
+            match frame {
+                ChannelMsg::Subscribe { ref key, .. } => {
+                    self.allows(key, context).await.then_some(frame)
+                }
+                ChannelMsg::SubscribeMany { keys } => {
+                    let mut allowed = Vec::with_capacity(keys.len());
+
+                    for key in keys {
+                        if self.allows(&key, context).await {
+                            allowed.push(key);
+                        }
+                    }
+
+                    Some(ChannelMsg::SubscribeMany { keys: allowed })
+                }
+                frame => Some(frame),
+            }
+        })
+    }
+}
+
+/// Adapts a send mapper. Maps the messages that clients send and drops those that it returns
+/// `None` for as dead letters.
+pub(crate) struct SendMapperLayer {
+    pub(crate) mapper: SendMapFn,
+    pub(crate) dead_letters: DeadLetters,
+}
+
+impl Layer for SendMapperLayer {
+    fn kind(&self) -> LayerKind {
+        LayerKind::SendMapper
+    }
+
+    fn on_inbound<'a>(
+        &'a self,
+        _client_id: Uuid,
+        context: &'a ConnectionContext,
+        frame: ChannelMsg,
+    ) -> MiddlewareFuture<'a> {
+        let ChannelMsg::Msg {
+            key,
+            msg,
+            id,
+            sender,
+            seq,
+        } = frame
+        else {
+            return Box::pin(future::ready(Some(frame)));
+        };
+
+        let mapped = match (self.mapper)(key.as_value().clone(), msg.clone(), context) {
+            Ok(Some(mapped)) => Some(mapped),
+            Ok(None) => {
+                self.dead_letters
+                    .report(DeadLetterReason::DroppedByMapper, &key, &msg);
+                None
+            }
+            // The mapper doesn't apply to the message
+            Err(_) => Some(msg),
+        };
+
+        Box::pin(future::ready(mapped.map(|msg| ChannelMsg::Msg {
+            key,
+            msg,
+            id,
+            sender,
+            seq,
+        })))
+    }
+}
+
+/// Like [`SendMapperLayer`] for an async send mapper.
+pub(crate) struct AsyncSendMapperLayer {
+    pub(crate) mapper: AsyncSendMapFn,
+    pub(crate) dead_letters: DeadLetters,
+}
+
+impl Layer for AsyncSendMapperLayer {
+    fn kind(&self) -> LayerKind {
+        LayerKind::AsyncSendMapper
+    }
+
+    fn on_inbound<'a>(
+        &'a self,
+        _client_id: Uuid,
+        context: &'a ConnectionContext,
+        frame: ChannelMsg,
+    ) -> MiddlewareFuture<'a> {
+        let ChannelMsg::Msg {
+            key,
+            msg,
+            id,
+            sender,
+            seq,
+        } = frame
+        else {
+            return Box::pin(future::ready(Some(frame)));
+        };
+
+        let mapping = (self.mapper)(key.as_value().clone(), msg.clone(), context);
+
+        Box::pin(async move {
+            let msg = match mapping {
+                Ok(mapping) => match mapping.await {
+                    Some(mapped) => mapped,
+                    None => {
+                        self.dead_letters
+                            .report(DeadLetterReason::DroppedByMapper, &key, &msg);
+                        return None;
+                    }
+                },
+                // The mapper doesn't apply to the message
+                Err(_) => msg,
+            };
+
+            Some(ChannelMsg::Msg {
+                key,
+                msg,
+                id,
+                sender,
+                seq,
+            })
+        })
+    }
+}
+
+/// The middleware chain. It's shared with the connections so that middleware that is added later
+/// applies to existing connections as well.
+#[derive(Clone, Default)]
+pub(crate) struct Middlewares(Arc<RwLock<Vec<Arc<dyn Layer>>>>);
+
+impl Middlewares {
+    pub(crate) fn push(&self, layer: impl Layer) {
+        self.0
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Arc::new(layer));
+    }
+
+    /// Removes the layers of the given kinds.
+    pub(crate) fn remove(&self, kinds: &[LayerKind]) {
+        self.0
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|layer| !kinds.contains(&layer.kind()));
+    }
+
+    /// Returns the number of layers of the given kind.
+    pub(crate) fn count(&self, kind: LayerKind) -> usize {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|layer| layer.kind() == kind)
+            .count()
+    }
+
+    /// Returns `true` if outbound frames might be changed. Only middleware looks at them.
+    #[cfg_attr(feature = "reliable", allow(dead_code))]
+    pub(crate) fn intercepts_outbound(&self) -> bool {
+        self.count(LayerKind::Middleware) > 0
+    }
+
+    pub(crate) async fn inbound(
+        &self,
+        client_id: Uuid,
+        context: &ConnectionContext,
+        frame: ChannelMsg,
+    ) -> Option<ChannelMsg> {
+        let mut frame = frame;
+
+        for layer in self.snapshot() {
+            frame = layer.on_inbound(client_id, context, frame).await?;
+        }
+
+        Some(frame)
+    }
+
+    pub(crate) async fn outbound(&self, client_id: Uuid, frame: ChannelMsg) -> Option<ChannelMsg> {
+        let mut frame = frame;
+
+        for layer in self.snapshot() {
+            frame = layer.on_outbound(client_id, frame).await?;
+        }
+
+        Some(frame)
+    }
+
+    /// Copies the chain so that the lock isn't held while the middleware runs.
+    fn snapshot(&self) -> Vec<Arc<dyn Layer>> {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
@@ -2,20 +2,229 @@ use std::fmt::Debug;
 
 use serde::{Deserialize, Serialize};
 
+mod backoff;
+mod cipher;
+pub(crate) mod codec;
 mod context;
 #[cfg(feature = "ssr")]
+mod context_map;
+#[cfg_attr(feature = "ssr", allow(dead_code))]
+mod dispatch;
+mod error;
+mod key;
+#[cfg(all(feature = "longpoll", not(feature = "ssr")))]
+mod longpoll;
+#[cfg(feature = "ssr")]
+mod middleware;
+#[cfg(feature = "patch")]
+mod patch;
+#[cfg(feature = "protobuf")]
+mod protobuf;
+mod refetch;
+#[cfg(feature = "ssr")]
 mod server;
+mod stream;
+mod topic;
+pub(crate) mod trace;
+mod typed_channel;
+#[cfg(all(feature = "worker", not(feature = "ssr")))]
+mod worker;
 
+pub use backoff::ReconnectBackoff;
+pub use cipher::*;
 pub use context::*;
+#[cfg(feature = "ssr")]
+pub use context_map::ContextMap;
+#[cfg(feature = "ssr")]
+pub(crate) use context_map::context_of;
+pub use error::*;
+pub use key::*;
+#[cfg(feature = "ssr")]
+pub(crate) use middleware::Middlewares;
+#[cfg(feature = "ssr")]
+pub use middleware::{MiddlewareFuture, SocketMiddleware};
+#[cfg(feature = "patch")]
+pub use patch::{PatchError, apply_patch};
+#[cfg(feature = "protobuf")]
+pub use protobuf::Protobuf;
+pub use refetch::Refetch;
 use serde_json::Value;
 #[cfg(feature = "ssr")]
-pub use server::{ServerSocket, ServerSocketInner, send, send_to_self};
+pub use server::{
+    ClientMeta, DEFAULT_CHANNEL_CAPACITY, DEFAULT_MAX_SUBSCRIPTIONS_PER_CLIENT, DeadLetterReason,
+    DeliveryTarget, DisconnectInfo, KeyPausePolicy, KeySnapshot, MAX_PINNED_MSGS, PausedKeyState,
+    ServerSocket, ServerSocketBuilder, ServerSocketInner, SocketSnapshot, SocketState, send,
+    send_in, send_to_self,
+};
+#[cfg(all(feature = "ssr", feature = "longpoll"))]
+pub(crate) use server::{ConnectionSlot, read_client_id_from_cookie_header};
+#[cfg(feature = "ssr")]
+pub(crate) use server::{SharedFrame, SubscriberFilter, TrafficCounter, WireFrameFn};
+pub use stream::MessageStream;
+pub use topic::Topic;
+pub use trace::Direction;
+pub use typed_channel::TypedChannel;
+use uuid::Uuid;
+#[cfg(all(feature = "worker", not(feature = "ssr")))]
+pub use worker::serve_in_worker;
 
 pub const WEBSOCKET_CHANNEL_URL: &str = "/socket-msg";
+#[cfg(feature = "longpoll")]
+pub const LONGPOLL_SEND_URL: &str = "/socket-msg/send";
+#[cfg(feature = "longpoll")]
+pub const LONGPOLL_POLL_URL: &str = "/socket-msg/poll";
+#[cfg(feature = "metrics")]
+pub const METRICS_URL: &str = "/socket-metrics";
+
+/// The header that can carry the client ID instead of the `socket_client_id` cookie.
+///
+/// See [`SocketContext::client_id`].
+pub const CLIENT_ID_HEADER: &str = "x-socket-client-id";
 
+/// A frame of the protocol that is spoken between the client and the server.
+///
+/// You usually don't have to deal with this directly. It's useful for debugging and for building
+/// custom dispatch logic, see [`SocketContext::last_frame`].
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub(crate) enum ChannelMsg {
-    Msg { key: Value, msg: Value },
-    Subscribe { key: Value },
-    Unsubscribe { key: Value },
+#[non_exhaustive]
+pub enum ChannelMsg {
+    /// A message that was sent with `key`.
+    Msg {
+        key: CanonicalKey,
+        msg: Value,
+        /// Set by the client for optimistic sends to recognize the echo of its own message.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<Uuid>,
+        /// Set by the server to the client that sent the message if
+        /// `ServerSocketInner::stamp_sender` is enabled. Whatever the client puts here is ignored.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sender: Option<SenderInfo>,
+        /// Set by the server to the number of the message among all messages that were sent with
+        /// `key`. It increases by one with every message so clients can detect that they missed some.
+        /// Messages that are sent to a single client don't have it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+    },
+    /// A message that was sent with `path` and is delivered to the subscribers of `key`. Used for
+    /// descendant paths of hierarchical keys and for type subscriptions.
+    PathMsg {
+        key: CanonicalKey,
+        path: Value,
+        msg: Value,
+    },
+    /// A JSON patch (RFC 6902) that updates the retained message of `key`. `seq` is set by the
+    /// server like the one of `Msg`. See `ServerSocketInner::send_patch`.
+    #[cfg(feature = "patch")]
+    Patch {
+        key: CanonicalKey,
+        patch: Value,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+    },
+    /// Sent by the client to receive the messages of `key`. If `filter` isn't `null`, only the
+    /// messages that pass it are forwarded, see `ServerSocketInner::add_subscription_filter_evaluator`.
+    Subscribe {
+        key: CanonicalKey,
+        #[serde(default, skip_serializing_if = "Value::is_null")]
+        filter: Value,
+    },
+    /// Sent by the client to receive the messages of all `keys` at once. The server answers with a
+    /// single `SubscribeManyResult` frame.
+    SubscribeMany { keys: Vec<CanonicalKey> },
+    /// Sent by the client to stop receiving the messages of `key`.
+    Unsubscribe { key: CanonicalKey },
+    /// Sent by the server when `key` was drained and the client isn't subscribed to it anymore.
+    KeyClosed { key: CanonicalKey },
+    /// Sent by the server when the subscriptions to `old` were moved to `new`.
+    KeyRemapped {
+        old: CanonicalKey,
+        new: CanonicalKey,
+    },
+    /// Sent by the server when the subscription to `key` was accepted. It carries the initial state
+    /// of the key so that the client receives it at once before any live message: `pinned` are the
+    /// messages that are pinned to the key, oldest first, `history` is the history of the key,
+    /// oldest first, and `retained` is the latest message of a stateful key or the retained message
+    /// of the key, if there is one. Messages that are pinned or retained aren't repeated in the
+    /// history. The client receives them in this order.
+    SubscribeResult {
+        key: CanonicalKey,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pinned: Vec<Value>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        history: Vec<Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        retained: Option<Value>,
+    },
+    /// The server ignored the subscription to `key`. `reason` is `"limit"` if the client has too many
+    /// subscriptions and `"filter"` if no evaluator accepted the filter of the subscription.
+    SubscribeDenied { key: CanonicalKey, reason: String },
+    /// The answer to `SubscribeMany` with a `SubscribeResult` or `SubscribeDenied` frame for every
+    /// key. Keys for which the subscription was ignored are missing.
+    SubscribeManyResult { results: Vec<ChannelMsg> },
+    /// Sent by the server if a message of the client was dropped because it didn't pass validation.
+    Invalid { key: CanonicalKey, reason: String },
+    /// Sent by the server if a frame of the client was rejected because it is larger than `max_size` bytes.
+    TooLarge { size: usize, max_size: usize },
+    /// Sent by the server when the connection is established with the ID that identifies the client.
+    Welcome { client_id: Uuid },
+    /// A message frame with a sequence number that the client has to acknowledge with `Ack`.
+    #[cfg(feature = "reliable")]
+    Reliable { seq: u64, msg: Box<ChannelMsg> },
+    /// Sent by the client to acknowledge the `Reliable` frame with the sequence number `seq`.
+    #[cfg(feature = "reliable")]
+    Ack { seq: u64 },
+}
+
+/// The server-verified sender of a message. Only available if the server stamps messages with
+/// their sender, see `ServerSocketInner::stamp_sender`.
+///
+/// Unlike an author field in your message type, clients can't forge this.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SenderInfo {
+    /// The ID of the client that sent the message.
+    pub client_id: Uuid,
+    /// Metadata that the server attached to the sender, for example the user name. `Null` if there
+    /// is none.
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub meta: Value,
+}
+
+/// A WebSocket `Close` frame. The server sends it when it closes the connection, see
+/// `ServerSocketInner::close_client`, and the client exposes the last one it received with
+/// [`SocketContext::last_close`].
+///
+/// Codes from 4000 to 4999 are reserved for applications, for example 4001 when a session was
+/// revoked. The client doesn't reconnect automatically after the server closed the connection with
+/// such a code, so the app can decide what to do, like redirecting to the login page.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CloseFrame {
+    /// The [close code](https://developer.mozilla.org/en-US/docs/Web/API/CloseEvent/code).
+    pub code: u16,
+    pub reason: String,
+}
+
+impl CloseFrame {
+    pub fn new(code: u16, reason: impl Into<String>) -> Self {
+        Self {
+            code,
+            reason: reason.into(),
+        }
+    }
+
+    /// Returns `true` if the code is in the range 4000 to 4999 that is reserved for applications.
+    pub fn is_application_code(&self) -> bool {
+        (4000..=4999).contains(&self.code)
+    }
+}
+
+/// The key that is used on the wire to subscribe to every message of the given type.
+pub(crate) fn type_key(type_name: &str) -> CanonicalKey {
+    CanonicalKey::new(serde_json::json!({ "$type": type_name }))
+}
+
+/// Returns the type name if the key is a key created by [`type_key`].
+#[cfg(feature = "ssr")]
+pub(crate) fn type_name_of_key(key: &Value) -> Option<&str> {
+    let object = key.as_object().filter(|object| object.len() == 1)?;
+    object.get("$type")?.as_str()
 }
@@ -0,0 +1,179 @@
+//! JSON patches (RFC 6902) that update the retained message of a key. Requires the `patch` feature.
+//!
+//! The server applies every patch to the retained message of the key before it forwards the patch
+//! to the subscribers. The clients apply it to the last message they received for the key, so
+//! only the change goes over the wire.
+
+use std::fmt::{Display, Formatter};
+
+use serde_json::Value;
+
+/// Errors that can occur when applying a patch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+    /// The key has no retained message that the patch could be applied to.
+    NoBase,
+    /// The patch isn't a valid JSON patch document.
+    Malformed(String),
+    /// The operation at `index` couldn't be applied. The document is left unchanged.
+    Failed { index: usize, reason: String },
+    /// The patched message was rejected by a validator.
+    Rejected(String),
+}
+
+impl Display for PatchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoBase => write!(f, "No retained message to apply the patch to"),
+            Self::Malformed(reason) => write!(f, "Malformed patch: {reason}"),
+            Self::Failed { index, reason } => {
+                write!(f, "Failed to apply patch operation {index}: {reason}")
+            }
+            Self::Rejected(reason) => write!(f, "Patched message is invalid: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// Applies the JSON patch `patch` to `doc`. Either all operations are applied or none.
+///
+/// ```
+/// # use leptos_axum_socket::apply_patch;
+/// # use serde_json::json;
+/// #
+/// let mut doc = json!({ "title": "Draft", "tags": ["a"] });
+///
+/// apply_patch(
+///     &mut doc,
+///     &json!([
+///         { "op": "replace", "path": "/title", "value": "Final" },
+///         { "op": "add", "path": "/tags/-", "value": "b" },
+///     ]),
+/// )
+/// .unwrap();
+///
+/// assert_eq!(doc, json!({ "title": "Final", "tags": ["a", "b"] }));
+/// ```
+pub fn apply_patch(doc: &mut Value, patch: &Value) -> Result<(), PatchError> {
+    let ops = patch
+        .as_array()
+        .ok_or_else(|| PatchError::Malformed("expected an array of operations".to_string()))?;
+
+    let mut patched = doc.clone();
+
+    for (index, op) in ops.iter().enumerate() {
+        apply_op(&mut patched, op).map_err(|reason| PatchError::Failed { index, reason })?;
+    }
+
+    *doc = patched;
+    Ok(())
+}
+
+fn apply_op(doc: &mut Value, op: &Value) -> Result<(), String> {
+    let field = |name: &str| op.get(name).ok_or_else(|| format!("missing \"{name}\""));
+    let pointer = |name: &str| {
+        field(name)?
+            .as_str()
+            .ok_or_else(|| format!("\"{name}\" is not a string"))
+    };
+
+    let path = pointer("path")?;
+
+    match field("op")?.as_str() {
+        Some("add") => add(doc, path, field("value")?.clone()),
+        Some("remove") => remove(doc, path).map(drop),
+        Some("replace") => {
+            let target = doc
+                .pointer_mut(path)
+                .ok_or_else(|| format!("\"{path}\" doesn't exist"))?;
+            *target = field("value")?.clone();
+            Ok(())
+        }
+        Some("move") => {
+            let from = pointer("from")?;
+            if path.starts_with(from) && path[from.len()..].starts_with('/') {
+                return Err(format!("can't move \"{from}\" into itself"));
+            }
+
+            let value = remove(doc, from)?;
+            add(doc, path, value)
+        }
+        Some("copy") => {
+            let from = pointer("from")?;
+            let value = doc
+                .pointer(from)
+                .ok_or_else(|| format!("\"{from}\" doesn't exist"))?
+                .clone();
+
+            add(doc, path, value)
+        }
+        Some("test") => {
+            if doc.pointer(path) == Some(field("value")?) {
+                Ok(())
+            } else {
+                Err(format!("\"{path}\" doesn't have the expected value"))
+            }
+        }
+        _ => Err("unknown \"op\"".to_string()),
+    }
+}
+
+/// Splits the pointer into the pointer of the parent and the unescaped last reference token.
+fn split_pointer(path: &str) -> Result<(&str, String), String> {
+    let (parent, token) = path
+        .rsplit_once('/')
+        .ok_or_else(|| format!("\"{path}\" is not a JSON pointer"))?;
+
+    Ok((parent, token.replace("~1", "/").replace("~0", "~")))
+}
+
+fn add(doc: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    if path.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+
+    let (parent, token) = split_pointer(path)?;
+
+    match doc.pointer_mut(parent) {
+        Some(Value::Object(map)) => {
+            map.insert(token, value);
+            Ok(())
+        }
+        Some(Value::Array(values)) => {
+            let index = match token.as_str() {
+                "-" => values.len(),
+                token => array_index(token, values.len() + 1)?,
+            };
+            values.insert(index, value);
+            Ok(())
+        }
+        Some(_) => Err(format!("\"{parent}\" is neither an object nor an array")),
+        None => Err(format!("\"{parent}\" doesn't exist")),
+    }
+}
+
+fn remove(doc: &mut Value, path: &str) -> Result<Value, String> {
+    let (parent, token) = split_pointer(path)?;
+
+    match doc.pointer_mut(parent) {
+        Some(Value::Object(map)) => map
+            .remove(&token)
+            .ok_or_else(|| format!("\"{path}\" doesn't exist")),
+        Some(Value::Array(values)) => {
+            let index = array_index(&token, values.len())?;
+            Ok(values.remove(index))
+        }
+        _ => Err(format!("\"{path}\" doesn't exist")),
+    }
+}
+
+/// Parses an array index that has to be smaller than `len`.
+fn array_index(token: &str, len: usize) -> Result<usize, String> {
+    token
+        .parse::<usize>()
+        .ok()
+        .filter(|index| *index < len && (token == "0" || !token.starts_with('0')))
+        .ok_or_else(|| format!("\"{token}\" is not a valid array index"))
+}
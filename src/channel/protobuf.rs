@@ -0,0 +1,103 @@
+use std::ops::{Deref, DerefMut};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
+
+/// Sends a [`prost`] message as payload so you don't have to duplicate your protobuf schemas with
+/// serde. Requires the `protobuf` feature.
+///
+/// The message is encoded with protobuf and embedded into the frame as a base64 string. Keys stay
+/// JSON because they're needed to route the messages. Since the payload is opaque to the server,
+/// send mappers and validators of a protobuf message type have to decode it themselves.
+///
+/// ```
+/// # use leptos_axum_socket::{Protobuf, ServerSocket, SocketMsg};
+/// # use serde::{Deserialize, Serialize};
+/// # use axum::extract::FromRef;
+/// #
+/// # #[derive(FromRef, Clone)]
+/// # pub struct AppState {
+/// #     pub socket: ServerSocket,
+/// # }
+/// #
+/// // Usually generated by `prost-build`
+/// #[derive(Clone, PartialEq, prost::Message)]
+/// pub struct Chat {
+///     #[prost(string, tag = "1")]
+///     pub text: String,
+/// }
+///
+/// #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+/// pub struct RoomKey(u64);
+///
+/// #[derive(Clone, Serialize, Deserialize, Debug)]
+/// #[serde(transparent)]
+/// pub struct ChatMsg(pub Protobuf<Chat>);
+///
+/// impl SocketMsg for ChatMsg {
+///     type Key = RoomKey;
+/// }
+///
+/// let msg = ChatMsg(Protobuf(Chat { text: "Hi".to_string() }));
+/// let json = serde_json::to_string(&msg).unwrap();
+///
+/// assert_eq!(json, r#""CgJIaQ==""#);
+/// assert_eq!(serde_json::from_str::<ChatMsg>(&json).unwrap().0.text, "Hi");
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Protobuf<M>(pub M);
+
+impl<M> Protobuf<M> {
+    pub fn into_inner(self) -> M {
+        self.0
+    }
+}
+
+impl<M> From<M> for Protobuf<M> {
+    fn from(msg: M) -> Self {
+        Self(msg)
+    }
+}
+
+impl<M> Deref for Protobuf<M> {
+    type Target = M;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<M> DerefMut for Protobuf<M> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<M> Serialize for Protobuf<M>
+where
+    M: prost::Message,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&STANDARD.encode(self.0.encode_to_vec()))
+    }
+}
+
+impl<'de, M> Deserialize<'de> for Protobuf<M>
+where
+    M: prost::Message + Default,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let encoded = String::deserialize(deserializer)?;
+        let bytes = STANDARD.decode(encoded).map_err(D::Error::custom)?;
+
+        M::decode(bytes.as_slice())
+            .map(Self)
+            .map_err(D::Error::custom)
+    }
+}
@@ -0,0 +1,55 @@
+use std::fmt::Debug;
+
+use leptos::server::{
+    ArcLocalResource, ArcResource, FromEncodedStr, IntoEncodedString, LocalResource, Resource,
+    codee::{Decoder, Encoder},
+};
+
+/// A resource that can be refetched by [`SocketContext::invalidate_on`](crate::SocketContext::invalidate_on).
+///
+/// Implemented for all Leptos resource types.
+pub trait Refetch {
+    /// Re-runs the async function of the resource.
+    fn refetch(&self);
+}
+
+impl<T, Ser> Refetch for Resource<T, Ser>
+where
+    Ser: Encoder<T> + Decoder<T>,
+    <Ser as Encoder<T>>::Error: Debug,
+    <Ser as Decoder<T>>::Error: Debug,
+    <<Ser as Decoder<T>>::Encoded as FromEncodedStr>::DecodingError: Debug,
+    <Ser as Encoder<T>>::Encoded: IntoEncodedString,
+    <Ser as Decoder<T>>::Encoded: FromEncodedStr,
+    T: Send + Sync,
+{
+    fn refetch(&self) {
+        Resource::refetch(self);
+    }
+}
+
+impl<T, Ser> Refetch for ArcResource<T, Ser>
+where
+    Ser: Encoder<T> + Decoder<T>,
+    <Ser as Encoder<T>>::Error: Debug,
+    <Ser as Decoder<T>>::Error: Debug,
+    <<Ser as Decoder<T>>::Encoded as FromEncodedStr>::DecodingError: Debug,
+    <Ser as Encoder<T>>::Encoded: IntoEncodedString,
+    <Ser as Decoder<T>>::Encoded: FromEncodedStr,
+{
+    fn refetch(&self) {
+        ArcResource::refetch(self);
+    }
+}
+
+impl<T> Refetch for LocalResource<T> {
+    fn refetch(&self) {
+        LocalResource::refetch(self);
+    }
+}
+
+impl<T> Refetch for ArcLocalResource<T> {
+    fn refetch(&self) {
+        ArcLocalResource::refetch(self);
+    }
+}
@@ -1,33 +1,90 @@
 use axum::extract::FromRef;
-use axum::http::HeaderMap;
+use axum::extract::ws::Utf8Bytes;
 use axum::http::header::COOKIE;
+use axum::http::{HeaderMap, HeaderName};
+use futures_util::{Stream, StreamExt, stream};
 use leptos::prelude::*;
 use leptos_use::utils::header;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::any::Any;
-use std::pin::Pin;
-use std::{collections::HashMap, sync::Arc};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::pin::{Pin, pin};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
 use std::{fmt::Debug, hash::Hash};
-use tokio::sync::broadcast::{self, Receiver};
-use tokio::sync::mpsc;
-use tokio::sync::{Mutex, MutexGuard};
+use tokio::runtime::Handle;
+use tokio::sync::broadcast::{self, Receiver, error::RecvError};
+use tokio::sync::{Mutex, MutexGuard, Notify, TryLockError};
+use tokio::sync::{mpsc, oneshot};
 use tokio::task::JoinHandle;
-use tracing::{debug, error, instrument};
+use tracing::{debug, error, instrument, warn};
 use uuid::Uuid;
 
-use crate::{ChannelMsg, SocketMsg};
+#[cfg(feature = "patch")]
+use crate::PatchError;
+use crate::channel::middleware::{
+    AsyncSendMapperLayer, LayerKind, SendMapperLayer, SubscribeFilterLayer,
+};
+use crate::{
+    CLIENT_ID_HEADER, CanonicalKey, ChannelMsg, CloseFrame, Direction, Middlewares,
+    ReconnectBackoff, SenderInfo, ServerSocketMsg, SocketMiddleware, SocketMsg, codec, context_of,
+    type_key, type_name_of_key,
+};
 
 /// This has to be added to the axum state and is used to send and subscribe to channels.
-#[derive(Clone, Debug, Default)]
-pub struct ServerSocket(Arc<Mutex<ServerSocketInner>>);
+#[derive(Clone, Debug)]
+pub struct ServerSocket(Arc<Mutex<ServerSocketInner>>, Spawner, Arc<ConnectionSlots>);
+
+impl Default for ServerSocket {
+    fn default() -> Self {
+        ServerSocketBuilder::default().build()
+    }
+}
 
 impl ServerSocket {
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Like [`ServerSocket::new`] but all tasks of the socket and its connections are spawned on
+    /// the runtime of `handle` instead of the runtime that is current when they are spawned.
+    ///
+    /// This is useful if the socket should run on a dedicated runtime or in a test harness with a
+    /// runtime of its own.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::ServerSocket;
+    /// #
+    /// let runtime = tokio::runtime::Builder::new_multi_thread()
+    ///     .enable_all()
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let socket = ServerSocket::with_runtime_handle(runtime.handle().clone());
+    /// ```
+    pub fn with_runtime_handle(handle: Handle) -> Self {
+        Self::builder().runtime_handle(handle).build()
+    }
+
+    /// Returns a builder to configure the socket in one place before it's used.
+    /// See [`ServerSocketBuilder`].
+    pub fn builder() -> ServerSocketBuilder {
+        ServerSocketBuilder::default()
+    }
+
+    pub(crate) fn spawner(&self) -> &Spawner {
+        &self.1
+    }
+
+    /// Takes one of the connections that are allowed by
+    /// [`ServerSocketInner::set_max_connections`]. Returns `None` if all are taken.
+    pub(crate) fn acquire_connection_slot(&self) -> Option<ConnectionSlot> {
+        ConnectionSlots::acquire(&self.2)
+    }
+
     /// Locks the server socket for exclusive access. With this you can then send messages to the socket.
     ///
     /// See [`ServerSocketInner::send`].
@@ -35,55 +92,447 @@ impl ServerSocket {
     pub async fn lock(&self) -> MutexGuard<'_, ServerSocketInner> {
         self.0.lock().await
     }
-}
 
-type SubscribeFilterFn =
-    Arc<dyn Fn(Value, &dyn Any) -> Pin<Box<dyn Future<Output = bool> + Send>> + Send + Sync>;
-type SendMapFn =
-    Arc<dyn Fn(Value, Value, &dyn Any) -> serde_json::Result<Option<Value>> + Send + Sync>;
+    /// Like [`ServerSocketInner::send`] but without awaiting, for synchronous code like a `tracing`
+    /// layer or the impl of a trait that isn't async. The message is only sent if the socket isn't
+    /// locked at the moment. Otherwise an error is returned instead of blocking.
+    ///
+    /// Because it never blocks, it can also be called on a thread of the async runtime without
+    /// risking a deadlock. If the message must not be dropped, send it from a spawned task with
+    /// [`ServerSocket::lock`] instead.
+    ///
+    /// Returns the number of receivers the message was delivered to.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::{ServerSocket, SocketMsg};
+    /// # use serde::{Serialize, Deserialize};
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// # struct LogKey;
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, Debug)]
+    /// # struct LogLine(String);
+    /// #
+    /// # impl SocketMsg for LogLine {
+    /// #     type Key = LogKey;
+    /// # }
+    /// #
+    /// fn on_log_line(socket: &ServerSocket, line: &str) {
+    ///     if socket
+    ///         .try_send_blocking(&LogKey, &LogLine(line.to_string()))
+    ///         .is_err()
+    ///     {
+    ///         // The socket is busy, drop the line
+    ///     }
+    /// }
+    /// ```
+    pub fn try_send_blocking<Msg>(&self, key: &Msg::Key, msg: &Msg) -> Result<usize, TryLockError>
+    where
+        Msg: SocketMsg + Serialize + Clone + Send + Sync + Debug + 'static,
+        for<'de> Msg: Deserialize<'de>,
+        Msg::Key: Hash + Eq + Serialize + Clone + Send + Sync + Debug + 'static,
+        for<'de> Msg::Key: Deserialize<'de>,
+    {
+        Ok(self.0.try_lock()?.send(key, msg))
+    }
 
-/// This is used on the server to manage socket connections.
-#[derive(Default)]
-pub struct ServerSocketInner {
-    sender_map: HashMap<Value, broadcast::Sender<ChannelMsg>>,
-    client_to_sender: HashMap<Uuid, mpsc::Sender<ChannelMsg>>,
-    subscribe_filters: Vec<SubscribeFilterFn>,
-    send_mappers: Vec<SendMapFn>,
-    handles: HashMap<(Uuid, Value), JoinHandle<()>>,
-}
+    /// Waits until at least one client is subscribed to the given key or the timeout elapses.
+    /// Returns `true` if there is a subscriber.
+    ///
+    /// This is useful to avoid sending messages that nobody receives, for example in tests that send
+    /// right after a client subscribed.
+    ///
+    /// ```
+    /// # use std::time::{Duration, SystemTime};
+    /// # use leptos_axum_socket::{ServerSocket, SocketMsg};
+    /// # use serde::{Serialize, Deserialize};
+    /// # use axum::extract::{State, FromRef};
+    /// #
+    /// # #[derive(FromRef, Clone)]
+    /// # pub struct AppState {
+    /// #     pub socket: ServerSocket,
+    /// # }
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// # struct TheKey;
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, Debug)]
+    /// # struct TheMessage;
+    /// #
+    /// # impl SocketMsg for TheMessage {
+    /// #     type Key = TheKey;
+    /// # }
+    /// #
+    /// async fn axum_handler(State(socket): State<ServerSocket>) {
+    ///     if socket.wait_for_subscriber(&TheKey, Duration::from_secs(5)).await {
+    ///         socket.lock().await.send(&TheKey, &TheMessage);
+    ///     }
+    /// }
+    /// ```
+    pub async fn wait_for_subscriber<K>(&self, key: &K, timeout: Duration) -> bool
+    where
+        K: Serialize,
+    {
+        let key = codec::to_key(key).unwrap();
+        let notify = Arc::clone(&self.lock().await.subscribe_notify);
 
-impl std::fmt::Debug for ServerSocketInner {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("ServerSocketChannels")
-            .field("sender_map", &self.sender_map)
-            .field("subscribe_filters", &self.subscribe_filters.len())
-            .field("send_mappers", &self.send_mappers.len())
-            .finish()
+        tokio::time::timeout(timeout, async {
+            loop {
+                let mut notified = pin!(notify.notified());
+                // Register before checking so that no subscription can be missed in between
+                notified.as_mut().enable();
+
+                if self.lock().await.has_subscribers(&key) {
+                    return;
+                }
+
+                notified.await;
+            }
+        })
+        .await
+        .is_ok()
     }
-}
 
-impl ServerSocketInner {
-    #[instrument]
-    fn sender(&mut self, key: Value) -> broadcast::Sender<ChannelMsg> {
-        let sender = self.sender_map.entry(key).or_insert_with(|| {
-            debug!("Creating new sender for key");
+    /// Calls `f` for every connected client. If it returns `Some((key, msg))` the message is sent to
+    /// that client only, as if it was sent with the key. This is useful for admin tooling, for example
+    /// to send a moderation notice to some of the clients.
+    ///
+    /// ```
+    /// # use std::time::{Duration, SystemTime};
+    /// # use leptos_axum_socket::ServerSocket;
+    /// # use serde_json::json;
+    /// #
+    /// async fn notify_new_clients(socket: ServerSocket) {
+    ///     let one_minute_ago = SystemTime::now() - Duration::from_secs(60);
+    ///
+    ///     socket
+    ///         .for_each_client(|_client_id, meta| {
+    ///             (meta.connected_at > one_minute_ago)
+    ///                 .then(|| (json!("notices"), json!("Welcome!")))
+    ///         })
+    ///         .await;
+    /// }
+    /// ```
+    pub async fn for_each_client<F>(&self, f: F)
+    where
+        F: Fn(&Uuid, &ClientMeta) -> Option<(Value, Value)>,
+    {
+        self.send_to_clients(f).await;
+    }
 
-            broadcast::Sender::new(16)
+    /// Sends the message directly to every connected client for which `predicate` returns `true`,
+    /// regardless of whether the client is subscribed to the key on the server. The client handles
+    /// the message like any other message of the key, so it needs a local subscription to the key.
+    ///
+    /// This is a targeted multicast based on the connection rather than on subscriptions.
+    /// Returns the number of clients the message was sent to.
+    ///
+    /// ```
+    /// # use std::time::{Duration, SystemTime};
+    /// # use leptos_axum_socket::{ServerSocket, SocketMsg};
+    /// # use serde::{Serialize, Deserialize};
+    /// # use axum::extract::FromRef;
+    /// #
+    /// # #[derive(FromRef, Clone)]
+    /// # pub struct AppState {
+    /// #     pub socket: ServerSocket,
+    /// # }
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// # struct NoticeKey;
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, Debug)]
+    /// # struct Notice(String);
+    /// #
+    /// # impl SocketMsg for Notice {
+    /// #     type Key = NoticeKey;
+    /// # }
+    /// #
+    /// async fn ask_long_running_clients_to_reload(socket: ServerSocket) {
+    ///     let one_day_ago = SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+    ///
+    ///     socket
+    ///         .send_where(
+    ///             &NoticeKey,
+    ///             &Notice("Please reload the page".to_string()),
+    ///             |meta| meta.connected_at < one_day_ago,
+    ///         )
+    ///         .await;
+    /// }
+    /// ```
+    pub async fn send_where<Msg>(
+        &self,
+        key: &Msg::Key,
+        msg: &Msg,
+        predicate: impl Fn(&ClientMeta) -> bool,
+    ) -> usize
+    where
+        Msg: SocketMsg + Serialize,
+        Msg::Key: Serialize,
+    {
+        let key = codec::to_key(key).unwrap().into_value();
+        let msg = codec::to_value(msg).unwrap();
+
+        self.send_to_clients(|_, meta| predicate(meta).then(|| (key.clone(), msg.clone())))
+            .await
+    }
+
+    /// Sends the message to a single client like [`DeliveryTarget::Client`] but doesn't wait
+    /// indefinitely if the client's buffer is full. Instead it tries again up to `retries` times,
+    /// waiting for the delays of `backoff` in between. The socket isn't locked while waiting.
+    ///
+    /// Returns `true` if the message was sent. Otherwise it's passed to the dead letter handlers
+    /// with [`DeadLetterReason::ClientBufferFull`] or [`DeadLetterReason::ClientNotFound`].
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use leptos_axum_socket::{ReconnectBackoff, ServerSocket, SocketMsg};
+    /// # use serde::{Serialize, Deserialize};
+    /// # use axum::extract::FromRef;
+    /// # use uuid::Uuid;
+    /// #
+    /// # #[derive(FromRef, Clone)]
+    /// # pub struct AppState {
+    /// #     pub socket: ServerSocket,
+    /// # }
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// # struct ProgressKey;
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, Debug)]
+    /// # struct Progress(u8);
+    /// #
+    /// # impl SocketMsg for Progress {
+    /// #     type Key = ProgressKey;
+    /// # }
+    /// #
+    /// async fn report_progress(socket: ServerSocket, client_id: Uuid, percent: u8) {
+    ///     let backoff = ReconnectBackoff::new(Duration::from_millis(50), Duration::from_secs(1));
+    ///
+    ///     socket
+    ///         .send_to_client_retry(client_id, &ProgressKey, &Progress(percent), 3, backoff)
+    ///         .await;
+    /// }
+    /// ```
+    pub async fn send_to_client_retry<Msg>(
+        &self,
+        client_id: Uuid,
+        key: &Msg::Key,
+        msg: &Msg,
+        retries: u32,
+        backoff: ReconnectBackoff,
+    ) -> bool
+    where
+        Msg: SocketMsg + Serialize,
+        Msg::Key: Serialize,
+    {
+        let frame = SharedFrame::new(ChannelMsg::Msg {
+            key: codec::to_key(key).unwrap(),
+            msg: codec::to_value(msg).unwrap(),
+            id: None,
+            sender: None,
+            seq: None,
         });
-        sender.clone()
+
+        let mut reason = DeadLetterReason::ClientBufferFull;
+
+        for attempt in 0..=retries {
+            if attempt > 0 {
+                tokio::time::sleep(backoff.delay(attempt - 1)).await;
+            }
+
+            let Some(sender) = self.lock().await.client_to_sender.get(&client_id).cloned() else {
+                reason = DeadLetterReason::ClientNotFound;
+                break;
+            };
+
+            match sender.try_send(Arc::clone(&frame)) {
+                Ok(()) => {
+                    self.lock().await.messages_sent += 1;
+                    return true;
+                }
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    debug!("Buffer of client {client_id} is full (attempt {attempt})");
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => {
+                    reason = DeadLetterReason::ClientNotFound;
+                    break;
+                }
+            }
+        }
+
+        if let ChannelMsg::Msg { key, msg, .. } = frame.msg() {
+            self.lock().await.dead_letter(reason, key, msg);
+        }
+
+        false
     }
 
-    /// Broadcast a message from the server to the subscribers of the given key.
+    /// Returns the logical state of the socket, so that it can be restored on another instance with
+    /// [`ServerSocket::import_state`], for example a hot standby that takes over after a failover.
     ///
-    /// This is used to send messages from an axum handler.
-    /// If you want to send from a server function, use the module level [`send`] function.
+    /// ```
+    /// # use leptos_axum_socket::ServerSocket;
+    /// #
+    /// async fn failover(primary: ServerSocket, standby: ServerSocket) {
+    ///     let state = serde_json::to_string(&primary.export_state().await).unwrap();
     ///
-    /// ## Example
+    ///     // Usually sent over the network or written to shared storage in between
+    ///
+    ///     standby.import_state(serde_json::from_str(&state).unwrap()).await;
+    /// }
+    /// ```
+    pub async fn export_state(&self) -> SocketState {
+        let inner = self.lock().await;
+
+        let entries = |values: &HashMap<CanonicalKey, Value>| {
+            values
+                .iter()
+                .map(|(key, msg)| (key.as_value().clone(), msg.clone()))
+                .collect()
+        };
+
+        SocketState {
+            latest: entries(&inner.latest_values),
+            retained: entries(&inner.retained_values),
+            pinned: inner
+                .pinned_msgs
+                .iter()
+                .map(|(key, pinned)| (key.as_value().clone(), pinned.iter().cloned().collect()))
+                .collect(),
+            seqs: inner
+                .key_seqs
+                .iter()
+                .map(|(key, seq)| (key.as_value().clone(), *seq))
+                .collect(),
+            paused_keys: inner
+                .paused_keys
+                .iter()
+                .map(|(key, paused)| PausedKeyState {
+                    key: key.as_value().clone(),
+                    policy: paused.policy,
+                    buffered: paused.buffered.iter().cloned().collect(),
+                })
+                .collect(),
+            histories: inner
+                .histories
+                .iter()
+                .map(|(key, history)| (key.as_value().clone(), history.iter().cloned().collect()))
+                .collect(),
+            messages_sent: inner.messages_sent,
+        }
+    }
+
+    /// Returns the JSON schemas of the message types that were registered with
+    /// [`ServerSocketInner::register_schema`]. It's an object with the name of every message type
+    /// that holds the schemas of its `key` and its `msg`. Requires the `json-schema` feature.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::ServerSocket;
+    /// #
+    /// async fn write_schemas(socket: ServerSocket) {
+    ///     let schemas = socket.export_schemas().await;
+    ///     std::fs::write("protocol.json", schemas.to_string()).unwrap();
+    /// }
+    /// ```
+    #[cfg(feature = "json-schema")]
+    pub async fn export_schemas(&self) -> Value {
+        Value::Object(self.lock().await.schemas.clone())
+    }
+
+    /// Restores the state that was returned by [`ServerSocket::export_state`] of another instance.
+    /// State of the same keys is replaced. The messages are sent to the clients when they subscribe
+    /// again after reconnecting to this instance.
+    ///
+    /// Configuration that is done in code, like stateful or hierarchical keys, isn't part of the
+    /// state and has to be done on this instance as well.
+    pub async fn import_state(&self, state: SocketState) {
+        let mut inner = self.lock().await;
+
+        for (key, msg) in state.latest {
+            inner.latest_values.insert(CanonicalKey::new(key), msg);
+        }
+        for (key, msg) in state.retained {
+            inner.retained_values.insert(CanonicalKey::new(key), msg);
+        }
+        for (key, pinned) in state.pinned {
+            inner
+                .pinned_msgs
+                .insert(CanonicalKey::new(key), pinned.into());
+        }
+        for (key, seq) in state.seqs {
+            inner.key_seqs.insert(CanonicalKey::new(key), seq);
+        }
+        for paused in state.paused_keys {
+            inner.paused_keys.insert(
+                CanonicalKey::new(paused.key),
+                PausedKey {
+                    policy: paused.policy,
+                    buffered: paused.buffered.into(),
+                },
+            );
+        }
+        for (key, history) in state.histories {
+            inner
+                .histories
+                .insert(CanonicalKey::new(key), history.into());
+        }
+
+        inner.messages_sent = state.messages_sent;
+    }
+
+    /// Sends the messages returned by `f` to the clients. Returns the number of clients a message
+    /// was sent to.
+    async fn send_to_clients<F>(&self, f: F) -> usize
+    where
+        F: Fn(&Uuid, &ClientMeta) -> Option<(Value, Value)>,
+    {
+        let frames = {
+            let inner = self.lock().await;
+
+            inner
+                .client_to_sender
+                .iter()
+                .filter_map(|(client_id, sender)| {
+                    let (key, msg) = f(client_id, &inner.client_meta(*client_id))?;
+                    Some((
+                        sender.clone(),
+                        SharedFrame::new(ChannelMsg::Msg {
+                            key: CanonicalKey::new(key),
+                            msg,
+                            id: None,
+                            sender: None,
+                            seq: None,
+                        }),
+                    ))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Send without holding the lock so that slow clients don't block the socket
+        let mut sent = 0;
+        for (sender, frame) in frames {
+            match sender.send(frame).await {
+                Ok(()) => sent += 1,
+                Err(err) => debug!("Failed to send websocket message: {:?}", err),
+            }
+        }
+
+        sent
+    }
+
+    /// Returns a stream of all messages that are sent with the given key. This is handy to feed the
+    /// messages of a key into a `futures` or `tokio-stream` pipeline on the server.
+    ///
+    /// If the stream can't keep up, the missed messages are skipped and a warning is logged.
+    /// Messages that can't be deserialized into `Msg` are skipped as well. The stream ends when the
+    /// key is drained with [`ServerSocketInner::drain_key`].
     ///
     /// ```
+    /// # use futures_util::StreamExt;
     /// # use leptos_axum_socket::{ServerSocket, SocketMsg};
     /// # use serde::{Serialize, Deserialize};
-    /// # use axum::extract::{State, FromRef};
+    /// # use axum::extract::FromRef;
     /// #
     /// # #[derive(FromRef, Clone)]
     /// # pub struct AppState {
@@ -98,138 +547,2536 @@ impl ServerSocketInner {
     /// #
     /// # impl SocketMsg for TheMessage {
     /// #     type Key = TheKey;
-    /// #     #[cfg(feature = "ssr")]
-    /// #     type AppState = AppState;
     /// # }
+    /// #
+    /// async fn log_messages(socket: ServerSocket) {
+    ///     let mut messages = socket.stream::<TheMessage>(&TheKey).await;
     ///
-    /// async fn axum_handler(State(socket): State<ServerSocket>) {
-    ///     socket.lock().await.send(&TheKey, &TheMessage);
+    ///     while let Some(msg) = messages.next().await {
+    ///         println!("{msg:?}");
+    ///     }
     /// }
     /// ```
-    #[instrument]
-    pub fn send<Msg>(&mut self, key: &Msg::Key, msg: &Msg)
+    pub async fn stream<Msg>(
+        &self,
+        key: &Msg::Key,
+    ) -> impl Stream<Item = Msg> + Send + Unpin + 'static
+    where
+        Msg: SocketMsg + Send + 'static,
+        for<'de> Msg: Deserialize<'de>,
+        Msg::Key: Serialize,
+    {
+        let key = codec::to_key(key).unwrap();
+        let receiver = self.lock().await.subscribe(key);
+
+        Box::pin(stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                let msg = match receiver.recv().await.as_deref().map(SharedFrame::msg) {
+                    Ok(ChannelMsg::Msg { msg, .. } | ChannelMsg::PathMsg { msg, .. }) => {
+                        msg.clone()
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(count)) => {
+                        warn!("Stream lagged behind. Skipped {count} messages");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return None,
+                };
+
+                match serde_json::from_value(msg) {
+                    Ok(msg) => return Some((msg, receiver)),
+                    Err(err) => error!("Failed to deserialize message: {err}"),
+                }
+            }
+        }))
+    }
+
+    /// Returns a stream that yields the key and its new number of subscribed clients whenever a client
+    /// subscribes to or unsubscribes from a key, including when it disconnects. This is useful for
+    /// live dashboards.
+    ///
+    /// The count is the number of clients, not the number of receivers like in
+    /// [`KeySnapshot::receiver_count`]. It is `0` when the last client left. If the stream can't keep
+    /// up, the missed updates are skipped and a warning is logged. Use
+    /// [`ServerSocketInner::snapshot`] to get the initial counts.
+    ///
+    /// ```
+    /// # use futures_util::StreamExt;
+    /// # use leptos_axum_socket::ServerSocket;
+    /// #
+    /// async fn log_subscriber_counts(socket: ServerSocket) {
+    ///     let mut counts = socket.subscribe_count_stream().await;
+    ///
+    ///     while let Some((key, count)) = counts.next().await {
+    ///         println!("{key} has {count} subscribers");
+    ///     }
+    /// }
+    /// ```
+    pub async fn subscribe_count_stream(
+        &self,
+    ) -> impl Stream<Item = (Value, usize)> + Send + Unpin + 'static {
+        let receiver = self
+            .lock()
+            .await
+            .subscriber_count_tx
+            .get_or_insert_with(|| broadcast::channel(256).0)
+            .subscribe();
+
+        Box::pin(stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok((key, count)) => return Some(((key.into_value(), count), receiver)),
+                    Err(RecvError::Lagged(count)) => {
+                        warn!("Subscriber count stream lagged behind. Skipped {count} updates");
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }))
+    }
+
+    /// Spawns a task that sends every item of `stream` to the subscribers of its key. This is the
+    /// counterpart of [`ServerSocket::stream`] and bridges an external source like a message queue
+    /// consumer or a database change stream into the socket.
+    ///
+    /// The task ends when the stream ends. Abort the returned handle to stop it earlier.
+    ///
+    /// ```
+    /// # use futures_util::stream;
+    /// # use leptos_axum_socket::{ServerSocket, SocketMsg};
+    /// # use serde::{Serialize, Deserialize};
+    /// # use axum::extract::FromRef;
+    /// #
+    /// # #[derive(FromRef, Clone)]
+    /// # pub struct AppState {
+    /// #     pub socket: ServerSocket,
+    /// # }
+    /// #
+    /// #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// struct OrderKey(u64);
+    ///
+    /// #[derive(Clone, Serialize, Deserialize, Debug)]
+    /// struct OrderChanged {
+    ///     status: String,
+    /// }
+    ///
+    /// impl SocketMsg for OrderChanged {
+    ///     type Key = OrderKey;
+    /// }
+    ///
+    /// fn bridge_changes(socket: &ServerSocket) {
+    ///     // In a real app this would be e.g. a Kafka consumer
+    ///     let changes = stream::iter([(
+    ///         OrderKey(1),
+    ///         OrderChanged {
+    ///             status: "shipped".to_string(),
+    ///         },
+    ///     )]);
+    ///
+    ///     socket.publish_stream(changes);
+    /// }
+    /// ```
+    pub fn publish_stream<Msg, S>(&self, stream: S) -> JoinHandle<()>
     where
         Msg: SocketMsg + Serialize + Clone + Send + Sync + Debug + 'static,
         for<'de> Msg: Deserialize<'de>,
         Msg::Key: Hash + Eq + Serialize + Clone + Send + Sync + Debug + 'static,
         for<'de> Msg::Key: Deserialize<'de>,
+        S: Stream<Item = (Msg::Key, Msg)> + Send + 'static,
+    {
+        let socket = self.clone();
+
+        self.1.spawn(async move {
+            let mut stream = pin!(stream);
+
+            while let Some((key, msg)) = stream.next().await {
+                socket.lock().await.send(&key, &msg);
+            }
+        })
+    }
+}
+
+/// Builder for a [`ServerSocket`] that is fully configured before any client connects.
+/// Create it with [`ServerSocket::builder`].
+///
+/// Everything can also be configured later through [`ServerSocket::lock`]. The builder only saves
+/// you from locking the socket in `main.rs`. Use [`ServerSocketBuilder::configure`] for the options
+/// that don't have a setter here.
+///
+/// ```
+/// # use leptos_axum_socket::ServerSocket;
+/// #
+/// let socket = ServerSocket::builder()
+///     .channel_capacity(64)
+///     .max_subscriptions_per_client(32)
+///     .stamp_sender()
+///     .configure(|socket| socket.clear_retained(&"motd"))
+///     .build();
+/// ```
+#[derive(Debug, Default)]
+pub struct ServerSocketBuilder {
+    inner: ServerSocketInner,
+}
+
+impl ServerSocketBuilder {
+    /// Spawns all tasks of the socket and its connections on the runtime of `handle`. See
+    /// [`ServerSocket::with_runtime_handle`].
+    pub fn runtime_handle(mut self, handle: Handle) -> Self {
+        self.inner.spawner = Spawner(Some(handle));
+        self
+    }
+
+    /// See [`ServerSocketInner::set_channel_capacity`].
+    pub fn channel_capacity(mut self, capacity: usize) -> Self {
+        self.inner.set_channel_capacity(capacity);
+        self
+    }
+
+    /// See [`ServerSocketInner::set_max_subscriptions_per_client`].
+    pub fn max_subscriptions_per_client(mut self, max: usize) -> Self {
+        self.inner.set_max_subscriptions_per_client(max);
+        self
+    }
+
+    /// See [`ServerSocketInner::set_max_connections`].
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.inner.set_max_connections(max);
+        self
+    }
+
+    /// See [`ServerSocketInner::stamp_sender`].
+    pub fn stamp_sender(mut self) -> Self {
+        self.inner.stamp_sender();
+        self
+    }
+
+    /// See [`ServerSocketInner::add_middleware`].
+    pub fn middleware(mut self, middleware: impl SocketMiddleware) -> Self {
+        self.inner.add_middleware(middleware);
+        self
+    }
+
+    /// See [`ServerSocketInner::on_wire_frame`].
+    pub fn on_wire_frame(
+        mut self,
+        hook: impl Fn(Direction, &[u8]) + Send + Sync + 'static,
+    ) -> Self {
+        self.inner.on_wire_frame(hook);
+        self
+    }
+
+    /// Calls `f` with the socket that is being built, e.g. to add filters, mappers and validators.
+    pub fn configure(mut self, f: impl FnOnce(&mut ServerSocketInner)) -> Self {
+        f(&mut self.inner);
+        self
+    }
+
+    /// Creates the configured socket.
+    pub fn build(self) -> ServerSocket {
+        let spawner = self.inner.spawner.clone();
+        let connection_slots = Arc::clone(&self.inner.connection_slots);
+
+        ServerSocket(Arc::new(Mutex::new(self.inner)), spawner, connection_slots)
+    }
+}
+
+/// Spawns tasks on the runtime the socket was created with or on the current runtime.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Spawner(Option<Handle>);
+
+impl Spawner {
+    pub(crate) fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        match &self.0 {
+            Some(handle) => handle.spawn(future),
+            None => tokio::spawn(future),
+        }
+    }
+}
+
+/// Counts the open connections and the client IDs they use. It's shared outside of the lock so
+/// that upgrades can be rejected, and resumed IDs checked, before they are accepted.
+#[derive(Debug)]
+pub(crate) struct ConnectionSlots {
+    max: AtomicUsize,
+    open: AtomicUsize,
+    client_ids: std::sync::Mutex<HashSet<Uuid>>,
+}
+
+impl Default for ConnectionSlots {
+    fn default() -> Self {
+        Self {
+            max: AtomicUsize::new(usize::MAX),
+            open: AtomicUsize::new(0),
+            client_ids: Default::default(),
+        }
+    }
+}
+
+impl ConnectionSlots {
+    fn acquire(slots: &Arc<Self>) -> Option<ConnectionSlot> {
+        slots
+            .open
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                (open < slots.max.load(Ordering::SeqCst)).then_some(open + 1)
+            })
+            .ok()?;
+
+        Some(ConnectionSlot {
+            slots: Arc::clone(slots),
+            client_id: None,
+        })
+    }
+}
+
+/// An open connection. The slot and its client ID are freed when this is dropped.
+#[derive(Debug)]
+pub(crate) struct ConnectionSlot {
+    slots: Arc<ConnectionSlots>,
+    client_id: Option<Uuid>,
+}
+
+impl ConnectionSlot {
+    /// Reserves `client_id` for this connection. Returns `false` if another open connection
+    /// already uses it.
+    pub(crate) fn claim_client_id(&mut self, client_id: Uuid) -> bool {
+        let claimed = self
+            .slots
+            .client_ids
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(client_id);
+
+        if claimed {
+            self.release_client_id();
+            self.client_id = Some(client_id);
+        }
+
+        claimed
+    }
+
+    fn release_client_id(&mut self) {
+        if let Some(client_id) = self.client_id.take() {
+            self.slots
+                .client_ids
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(&client_id);
+        }
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.release_client_id();
+        self.slots.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The default for [`ServerSocketInner::set_max_subscriptions_per_client`].
+pub const DEFAULT_MAX_SUBSCRIPTIONS_PER_CLIENT: usize = 256;
+
+/// The default for [`ServerSocketInner::set_channel_capacity`].
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 16;
+
+/// Number of messages that can be pinned to a key. Pinning another one unpins the oldest.
+pub const MAX_PINNED_MSGS: usize = 16;
+
+/// Number of message IDs that are remembered, together with the ID of the client that sent them, to
+/// recognize messages that a client sends again after reconnecting.
+const RECENT_MSG_IDS: usize = 1024;
+
+/// ID of the history entry of the message that [`ServerSocketInner::latest_msg`] returns. The
+/// entries of pinned messages have the ID of the pin.
+const LATEST_HISTORY_ID: Uuid = Uuid::nil();
+
+pub(crate) type SubscribeFilterFn =
+    Arc<dyn Fn(Value, &dyn Any) -> Pin<Box<dyn Future<Output = bool> + Send>> + Send + Sync>;
+pub(crate) type SendMapFn =
+    Arc<dyn Fn(Value, Value, &dyn Any) -> serde_json::Result<Option<Value>> + Send + Sync>;
+pub(crate) type AsyncSendMapFn =
+    Arc<dyn Fn(Value, Value, &dyn Any) -> serde_json::Result<MapMsgFuture> + Send + Sync>;
+pub(crate) type MapMsgFuture = Pin<Box<dyn Future<Output = Option<Value>> + Send>>;
+type ValidatorFn = Arc<dyn Fn(&Value, &Value) -> Option<Result<(), String>> + Send + Sync>;
+type DeadLetterFn = Arc<dyn Fn(DeadLetterReason, &Value, &Value) + Send + Sync>;
+type DisconnectFn = Arc<dyn Fn(&DisconnectInfo) + Send + Sync>;
+type KeyMatchFn = Arc<dyn Fn(&Value) -> bool + Send + Sync>;
+type MsgMatchFn = Arc<dyn Fn(&Value, &Value) -> bool + Send + Sync>;
+type SenderMetaFn = Arc<dyn Fn(Uuid, &dyn Any) -> Value + Send + Sync>;
+type FilterEvaluatorFn =
+    Arc<dyn Fn(&Value, &Value) -> serde_json::Result<MsgPredicate> + Send + Sync>;
+type MsgPredicate = Arc<dyn Fn(&Value) -> bool + Send + Sync>;
+pub(crate) type WireFrameFn = Arc<dyn Fn(Direction, &[u8]) + Send + Sync>;
+
+/// The handlers of [`ServerSocketInner::on_dead_letter`]. They are shared with the send mappers in
+/// the middleware chain, which report the messages they drop.
+#[derive(Clone, Default)]
+pub(crate) struct DeadLetters(Arc<std::sync::RwLock<Vec<DeadLetterFn>>>);
+
+impl DeadLetters {
+    fn push(&self, handler: DeadLetterFn) {
+        self.0
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(handler);
+    }
+
+    pub(crate) fn report(&self, reason: DeadLetterReason, key: &Value, msg: &Value) {
+        for handler in self.0.read().unwrap_or_else(PoisonError::into_inner).iter() {
+            handler(reason, key, msg);
+        }
+    }
+}
+
+/// A serializable snapshot of the internal state of the server socket. Useful for debugging.
+///
+/// See [`ServerSocketInner::snapshot`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SocketSnapshot {
+    /// Number of keys that currently have a broadcast sender.
+    pub sender_count: usize,
+    /// The keys that currently have a broadcast sender.
+    pub keys: Vec<KeySnapshot>,
+    /// IDs of all connected clients.
+    pub client_ids: Vec<Uuid>,
+    /// Number of registered subscribe filters.
+    pub subscribe_filter_count: usize,
+    /// Number of registered send mappers.
+    pub send_mapper_count: usize,
+    /// Number of messages that were sent with a key since the server started.
+    pub messages_sent: u64,
+    /// Bytes of the frames that were sent to the clients since the server started, before they
+    /// were compressed. See [`ClientMeta::raw_bytes_sent`].
+    #[serde(default)]
+    pub raw_bytes_sent: u64,
+    /// Bytes of the frames that were sent to the clients since the server started, as they went
+    /// over the wire. See [`ClientMeta::wire_bytes_sent`].
+    #[serde(default)]
+    pub wire_bytes_sent: u64,
+}
+
+/// The logical state of a [`ServerSocket`] that can be moved to another instance. Connections and
+/// subscriptions can't be moved, the clients subscribe again when they reconnect.
+///
+/// See [`ServerSocket::export_state`].
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SocketState {
+    /// The latest message of every stateful key.
+    pub latest: Vec<(Value, Value)>,
+    /// The messages that were sent with [`ServerSocketInner::send_retained`].
+    pub retained: Vec<(Value, Value)>,
+    /// The messages that were pinned with [`ServerSocketInner::send_pinned`] together with their IDs.
+    #[serde(default)]
+    pub pinned: Vec<(Value, Vec<(Uuid, Value)>)>,
+    /// The sequence number of the last message of every key.
+    pub seqs: Vec<(Value, u64)>,
+    /// The keys that are paused with [`ServerSocketInner::pause_key`].
+    pub paused_keys: Vec<PausedKeyState>,
+    /// The history of every key together with the IDs of its entries, see
+    /// [`ServerSocketInner::set_history_len`].
+    #[serde(default)]
+    pub histories: Vec<(Value, Vec<(Uuid, Value)>)>,
+    /// Number of messages that were sent with a key.
+    pub messages_sent: u64,
+}
+
+/// Part of a [`SocketState`] that describes a paused key.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PausedKeyState {
+    pub key: Value,
+    pub policy: KeyPausePolicy,
+    /// The buffered messages together with their IDs and senders.
+    pub buffered: Vec<(Value, Option<Uuid>, Option<SenderInfo>)>,
+}
+
+/// Why a message couldn't be delivered.
+///
+/// See [`ServerSocketInner::on_dead_letter`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DeadLetterReason {
+    /// Nobody was subscribed to the key, to one of its ancestors or to its type.
+    NoReceivers,
+    /// The client of `send_to_self` isn't connected.
+    ClientNotFound,
+    /// A send mapper returned `None`.
+    DroppedByMapper,
+    /// The key was paused and the message wasn't buffered. See [`ServerSocketInner::pause_key`].
+    KeyPaused,
+    /// The client's buffer was still full after the last retry. See
+    /// [`ServerSocket::send_to_client_retry`].
+    ClientBufferFull,
+}
+
+/// What happens to the messages that are sent with a paused key.
+///
+/// See [`ServerSocketInner::pause_key`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyPausePolicy {
+    /// Keep up to `max` messages and send them on resume. If more are sent, the oldest ones are
+    /// dropped.
+    Buffer { max: usize },
+    /// Drop all messages.
+    Drop,
+}
+
+/// The messages that were sent while a key was paused.
+struct PausedKey {
+    policy: KeyPausePolicy,
+    buffered: VecDeque<(Value, Option<Uuid>, Option<SenderInfo>)>,
+}
+
+impl PausedKey {
+    /// Buffers the message according to the policy. Returns the message that was dropped instead.
+    fn hold(&mut self, msg: Value, id: Option<Uuid>, sender: Option<SenderInfo>) -> Option<Value> {
+        let KeyPausePolicy::Buffer { max } = self.policy else {
+            return Some(msg);
+        };
+
+        if max == 0 {
+            return Some(msg);
+        }
+
+        self.buffered.push_back((msg, id, sender));
+
+        if self.buffered.len() > max {
+            self.buffered.pop_front().map(|(msg, ..)| msg)
+        } else {
+            None
+        }
+    }
+}
+
+/// Who a message is delivered to. See [`ServerSocketInner::deliver`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DeliveryTarget {
+    /// Every subscriber of the key. The message is fanned out through the key's broadcast channel,
+    /// so subscribers that lag too far behind skip messages.
+    AllSubscribers,
+    /// Only the client with this ID, regardless of whether it's subscribed to the key. Delivery
+    /// waits if the client's buffer is full.
+    Client(Uuid),
+    /// Only the clients with these IDs, like [`DeliveryTarget::Client`].
+    Clients(Vec<Uuid>),
+}
+
+/// Information about a connected client.
+///
+/// See [`ServerSocket::for_each_client`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ClientMeta {
+    /// When the client connected.
+    pub connected_at: SystemTime,
+    /// The keys the client is subscribed to.
+    pub keys: HashSet<CanonicalKey>,
+    /// Whether the frames sent to the client are compressed with `permessage-deflate`. axum doesn't
+    /// negotiate the extension, so this is currently always `false`.
+    pub compression: bool,
+    /// Bytes of the frames that were sent to the client, before they were compressed.
+    pub raw_bytes_sent: u64,
+    /// Bytes of the frames that were sent to the client, as they went over the wire. Divide by
+    /// [`ClientMeta::raw_bytes_sent`] to get the compression ratio.
+    pub wire_bytes_sent: u64,
+}
+
+/// Counts the bytes that are sent, before and after compression.
+#[derive(Debug, Default)]
+pub(crate) struct BytesSent {
+    raw: AtomicU64,
+    wire: AtomicU64,
+}
+
+impl BytesSent {
+    fn get(&self) -> (u64, u64) {
+        (
+            self.raw.load(Ordering::Relaxed),
+            self.wire.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Counts the bytes that are sent to one client, as well as the total of all clients.
+#[derive(Clone, Debug)]
+pub(crate) struct TrafficCounter {
+    client: Arc<BytesSent>,
+    total: Arc<BytesSent>,
+}
+
+impl TrafficCounter {
+    /// Called for every frame that is sent to the client.
+    pub(crate) fn count(&self, raw: usize, wire: usize) {
+        for bytes_sent in [&self.client, &self.total] {
+            bytes_sent.raw.fetch_add(raw as u64, Ordering::Relaxed);
+            bytes_sent.wire.fetch_add(wire as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Information about a client that disconnected.
+///
+/// See [`ServerSocketInner::on_disconnect`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct DisconnectInfo {
+    pub client_id: Uuid,
+    /// When the client connected and the keys it was subscribed to when it disconnected.
+    pub meta: ClientMeta,
+}
+
+/// The filter that a client sent with its subscription to a key. Decides which messages are
+/// forwarded to it.
+#[derive(Clone, Default)]
+pub(crate) struct SubscriberFilter(Option<MsgPredicate>);
+
+impl SubscriberFilter {
+    /// Returns `true` if the frame should be forwarded. Frames that don't carry a message always are.
+    pub(crate) fn accepts(&self, frame: &ChannelMsg) -> bool {
+        match frame {
+            ChannelMsg::Msg { msg, .. } | ChannelMsg::PathMsg { msg, .. } => self.accepts_msg(msg),
+            _ => true,
+        }
+    }
+
+    /// Returns `true` if the message should be forwarded.
+    pub(crate) fn accepts_msg(&self, msg: &Value) -> bool {
+        self.0.as_ref().is_none_or(|predicate| predicate(msg))
+    }
+}
+
+/// A frame that is sent to clients. It's shared between all receivers and serialized at most
+/// once, no matter how many clients it is sent to. This is used for the broadcast channels of the
+/// keys as well as for the channels of the individual clients.
+#[derive(Debug)]
+pub(crate) struct SharedFrame {
+    msg: ChannelMsg,
+    #[cfg_attr(feature = "reliable", allow(dead_code))]
+    text: OnceLock<Option<Utf8Bytes>>,
+}
+
+impl SharedFrame {
+    pub(crate) fn new(msg: ChannelMsg) -> Arc<Self> {
+        Arc::new(Self {
+            msg,
+            text: OnceLock::new(),
+        })
+    }
+
+    pub(crate) fn msg(&self) -> &ChannelMsg {
+        &self.msg
+    }
+
+    /// Returns the message of the frame. It's only cloned if the frame is still shared.
+    #[cfg(feature = "longpoll")]
+    pub(crate) fn into_msg(frame: Arc<Self>) -> ChannelMsg {
+        Arc::try_unwrap(frame)
+            .map(|frame| frame.msg)
+            .unwrap_or_else(|frame| frame.msg.clone())
+    }
+
+    /// Returns the serialized frame. `None` if it can't be serialized. Cloning the returned bytes
+    /// doesn't copy the text.
+    #[cfg_attr(feature = "reliable", allow(dead_code))]
+    pub(crate) fn text(&self) -> Option<&Utf8Bytes> {
+        self.text
+            .get_or_init(|| {
+                codec::to_string(&self.msg)
+                    .map(Utf8Bytes::from)
+                    .map_err(|err| error!("Failed to serialize frame: {err}"))
+                    .ok()
+            })
+            .as_ref()
+    }
+}
+
+/// Part of a [`SocketSnapshot`] that describes a single key.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeySnapshot {
+    pub key: Value,
+    /// Number of receivers that are currently subscribed to the key.
+    pub receiver_count: usize,
+}
+
+/// This is used on the server to manage socket connections.
+#[derive(Default)]
+pub struct ServerSocketInner {
+    sender_map: HashMap<CanonicalKey, broadcast::Sender<Arc<SharedFrame>>>,
+    client_to_sender: HashMap<Uuid, mpsc::Sender<Arc<SharedFrame>>>,
+    close_senders: HashMap<Uuid, oneshot::Sender<CloseFrame>>,
+    validators: Vec<ValidatorFn>,
+    middlewares: Middlewares,
+    sender_meta: Option<SenderMetaFn>,
+    subscription_filter_evaluators: Vec<FilterEvaluatorFn>,
+    wire_frame_hook: Option<WireFrameFn>,
+    handles: HashMap<(Uuid, CanonicalKey), JoinHandle<()>>,
+    client_keys: HashMap<Uuid, HashSet<CanonicalKey>>,
+    connected_at: HashMap<Uuid, (SystemTime, Instant)>,
+    bytes_sent: HashMap<Uuid, Arc<BytesSent>>,
+    total_bytes_sent: Arc<BytesSent>,
+    #[cfg(feature = "longpoll")]
+    longpoll_sessions: HashMap<Uuid, crate::handlers::longpoll::LongPollSession>,
+    stateful_keys: Vec<KeyMatchFn>,
+    latest_values: HashMap<CanonicalKey, Value>,
+    retained_values: HashMap<CanonicalKey, Value>,
+    pinned_msgs: HashMap<CanonicalKey, VecDeque<(Uuid, Value)>>,
+    history_lens: Vec<(KeyMatchFn, usize)>,
+    histories: HashMap<CanonicalKey, VecDeque<(Uuid, Value)>>,
+    hierarchical_keys: Vec<KeyMatchFn>,
+    registered_types: HashMap<String, MsgMatchFn>,
+    #[cfg(feature = "json-schema")]
+    schemas: serde_json::Map<String, Value>,
+    max_subscriptions_per_client: Option<usize>,
+    channel_capacity: Option<usize>,
+    dead_letters: DeadLetters,
+    disconnect_handlers: Vec<DisconnectFn>,
+    subscribe_notify: Arc<Notify>,
+    subscriber_counts: HashMap<CanonicalKey, usize>,
+    subscriber_count_tx: Option<broadcast::Sender<(CanonicalKey, usize)>>,
+    messages_sent: u64,
+    key_seqs: HashMap<CanonicalKey, u64>,
+    paused_keys: HashMap<CanonicalKey, PausedKey>,
+    recent_msg_ids: HashSet<(Uuid, Uuid)>,
+    recent_msg_id_order: VecDeque<(Uuid, Uuid)>,
+    spawner: Spawner,
+    connection_slots: Arc<ConnectionSlots>,
+}
+
+impl std::fmt::Debug for ServerSocketInner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerSocketChannels")
+            .field("sender_map", &self.sender_map)
+            .field(
+                "subscribe_filters",
+                &self.middlewares.count(LayerKind::SubscribeFilter),
+            )
+            .field(
+                "send_mappers",
+                &self.middlewares.count(LayerKind::SendMapper),
+            )
+            .field(
+                "async_send_mappers",
+                &self.middlewares.count(LayerKind::AsyncSendMapper),
+            )
+            .field("validators", &self.validators.len())
+            .field(
+                "middlewares",
+                &self.middlewares.count(LayerKind::Middleware),
+            )
+            .field("stamp_sender", &self.sender_meta.is_some())
+            .field(
+                "subscription_filter_evaluators",
+                &self.subscription_filter_evaluators.len(),
+            )
+            .field("on_wire_frame", &self.wire_frame_hook.is_some())
+            .field("stateful_keys", &self.stateful_keys.len())
+            .field("latest_values", &self.latest_values.len())
+            .field("retained_values", &self.retained_values.len())
+            .field("pinned_msgs", &self.pinned_msgs.len())
+            .field("histories", &self.histories.len())
+            .field("hierarchical_keys", &self.hierarchical_keys.len())
+            .field("registered_types", &self.registered_types.keys())
+            .finish()
+    }
+}
+
+impl ServerSocketInner {
+    /// Returns a snapshot of the internal state that can be logged or rendered in an admin endpoint.
+    ///
+    /// ```
+    /// # use axum::{Json, extract::State};
+    /// # use leptos_axum_socket::{ServerSocket, SocketSnapshot};
+    /// #
+    /// async fn socket_debug(State(socket): State<ServerSocket>) -> Json<SocketSnapshot> {
+    ///     Json(socket.lock().await.snapshot())
+    /// }
+    /// ```
+    pub fn snapshot(&self) -> SocketSnapshot {
+        let (raw_bytes_sent, wire_bytes_sent) = self.total_bytes_sent.get();
+
+        SocketSnapshot {
+            sender_count: self.sender_map.len(),
+            keys: self
+                .sender_map
+                .iter()
+                .map(|(key, sender)| KeySnapshot {
+                    key: key.as_value().clone(),
+                    receiver_count: sender.receiver_count(),
+                })
+                .collect(),
+            client_ids: self.client_to_sender.keys().copied().collect(),
+            subscribe_filter_count: self.middlewares.count(LayerKind::SubscribeFilter),
+            send_mapper_count: self.middlewares.count(LayerKind::SendMapper),
+            messages_sent: self.messages_sent,
+            raw_bytes_sent,
+            wire_bytes_sent,
+        }
+    }
+
+    #[instrument]
+    fn sender(&mut self, key: &CanonicalKey) -> broadcast::Sender<Arc<SharedFrame>> {
+        if let Some(sender) = self.sender_map.get(key) {
+            return sender.clone();
+        }
+
+        debug!("Creating new sender for key");
+
+        let sender =
+            broadcast::Sender::new(self.channel_capacity.unwrap_or(DEFAULT_CHANNEL_CAPACITY));
+        self.sender_map.insert(key.clone(), sender.clone());
+        sender
+    }
+
+    /// Like [`ServerSocketInner::send`] but the message is retained for the key. Everyone who
+    /// subscribes to the key later immediately receives this message. Only the last retained message
+    /// of a key is kept. Use [`ServerSocketInner::clear_retained`] to remove it.
+    ///
+    /// Unlike [`ServerSocketInner::set_stateful`] this is decided per message instead of per key type.
+    ///
+    /// ```
+    /// # use axum::extract::{State, FromRef};
+    /// # use leptos_axum_socket::{ServerSocket, SocketMsg};
+    /// # use serde::{Serialize, Deserialize};
+    /// #
+    /// # #[derive(FromRef, Clone)]
+    /// # pub struct AppState {
+    /// #     pub socket: ServerSocket,
+    /// # }
+    /// #
+    /// #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// pub struct AnnouncementKey;
+    ///
+    /// #[derive(Clone, Serialize, Deserialize, Debug)]
+    /// pub struct Announcement(pub String);
+    ///
+    /// impl SocketMsg for Announcement {
+    ///     type Key = AnnouncementKey;
+    /// }
+    ///
+    /// async fn announce(State(socket): State<ServerSocket>) {
+    ///     socket.lock().await.send_retained(
+    ///         &AnnouncementKey,
+    ///         &Announcement("Maintenance at 10pm".to_string()),
+    ///     );
+    /// }
+    /// ```
+    #[instrument]
+    pub fn send_retained<Msg>(&mut self, key: &Msg::Key, msg: &Msg)
+    where
+        Msg: SocketMsg + Serialize + Clone + Send + Sync + Debug + 'static,
+        for<'de> Msg: Deserialize<'de>,
+        Msg::Key: Hash + Eq + Serialize + Clone + Send + Sync + Debug + 'static,
+        for<'de> Msg::Key: Deserialize<'de>,
+    {
+        let key = codec::to_key(key).unwrap();
+        let msg = codec::to_value(msg).unwrap();
+
+        self.retained_values.insert(key.clone(), msg.clone());
+        self.send_serialized(key.clone(), msg);
+        self.mark_history(&key, LATEST_HISTORY_ID);
+    }
+
+    /// Removes the retained message of the key that was sent with [`ServerSocketInner::send_retained`].
+    /// Nothing is sent to the current subscribers.
+    pub fn clear_retained<K>(&mut self, key: &K)
+    where
+        K: Serialize,
+    {
+        let key = codec::to_key(key).unwrap();
+        self.retained_values.remove(&key);
+    }
+
+    /// Returns the history of the key, oldest message first. Only keys that were configured with
+    /// [`ServerSocketInner::set_history_len`] have a history. Messages that can't be deserialized
+    /// into `Msg`, for example because another message type uses the same key, are skipped.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::{ServerSocket, SocketMsg};
+    /// # use serde::{Serialize, Deserialize};
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// # pub struct RoomKey(pub u32);
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, Debug)]
+    /// # pub struct ChatMsg(pub String);
+    /// #
+    /// # impl SocketMsg for ChatMsg {
+    /// #     type Key = RoomKey;
+    /// # }
+    /// #
+    /// async fn moderate(socket: ServerSocket, room_id: u32) {
+    ///     let mut socket = socket.lock().await;
+    ///
+    ///     let history = socket.history::<ChatMsg>(&RoomKey(room_id));
+    ///     if history.iter().any(|msg| msg.0.contains("spam")) {
+    ///         // Delete all messages of the room
+    ///         socket.flush_history(&RoomKey(room_id));
+    ///     }
+    /// }
+    /// ```
+    pub fn history<Msg>(&self, key: &Msg::Key) -> Vec<Msg>
+    where
+        Msg: SocketMsg,
+        for<'de> Msg: Deserialize<'de>,
+        Msg::Key: Serialize,
+    {
+        let key = codec::to_key(key).unwrap();
+
+        self.histories
+            .get(&key)
+            .into_iter()
+            .flatten()
+            .filter_map(|(_, msg)| Msg::deserialize(msg).ok())
+            .collect()
+    }
+
+    /// Removes the history of the key without affecting its subscribers. Returns the number of
+    /// removed messages.
+    ///
+    /// Sending requires the lock as well, so a message that is sent concurrently is either
+    /// removed too or becomes the first message of the new history.
+    pub fn flush_history<K>(&mut self, key: &K) -> usize
+    where
+        K: Serialize,
+    {
+        let key = codec::to_key(key).unwrap();
+
+        self.histories
+            .remove(&key)
+            .map_or(0, |history| history.len())
+    }
+
+    /// Like [`ServerSocketInner::send`] but the message is also pinned to the key, for example an
+    /// announcement in a chat room. Everyone who subscribes to the key later receives the pinned
+    /// messages first, oldest first, followed by the history and the retained message of the key.
+    ///
+    /// Unlike a retained message, up to [`MAX_PINNED_MSGS`] messages can be pinned to a key. If
+    /// more are pinned, the oldest is unpinned. Returns the ID to pass to
+    /// [`ServerSocketInner::unpin`].
+    ///
+    /// ```
+    /// # use leptos_axum_socket::{ServerSocket, SocketMsg};
+    /// # use serde::{Serialize, Deserialize};
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// # pub struct RoomKey(pub u32);
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, Debug)]
+    /// # pub struct ChatMsg(pub String);
+    /// #
+    /// # impl SocketMsg for ChatMsg {
+    /// #     type Key = RoomKey;
+    /// # }
+    /// #
+    /// async fn announce(socket: ServerSocket) {
+    ///     let mut socket = socket.lock().await;
+    ///
+    ///     let id = socket.send_pinned(&RoomKey(1), &ChatMsg("Be nice!".to_string()));
+    ///
+    ///     // Later
+    ///     socket.unpin(&RoomKey(1), id);
+    /// }
+    /// ```
+    pub fn send_pinned<Msg>(&mut self, key: &Msg::Key, msg: &Msg) -> Uuid
+    where
+        Msg: SocketMsg + Serialize + Clone + Send + Sync + Debug + 'static,
+        for<'de> Msg: Deserialize<'de>,
+        Msg::Key: Hash + Eq + Serialize + Clone + Send + Sync + Debug + 'static,
+        for<'de> Msg::Key: Deserialize<'de>,
+    {
+        let key = codec::to_key(key).unwrap();
+        let msg = codec::to_value(msg).unwrap();
+        let id = Uuid::new_v4();
+
+        let pinned = self.pinned_msgs.entry(key.clone()).or_default();
+        pinned.push_back((id, msg.clone()));
+        if pinned.len() > MAX_PINNED_MSGS {
+            pinned.pop_front();
+        }
+
+        self.send_serialized(key.clone(), msg);
+        self.mark_history(&key, id);
+
+        id
+    }
+
+    /// Unpins the message with the `id` that was returned by [`ServerSocketInner::send_pinned`].
+    /// Nothing is sent to the current subscribers. Returns `false` if the message isn't pinned to
+    /// the key.
+    pub fn unpin<K>(&mut self, key: &K, id: Uuid) -> bool
+    where
+        K: Serialize,
+    {
+        let key = codec::to_key(key).unwrap();
+
+        let Some(pinned) = self.pinned_msgs.get_mut(&key) else {
+            return false;
+        };
+
+        let len = pinned.len();
+        pinned.retain(|(pinned_id, _)| *pinned_id != id);
+        let unpinned = pinned.len() < len;
+
+        if pinned.is_empty() {
+            self.pinned_msgs.remove(&key);
+        }
+
+        unpinned
+    }
+
+    /// Returns the history of the key, oldest message first, without the pinned messages and the
+    /// message that [`ServerSocketInner::latest_msg`] returns. New subscribers receive those
+    /// separately.
+    pub(crate) fn history_msgs(&self, key: &CanonicalKey) -> impl Iterator<Item = &Value> {
+        let has_latest = self.latest_msg(key).is_some();
+        let pinned = self.pinned_msgs.get(key);
+
+        self.histories
+            .get(key)
+            .into_iter()
+            .flatten()
+            .filter(move |(id, _)| {
+                !(*id == LATEST_HISTORY_ID && has_latest
+                    || pinned.is_some_and(|pinned| pinned.iter().any(|(pin, _)| pin == id)))
+            })
+            .map(|(_, msg)| msg)
+    }
+
+    /// Returns the messages that are pinned to the key, oldest first.
+    pub(crate) fn pinned_msgs(&self, key: &CanonicalKey) -> impl Iterator<Item = &Value> {
+        self.pinned_msgs
+            .get(key)
+            .into_iter()
+            .flatten()
+            .map(|(_, msg)| msg)
+    }
+
+    /// Applies the JSON patch (RFC 6902) to the retained message of the key and sends only the
+    /// patch to the subscribers. They apply it to the last message they received for the key.
+    /// Requires the `patch` feature.
+    ///
+    /// The key needs a base message from [`ServerSocketInner::send_retained`] or a stateful key. If
+    /// the patch doesn't apply cleanly, nothing is sent. Returns the number of receivers the patch
+    /// was delivered to.
+    ///
+    /// Patches aren't forwarded to the subscribers of ancestors or of the message type and can't
+    /// be used together with a `PayloadCipher`.
+    ///
+    /// ```
+    /// # use axum::extract::{State, FromRef};
+    /// # use leptos_axum_socket::{ServerSocket, SocketMsg};
+    /// # use serde::{Serialize, Deserialize};
+    /// # use serde_json::json;
+    /// #
+    /// # #[derive(FromRef, Clone)]
+    /// # pub struct AppState {
+    /// #     pub socket: ServerSocket,
+    /// # }
+    /// #
+    /// #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// pub struct DocKey(pub u32);
+    ///
+    /// #[derive(Clone, Serialize, Deserialize, Debug)]
+    /// pub struct Doc {
+    ///     pub title: String,
+    ///     pub body: String,
+    /// }
+    ///
+    /// impl SocketMsg for Doc {
+    ///     type Key = DocKey;
+    /// }
+    ///
+    /// async fn rename(State(socket): State<ServerSocket>) {
+    ///     let mut socket = socket.lock().await;
+    ///
+    ///     socket.send_retained(
+    ///         &DocKey(1),
+    ///         &Doc { title: "Draft".to_string(), body: "...".to_string() },
+    ///     );
+    ///
+    ///     let patch = json!([{ "op": "replace", "path": "/title", "value": "Final" }]);
+    ///     socket.send_patch::<Doc>(&DocKey(1), &patch).unwrap();
+    /// }
+    /// ```
+    #[cfg(feature = "patch")]
+    pub fn send_patch<Msg>(&mut self, key: &Msg::Key, patch: &Value) -> Result<usize, PatchError>
+    where
+        Msg: SocketMsg,
+        Msg::Key: Serialize,
+    {
+        let key = codec::to_key(key).unwrap();
+        let patched = self.patched(&key, patch)?;
+
+        Ok(self.send_patch_serialized(key, patch.clone(), patched))
+    }
+
+    /// Returns the retained message of the key with the patch applied.
+    #[cfg(feature = "patch")]
+    pub(crate) fn patched(&self, key: &CanonicalKey, patch: &Value) -> Result<Value, PatchError> {
+        let mut msg = self
+            .latest_values
+            .get(key)
+            .or_else(|| self.retained_values.get(key))
+            .ok_or(PatchError::NoBase)?
+            .clone();
+
+        crate::apply_patch(&mut msg, patch)?;
+        Ok(msg)
+    }
+
+    /// Stores the `patched` message that [`ServerSocketInner::patched`] returned and broadcasts the
+    /// patch. Returns the number of receivers.
+    #[cfg(feature = "patch")]
+    pub(crate) fn send_patch_serialized(
+        &mut self,
+        key: CanonicalKey,
+        patch: Value,
+        patched: Value,
+    ) -> usize {
+        self.messages_sent += 1;
+
+        if let Some(latest) = self.latest_values.get_mut(&key) {
+            *latest = patched.clone();
+        }
+        if let Some(retained) = self.retained_values.get_mut(&key) {
+            *retained = patched.clone();
+        }
+
+        // Subscribers can't apply the patch if they miss the messages before it
+        if self.paused_keys.contains_key(&key) {
+            self.hold_paused(key, patched, None, None);
+            return 0;
+        }
+
+        let seq = self.next_seq(&key);
+
+        self.sender(&key)
+            .send(SharedFrame::new(ChannelMsg::Patch {
+                key,
+                patch,
+                seq: Some(seq),
+            }))
+            .unwrap_or_default()
+    }
+
+    /// Broadcast a message from the server to the subscribers of the given key.
+    ///
+    /// This is used to send messages from an axum handler.
+    /// If you want to send from a server function, use the module level [`send`] function.
+    ///
+    /// This is the same as [`ServerSocketInner::deliver`] with [`DeliveryTarget::AllSubscribers`]
+    /// but doesn't have to be awaited.
+    ///
+    /// Returns the number of receivers the message was delivered to, i.e. the number of subscriptions
+    /// to the key. This doesn't include subscribers of ancestor keys or type subscribers.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use leptos_axum_socket::{ServerSocket, SocketMsg};
+    /// # use serde::{Serialize, Deserialize};
+    /// # use axum::extract::{State, FromRef};
+    /// #
+    /// # #[derive(FromRef, Clone)]
+    /// # pub struct AppState {
+    /// #     pub socket: ServerSocket,
+    /// # }
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// # struct TheKey;
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, Debug)]
+    /// # struct TheMessage;
+    /// #
+    /// # impl SocketMsg for TheMessage {
+    /// #     type Key = TheKey;
+    /// # }
+    ///
+    /// async fn axum_handler(State(socket): State<ServerSocket>) {
+    ///     let receiver_count = socket.lock().await.send(&TheKey, &TheMessage);
+    ///     println!("Delivered to {receiver_count} subscribers");
+    /// }
+    /// ```
+    #[instrument]
+    pub fn send<Msg>(&mut self, key: &Msg::Key, msg: &Msg) -> usize
+    where
+        Msg: SocketMsg + Serialize + Clone + Send + Sync + Debug + 'static,
+        for<'de> Msg: Deserialize<'de>,
+        Msg::Key: Hash + Eq + Serialize + Clone + Send + Sync + Debug + 'static,
+        for<'de> Msg::Key: Deserialize<'de>,
+    {
+        let key = codec::to_key(key).unwrap();
+        let msg = codec::to_value(msg).unwrap();
+
+        self.send_serialized(key, msg)
+    }
+
+    /// Sends a message to every key of the message type `Msg` that currently has subscribers, for
+    /// example to announce something in all chat rooms. `msg_fn` is called with each key so that the
+    /// message can be customized per key.
+    ///
+    /// Keys are untyped on the wire, so every subscribed key that can be deserialized into
+    /// `Msg::Key` is included. Make sure the key type can't be confused with the keys of other
+    /// message types, e.g. by using a struct instead of a plain string.
+    ///
+    /// Returns the number of keys the message was sent to.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use leptos_axum_socket::{ServerSocket, SocketMsg};
+    /// # use serde::{Serialize, Deserialize};
+    /// # use axum::extract::FromRef;
+    /// #
+    /// # #[derive(FromRef, Clone)]
+    /// # pub struct AppState {
+    /// #     pub socket: ServerSocket,
+    /// # }
+    /// #
+    /// #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// pub struct RoomKey {
+    ///     pub room_id: u64,
+    /// }
+    ///
+    /// #[derive(Clone, Serialize, Deserialize, Debug)]
+    /// pub struct RoomMsg(pub String);
+    ///
+    /// impl SocketMsg for RoomMsg {
+    ///     type Key = RoomKey;
+    /// }
+    ///
+    /// async fn announce_maintenance(socket: ServerSocket) {
+    ///     socket.lock().await.broadcast_to_type(|key: &RoomKey| {
+    ///         RoomMsg(format!("Room {} closes in 5 minutes", key.room_id))
+    ///     });
+    /// }
+    /// ```
+    #[instrument(skip(msg_fn))]
+    pub fn broadcast_to_type<Msg>(&mut self, msg_fn: impl Fn(&Msg::Key) -> Msg) -> usize
+    where
+        Msg: SocketMsg + Serialize,
+        for<'de> Msg::Key: Deserialize<'de>,
+    {
+        let keys = self
+            .sender_map
+            .iter()
+            .filter(|(key, sender)| sender.receiver_count() > 0 && type_name_of_key(key).is_none())
+            .filter_map(|(key, _)| {
+                let typed_key = Msg::Key::deserialize(key.as_value()).ok()?;
+                Some((key.clone(), typed_key))
+            })
+            .collect::<Vec<_>>();
+
+        let key_count = keys.len();
+
+        for (key, typed_key) in keys {
+            match codec::to_value(&msg_fn(&typed_key)) {
+                Ok(msg) => {
+                    self.send_serialized(key, msg);
+                }
+                Err(err) => error!("Failed to serialize message: {err}"),
+            }
+        }
+
+        key_count
+    }
+
+    /// Like [`ServerSocketInner::send`] but only sends the message if someone is subscribed to the key.
+    ///
+    /// `send` creates a broadcast sender for the key even if nobody is listening. If you publish to many keys
+    /// speculatively, this avoids allocating senders that nobody reads.
+    /// Messages with stateful keys (see [`ServerSocketInner::set_stateful`]) are always sent so that the
+    /// latest message is retained.
+    ///
+    /// Returns `true` if the message was sent.
+    #[instrument]
+    pub fn send_if_subscribers<Msg>(&mut self, key: &Msg::Key, msg: &Msg) -> bool
+    where
+        Msg: SocketMsg + Serialize + Clone + Send + Sync + Debug + 'static,
+        for<'de> Msg: Deserialize<'de>,
+        Msg::Key: Hash + Eq + Serialize + Clone + Send + Sync + Debug + 'static,
+        for<'de> Msg::Key: Deserialize<'de>,
+    {
+        let key = codec::to_key(key).unwrap();
+
+        if !self.has_subscribers(&key) && !self.is_stateful(&key) {
+            debug!("Skipping message because there are no subscribers");
+            return false;
+        }
+
+        let msg = codec::to_value(msg).unwrap();
+        self.send_serialized(key, msg);
+
+        true
+    }
+
+    /// Broadcast a message from the server to the subscribers of the given key.
+    ///
+    /// This is used to send messages from an axum handler.
+    /// If you want to send from a server function, use the module level [`send_to_self`] function.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use leptos_axum_socket::{ServerSocket, SocketMsg};
+    /// # use serde::{Serialize, Deserialize};
+    /// # use axum::{extract::{State, FromRef}, http::header::HeaderMap};
+    /// #
+    /// # #[derive(FromRef, Clone)]
+    /// # pub struct AppState {
+    /// #     pub socket: ServerSocket,
+    /// # }
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// # struct TheKey;
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, Debug)]
+    /// # struct TheMessage;
+    /// #
+    /// # impl SocketMsg for TheMessage {
+    /// #     type Key = TheKey;
+    /// # }
+    ///
+    /// async fn axum_handler(State(socket): State<ServerSocket>, headers: HeaderMap) {
+    ///     socket.lock().await.send_to_self(&TheKey, &TheMessage, &headers).await;
+    /// }
+    /// ```
+    #[instrument]
+    pub async fn send_to_self<Msg>(&mut self, key: &Msg::Key, msg: &Msg, headers: &HeaderMap)
+    where
+        Msg: SocketMsg + Serialize + Clone + Send + Sync + Debug + 'static,
+        for<'de> Msg: Deserialize<'de>,
+        Msg::Key: Hash + Eq + Serialize + Clone + Send + Sync + Debug + 'static,
+        for<'de> Msg::Key: Deserialize<'de>,
+    {
+        let cookie_header = headers.get(COOKIE).and_then(|header| header.to_str().ok());
+        let client_id_header = headers
+            .get(CLIENT_ID_HEADER)
+            .and_then(|header| header.to_str().ok());
+
+        match read_client_id(cookie_header, client_id_header) {
+            Ok(client_id) => {
+                let key = codec::to_key(key).unwrap();
+                let msg = codec::to_value(msg).unwrap();
+
+                self.deliver_serialized(DeliveryTarget::Client(client_id), key, msg)
+                    .await;
+            }
+            Err(err) => error!("Can't send to self: {}", err),
+        }
+    }
+
+    /// Returns `true` if the client with the given ID is currently connected.
+    ///
+    /// This way you can choose another delivery path, like storing a notification for later, if
+    /// the client is offline.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::ServerSocket;
+    /// # use uuid::Uuid;
+    /// #
+    /// async fn notify(socket: ServerSocket, client_id: Uuid) {
+    ///     if !socket.lock().await.is_client_connected(client_id) {
+    ///         // store the notification for later
+    ///     }
+    /// }
+    /// ```
+    pub fn is_client_connected(&self, client_id: Uuid) -> bool {
+        self.client_to_sender.contains_key(&client_id)
+    }
+
+    /// Returns when the client with the given ID connected, or `None` if it isn't connected.
+    ///
+    /// The connection age is `connected_since.elapsed()`, which can be shown as "online for 2h" or
+    /// used to find stale connections. A client that reconnects starts over.
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use leptos_axum_socket::ServerSocket;
+    /// # use uuid::Uuid;
+    /// #
+    /// async fn online_for(socket: ServerSocket, client_id: Uuid) -> Option<Duration> {
+    ///     socket
+    ///         .lock()
+    ///         .await
+    ///         .connected_since(client_id)
+    ///         .map(|since| since.elapsed())
+    /// }
+    /// ```
+    pub fn connected_since(&self, client_id: Uuid) -> Option<Instant> {
+        self.connected_at
+            .get(&client_id)
+            .filter(|_| self.is_client_connected(client_id))
+            .map(|(_, since)| *since)
+    }
+
+    /// Returns the number of receivers the message was delivered to.
+    pub(crate) fn send_serialized(&mut self, key: CanonicalKey, msg: Value) -> usize {
+        self.send_serialized_from_client(key, msg, None, None)
+    }
+
+    /// Like `send_serialized` for a message that a client sent. Keeps the ID that the client
+    /// attached to the message and stamps it with the sender.
+    #[instrument]
+    pub(crate) fn send_serialized_from_client(
+        &mut self,
+        key: CanonicalKey,
+        msg: Value,
+        id: Option<Uuid>,
+        sender: Option<SenderInfo>,
+    ) -> usize {
+        if self.paused_keys.contains_key(&key) {
+            self.hold_paused(key, msg, id, sender);
+            return 0;
+        }
+
+        self.messages_sent += 1;
+
+        self.record_history(&key, &msg);
+
+        if self.is_stateful(&key) {
+            self.latest_values.insert(key.clone(), msg.clone());
+            self.mark_history(&key, LATEST_HISTORY_ID);
+        }
+
+        let mut forwarded = 0;
+        if self.is_hierarchical(&key) {
+            forwarded += self.send_to_ancestors(&key, &msg);
+        }
+
+        forwarded += self.send_to_type_subscribers(&key, &msg);
+
+        let seq = self.next_seq(&key);
+
+        match self.sender(&key).send(SharedFrame::new(ChannelMsg::Msg {
+            msg,
+            key,
+            id,
+            sender,
+            seq: Some(seq),
+        })) {
+            Ok(receiver_count) => receiver_count,
+            Err(err) => {
+                debug!(
+                    "Failed to send message because there are no receivers: {:?}",
+                    err
+                );
+
+                // Subscribers of ancestors or of the type might have received it anyway
+                if forwarded == 0
+                    && let ChannelMsg::Msg { key, msg, .. } = err.0.msg()
+                {
+                    self.dead_letter(DeadLetterReason::NoReceivers, key, msg);
+                }
+
+                0
+            }
+        }
+    }
+
+    /// Stops delivering the messages of the key without disconnecting its subscribers, for example
+    /// during a data migration. `policy` decides what happens to the messages that are sent in the
+    /// meantime. Call [`ServerSocketInner::resume_key`] to deliver them and continue.
+    ///
+    /// Messages that are dropped are passed to the dead letter handlers with
+    /// [`DeadLetterReason::KeyPaused`]. Pausing a paused key changes its policy and keeps the
+    /// buffered messages. Removing or draining the key resumes it without delivering them.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::{KeyPausePolicy, ServerSocket};
+    /// #
+    /// async fn migrate_room(socket: ServerSocket, room_id: u64) {
+    ///     socket
+    ///         .lock()
+    ///         .await
+    ///         .pause_key(&room_id, KeyPausePolicy::Buffer { max: 100 });
+    ///
+    ///     // migrate the data of the room
+    ///
+    ///     socket.lock().await.resume_key(&room_id);
+    /// }
+    /// ```
+    pub fn pause_key<K>(&mut self, key: &K, policy: KeyPausePolicy)
+    where
+        K: Serialize,
+    {
+        let key = codec::to_key(key).unwrap();
+
+        let paused = self.paused_keys.entry(key.clone()).or_insert(PausedKey {
+            policy,
+            buffered: VecDeque::new(),
+        });
+        paused.policy = policy;
+
+        let dropped = match policy {
+            KeyPausePolicy::Buffer { max } => {
+                let excess = paused.buffered.len().saturating_sub(max);
+                paused.buffered.drain(..excess).collect::<Vec<_>>()
+            }
+            KeyPausePolicy::Drop => paused.buffered.drain(..).collect(),
+        };
+
+        for (msg, ..) in dropped {
+            self.dead_letter(DeadLetterReason::KeyPaused, &key, &msg);
+        }
+    }
+
+    /// Continues to deliver the messages of a key that was paused with
+    /// [`ServerSocketInner::pause_key`]. The buffered messages are sent first in the order they
+    /// were sent.
+    ///
+    /// Returns the number of buffered messages that were sent.
+    pub fn resume_key<K>(&mut self, key: &K) -> usize
+    where
+        K: Serialize,
+    {
+        let key = codec::to_key(key).unwrap();
+
+        let Some(paused) = self.paused_keys.remove(&key) else {
+            return 0;
+        };
+
+        let count = paused.buffered.len();
+        for (msg, id, sender) in paused.buffered {
+            self.send_serialized_from_client(key.clone(), msg, id, sender);
+        }
+
+        count
+    }
+
+    /// Returns `true` if the key is paused. See [`ServerSocketInner::pause_key`].
+    pub fn is_key_paused<K>(&self, key: &K) -> bool
+    where
+        K: Serialize,
+    {
+        let key = codec::to_key(key).unwrap();
+        self.paused_keys.contains_key(&key)
+    }
+
+    /// Keeps a message of a paused key according to the pause policy of the key.
+    fn hold_paused(
+        &mut self,
+        key: CanonicalKey,
+        msg: Value,
+        id: Option<Uuid>,
+        sender: Option<SenderInfo>,
+    ) {
+        let dropped = self
+            .paused_keys
+            .get_mut(&key)
+            .and_then(|paused| paused.hold(msg, id, sender));
+
+        if let Some(msg) = dropped {
+            debug!("Dropped message of paused key {key}");
+            self.dead_letter(DeadLetterReason::KeyPaused, &key, &msg);
+        }
+    }
+
+    /// Returns the sequence number of the next message of `key`. Starts at 1.
+    fn next_seq(&mut self, key: &CanonicalKey) -> u64 {
+        let seq = self.key_seqs.entry(key.clone()).or_default();
+        *seq += 1;
+        *seq
+    }
+
+    /// Delivers a message to the given target. This is what [`ServerSocketInner::send`] and
+    /// [`ServerSocketInner::send_to_self`] do under the hood.
+    ///
+    /// Returns the number of receivers the message was delivered to. For
+    /// [`DeliveryTarget::AllSubscribers`] this is the same as the return value of `send`. For the
+    /// other targets it's the number of clients that are connected.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use leptos_axum_socket::{DeliveryTarget, ServerSocket, SocketMsg};
+    /// # use serde::{Serialize, Deserialize};
+    /// # use axum::extract::FromRef;
+    /// # use uuid::Uuid;
+    /// #
+    /// # #[derive(FromRef, Clone)]
+    /// # pub struct AppState {
+    /// #     pub socket: ServerSocket,
+    /// # }
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// # struct TheKey;
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, Debug)]
+    /// # struct TheMessage;
+    /// #
+    /// # impl SocketMsg for TheMessage {
+    /// #     type Key = TheKey;
+    /// # }
+    /// #
+    /// async fn notify_admins(socket: ServerSocket, admin_ids: Vec<Uuid>) {
+    ///     socket
+    ///         .lock()
+    ///         .await
+    ///         .deliver(DeliveryTarget::Clients(admin_ids), &TheKey, &TheMessage)
+    ///         .await;
+    /// }
+    /// ```
+    #[instrument]
+    pub async fn deliver<Msg>(&mut self, target: DeliveryTarget, key: &Msg::Key, msg: &Msg) -> usize
+    where
+        Msg: SocketMsg + Serialize + Clone + Send + Sync + Debug + 'static,
+        for<'de> Msg: Deserialize<'de>,
+        Msg::Key: Hash + Eq + Serialize + Clone + Send + Sync + Debug + 'static,
+        for<'de> Msg::Key: Deserialize<'de>,
+    {
+        let key = codec::to_key(key).unwrap();
+        let msg = codec::to_value(msg).unwrap();
+
+        self.deliver_serialized(target, key, msg).await
+    }
+
+    pub(crate) async fn deliver_serialized(
+        &mut self,
+        target: DeliveryTarget,
+        key: CanonicalKey,
+        msg: Value,
+    ) -> usize {
+        let client_ids = match target {
+            DeliveryTarget::AllSubscribers => return self.send_serialized(key, msg),
+            DeliveryTarget::Client(client_id) => vec![client_id],
+            DeliveryTarget::Clients(client_ids) => client_ids,
+        };
+
+        self.messages_sent += 1;
+
+        // Shared by all clients so that it's serialized only once
+        let frame = SharedFrame::new(ChannelMsg::Msg {
+            key,
+            msg,
+            id: None,
+            sender: None,
+            seq: None,
+        });
+
+        let mut receiver_count = 0;
+        for client_id in client_ids {
+            if self.deliver_to_client(client_id, Arc::clone(&frame)).await {
+                receiver_count += 1;
+            }
+        }
+
+        receiver_count
+    }
+
+    /// Sends the frame to the client's own channel and waits if it is full. Returns `false` if
+    /// the client isn't connected.
+    #[instrument]
+    async fn deliver_to_client(&self, client_id: Uuid, frame: Arc<SharedFrame>) -> bool {
+        let dead_letter = |frame: &SharedFrame| {
+            if let ChannelMsg::Msg { key, msg, .. } = frame.msg() {
+                self.dead_letter(DeadLetterReason::ClientNotFound, key, msg);
+            }
+        };
+
+        let Some(sender) = self.client_to_sender.get(&client_id) else {
+            error!(
+                "WebSocket transmitter for client ID {} not found",
+                client_id
+            );
+
+            dead_letter(&frame);
+            return false;
+        };
+
+        if let Err(err) = sender.send(frame).await {
+            debug!("Failed to send websocket message: {:?}", err);
+
+            dead_letter(&err.0);
+            return false;
+        }
+
+        true
+    }
+
+    pub(crate) fn insert_client_sender(
+        &mut self,
+        client_id: Uuid,
+        sender: mpsc::Sender<Arc<SharedFrame>>,
+    ) {
+        self.client_to_sender.insert(client_id, sender);
+        self.connected_at
+            .insert(client_id, (SystemTime::now(), Instant::now()));
+        self.bytes_sent.insert(client_id, Default::default());
+    }
+
+    /// Returns the counter of the bytes that are sent to the client, or `None` if it isn't
+    /// connected.
+    pub(crate) fn traffic_counter(&self, client_id: Uuid) -> Option<TrafficCounter> {
+        Some(TrafficCounter {
+            client: Arc::clone(self.bytes_sent.get(&client_id)?),
+            total: Arc::clone(&self.total_bytes_sent),
+        })
+    }
+
+    pub(crate) fn insert_close_sender(
+        &mut self,
+        client_id: Uuid,
+        sender: oneshot::Sender<CloseFrame>,
+    ) {
+        self.close_senders.insert(client_id, sender);
+    }
+
+    /// Closes the connection of the client, for example to kick it. Returns `false` if the client
+    /// isn't connected.
+    ///
+    /// A WebSocket `Close` frame with the given code and reason is sent to the client before the
+    /// connection is cleaned up like any other disconnect. The client can read it with
+    /// `SocketContext::last_close`. It reconnects automatically unless the code is an application
+    /// code from 4000 to 4999 (see [`CloseFrame`]) or it's rejected in the upgrade handler.
+    /// Long-polling clients are not affected.
+    ///
+    /// ```
+    /// # use axum::extract::{Path, State};
+    /// # use leptos_axum_socket::{CloseFrame, ServerSocket};
+    /// # use uuid::Uuid;
+    /// #
+    /// async fn revoke_session(State(socket): State<ServerSocket>, Path(client_id): Path<Uuid>) {
+    ///     socket
+    ///         .lock()
+    ///         .await
+    ///         .close_client(client_id, CloseFrame::new(4001, "unauthorized"));
+    /// }
+    /// ```
+    pub fn close_client(&mut self, client_id: Uuid, frame: CloseFrame) -> bool {
+        self.close_senders
+            .remove(&client_id)
+            .is_some_and(|sender| sender.send(frame).is_ok())
+    }
+
+    pub(crate) fn remove_client_sender(&mut self, client_id: Uuid) {
+        self.client_to_sender.remove(&client_id);
+    }
+
+    fn client_meta(&self, client_id: Uuid) -> ClientMeta {
+        let (raw_bytes_sent, wire_bytes_sent) = self
+            .bytes_sent
+            .get(&client_id)
+            .map(|bytes_sent| bytes_sent.get())
+            .unwrap_or_default();
+
+        ClientMeta {
+            connected_at: self
+                .connected_at
+                .get(&client_id)
+                .map(|(at, _)| *at)
+                .unwrap_or_else(SystemTime::now),
+            keys: self
+                .client_keys
+                .get(&client_id)
+                .cloned()
+                .unwrap_or_default(),
+            compression: false,
+            raw_bytes_sent,
+            wire_bytes_sent,
+        }
+    }
+
+    #[cfg(feature = "longpoll")]
+    pub(crate) fn client_sender(&self, client_id: Uuid) -> Option<mpsc::Sender<Arc<SharedFrame>>> {
+        self.client_to_sender.get(&client_id).cloned()
+    }
+
+    /// Removes everything that belongs to the client. Called when the client disconnects.
+    pub(crate) fn remove_client(&mut self, client_id: Uuid) {
+        if !self.disconnect_handlers.is_empty() {
+            let info = DisconnectInfo {
+                client_id,
+                meta: self.client_meta(client_id),
+            };
+
+            for handler in &self.disconnect_handlers {
+                handler(&info);
+            }
+        }
+
+        self.remove_client_sender(client_id);
+        self.connected_at.remove(&client_id);
+        self.bytes_sent.remove(&client_id);
+        self.close_senders.remove(&client_id);
+
+        for key in self.client_keys.remove(&client_id).unwrap_or_default() {
+            self.subscriber_removed(&key);
+
+            if let Some(handle) = self.handles.remove(&(client_id, key)) {
+                handle.abort();
+            }
+        }
+    }
+
+    /// Counts a client that subscribed to `key` and notifies the subscriber count streams.
+    fn subscriber_added(&mut self, key: &CanonicalKey) {
+        let count = self.subscriber_counts.entry(key.clone()).or_default();
+        *count += 1;
+
+        let count = *count;
+        self.publish_subscriber_count(key, count);
+    }
+
+    /// Counts a client that unsubscribed from `key` and notifies the subscriber count streams.
+    fn subscriber_removed(&mut self, key: &CanonicalKey) {
+        let count = match self.subscriber_counts.get_mut(key) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                *count
+            }
+            _ => {
+                self.subscriber_counts.remove(key);
+                0
+            }
+        };
+
+        self.publish_subscriber_count(key, count);
+    }
+
+    fn publish_subscriber_count(&mut self, key: &CanonicalKey, count: usize) {
+        if let Some(tx) = &self.subscriber_count_tx
+            && tx.send((key.clone(), count)).is_err()
+        {
+            // Every stream was dropped
+            self.subscriber_count_tx = None;
+        }
+    }
+
+    pub(crate) fn subscription_count(&self, client_id: Uuid) -> usize {
+        self.client_keys.get(&client_id).map_or(0, HashSet::len)
+    }
+
+    pub(crate) fn is_subscribed(&self, client_id: Uuid, key: &CanonicalKey) -> bool {
+        self.client_keys
+            .get(&client_id)
+            .is_some_and(|keys| keys.contains(key))
+    }
+
+    pub(crate) fn max_subscriptions_per_client(&self) -> usize {
+        self.max_subscriptions_per_client
+            .unwrap_or(DEFAULT_MAX_SUBSCRIPTIONS_PER_CLIENT)
+    }
+
+    /// Remembers the ID that a client attached to a message. Returns `false` if this client sent a
+    /// message with this ID before, for example because it sent it again after reconnecting.
+    pub(crate) fn remember_msg_id(&mut self, client_id: Uuid, id: Uuid) -> bool {
+        if !self.recent_msg_ids.insert((client_id, id)) {
+            return false;
+        }
+
+        self.recent_msg_id_order.push_back((client_id, id));
+        if self.recent_msg_id_order.len() > RECENT_MSG_IDS
+            && let Some(oldest) = self.recent_msg_id_order.pop_front()
+        {
+            self.recent_msg_ids.remove(&oldest);
+        }
+
+        true
+    }
+
+    /// Sends a frame to a single client without waiting. The frame is dropped if the client can't
+    /// keep up.
+    pub(crate) fn send_serialized_to_client(&self, client_id: Uuid, msg: ChannelMsg) {
+        if let Some(sender) = self.client_to_sender.get(&client_id)
+            && let Err(err) = sender.try_send(SharedFrame::new(msg))
+        {
+            debug!("Failed to send websocket message: {:?}", err);
+        }
+    }
+
+    #[instrument]
+    pub(crate) fn subscribe(&mut self, key: CanonicalKey) -> Receiver<Arc<SharedFrame>> {
+        let receiver = self.sender(&key).subscribe();
+        self.subscribe_notify.notify_waiters();
+
+        receiver
+    }
+
+    fn has_subscribers(&self, key: &CanonicalKey) -> bool {
+        self.sender_map
+            .get(key)
+            .is_some_and(|sender| sender.receiver_count() > 0)
+    }
+
+    /// Returns the latest message that was sent with the given key if the key is stateful or the
+    /// retained message of the key.
+    pub(crate) fn latest_msg(&self, key: &CanonicalKey) -> Option<&Value> {
+        self.latest_values
+            .get(key)
+            .or_else(|| self.retained_values.get(key))
+    }
+
+    /// Appends the message to the history of the key and drops the oldest messages that exceed its
+    /// length.
+    fn record_history(&mut self, key: &CanonicalKey, msg: &Value) {
+        let Some(len) = self.history_len(key) else {
+            return;
+        };
+
+        let history = self.histories.entry(key.clone()).or_default();
+        history.push_back((Uuid::new_v4(), msg.clone()));
+        while history.len() > len {
+            history.pop_front();
+        }
+    }
+
+    fn history_len(&self, key: &CanonicalKey) -> Option<usize> {
+        self.history_lens
+            .iter()
+            .find_map(|(is_match, len)| is_match(key).then_some(*len))
+    }
+
+    /// Gives the history entry of the message that was just sent with the key the `id` of the
+    /// pinned message or [`LATEST_HISTORY_ID`], so that new subscribers receive it only once.
+    /// Messages of a paused key are recorded when they are delivered, so nothing is marked.
+    fn mark_history(&mut self, key: &CanonicalKey, id: Uuid) {
+        if self.paused_keys.contains_key(key) || self.history_len(key).is_none() {
+            return;
+        }
+
+        let Some(history) = self.histories.get_mut(key) else {
+            return;
+        };
+
+        if id == LATEST_HISTORY_ID
+            && let Some(previous) = history
+                .iter_mut()
+                .rev()
+                .find(|(entry_id, _)| *entry_id == LATEST_HISTORY_ID)
+        {
+            previous.0 = Uuid::new_v4();
+        }
+
+        if let Some(last) = history.back_mut() {
+            last.0 = id;
+        }
+    }
+
+    fn is_stateful(&self, key: &Value) -> bool {
+        self.stateful_keys.iter().any(|is_match| is_match(key))
+    }
+
+    fn is_hierarchical(&self, key: &Value) -> bool {
+        self.hierarchical_keys.iter().any(|is_match| is_match(key))
+    }
+
+    /// Delivers the message to the subscribers of every ancestor path of the key.
+    /// Only ancestors that already have a sender are considered, so no senders are created.
+    /// Returns the number of receivers.
+    fn send_to_ancestors(&self, path: &Value, msg: &Value) -> usize {
+        let Some(segments) = path.as_array() else {
+            return 0;
+        };
+
+        let mut receiver_count = 0;
+        for len in 1..segments.len() {
+            let key = CanonicalKey::new(Value::Array(segments[..len].to_vec()));
+
+            if let Some(sender) = self.sender_map.get(&key) {
+                receiver_count += sender
+                    .send(SharedFrame::new(ChannelMsg::PathMsg {
+                        key,
+                        path: path.clone(),
+                        msg: msg.clone(),
+                    }))
+                    .unwrap_or_default();
+            }
+        }
+
+        receiver_count
+    }
+
+    /// Delivers the message to the clients that subscribed to every message of its type. Returns
+    /// the number of receivers.
+    fn send_to_type_subscribers(&self, key: &CanonicalKey, msg: &Value) -> usize {
+        let mut receiver_count = 0;
+        for (type_name, is_match) in &self.registered_types {
+            let type_key = type_key(type_name);
+
+            if let Some(sender) = self.sender_map.get(&type_key)
+                && sender.receiver_count() > 0
+                && is_match(key, msg)
+            {
+                receiver_count += sender
+                    .send(SharedFrame::new(ChannelMsg::PathMsg {
+                        key: type_key,
+                        path: key.as_value().clone(),
+                        msg: msg.clone(),
+                    }))
+                    .unwrap_or_default();
+            }
+        }
+
+        receiver_count
+    }
+
+    pub(crate) fn remember_handle(
+        &mut self,
+        client_id: Uuid,
+        key: CanonicalKey,
+        handle: JoinHandle<()>,
+    ) {
+        if self
+            .client_keys
+            .entry(client_id)
+            .or_default()
+            .insert(key.clone())
+        {
+            self.subscriber_added(&key);
+        }
+
+        if let Some(old_handle) = self.handles.insert((client_id, key), handle) {
+            old_handle.abort();
+        }
+    }
+
+    pub(crate) fn unsubscribe(&mut self, client_id: Uuid, key: CanonicalKey) {
+        if let Some(keys) = self.client_keys.get_mut(&client_id)
+            && keys.remove(&key)
+        {
+            self.subscriber_removed(&key);
+        }
+
+        if let Some(handle) = self.handles.remove(&(client_id, key)) {
+            handle.abort();
+        }
+    }
+
+    /// Evicts all subscribers of the given key, for example when a chat room is deleted.
+    ///
+    /// This stops forwarding messages of the key to any client, removes the key's broadcast sender
+    /// and retained state and notifies every affected client that the key has been closed.
+    /// On the client this can be handled with `SocketContext::on_key_closed`.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use leptos_axum_socket::ServerSocket;
+    /// # use serde::{Serialize, Deserialize};
+    /// # use axum::extract::State;
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// # struct RoomKey { room_id: u32 }
+    /// #
+    /// async fn delete_room(State(socket): State<ServerSocket>) {
+    ///     socket.lock().await.drain_key(&RoomKey { room_id: 42 }).await;
+    /// }
+    /// ```
+    #[instrument(skip(key))]
+    pub async fn drain_key<K>(&mut self, key: &K)
+    where
+        K: Serialize,
+    {
+        let key = codec::to_key(key).unwrap();
+
+        let closed = SharedFrame::new(ChannelMsg::KeyClosed { key: key.clone() });
+
+        for client_id in self.remove_key_serialized(&key) {
+            if let Some(sender) = self.client_to_sender.get(&client_id)
+                && let Err(err) = sender.send(Arc::clone(&closed)).await
+            {
+                debug!("Failed to send websocket message: {:?}", err);
+            }
+        }
+    }
+
+    /// Returns `true` if the key currently has a broadcast sender, i.e. something was sent with it
+    /// or someone subscribed to it since it was last removed.
+    pub fn key_exists<K>(&self, key: &K) -> bool
+    where
+        K: Serialize,
+    {
+        let key = codec::to_key(key).unwrap();
+        self.sender_map.contains_key(&key)
+    }
+
+    /// Removes the key's broadcast sender and evicts all of its subscribers without notifying the
+    /// clients. Their forwarding tasks are aborted and the key's latest and retained messages are
+    /// dropped.
+    ///
+    /// This is the raw teardown that [`ServerSocketInner::drain_key`] builds upon, meant for garbage
+    /// collection or tests. Clients still think they're subscribed, so prefer `drain_key` if they
+    /// should know about it.
+    ///
+    /// Returns the IDs of the clients that were subscribed.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use leptos_axum_socket::ServerSocket;
+    /// # use serde::{Serialize, Deserialize};
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// # struct RoomKey { room_id: u32 }
+    /// #
+    /// async fn collect_room(socket: ServerSocket, key: RoomKey) {
+    ///     let mut socket = socket.lock().await;
+    ///
+    ///     if socket.key_exists(&key) {
+    ///         let evicted = socket.remove_key(&key);
+    ///         println!("Evicted {} clients", evicted.len());
+    ///     }
+    /// }
+    /// ```
+    #[instrument(skip(key))]
+    pub fn remove_key<K>(&mut self, key: &K) -> Vec<Uuid>
+    where
+        K: Serialize,
+    {
+        let key = codec::to_key(key).unwrap();
+        self.remove_key_serialized(&key)
+    }
+
+    /// Moves all subscribers of the key `old` to the key `new`, for example when a chat room is
+    /// renamed or merged into another one.
+    ///
+    /// The subscribers receive all messages that are sent with `new` from now on without having to
+    /// subscribe again, so no message is lost during the migration. Every affected client is notified
+    /// and updates its subscriptions, i.e. the handlers that were subscribed to `old` are called with
+    /// the messages of `new`. Clients that are subscribed to both keys receive every message only once.
+    ///
+    /// The latest or retained message of `old` is moved to `new` unless `new` already has one.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use leptos_axum_socket::ServerSocket;
+    /// # use serde::{Serialize, Deserialize};
+    /// # use axum::extract::State;
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// # struct RoomKey { room_id: u32 }
+    /// #
+    /// async fn merge_rooms(State(socket): State<ServerSocket>) {
+    ///     socket
+    ///         .lock()
+    ///         .await
+    ///         .remap_key(&RoomKey { room_id: 42 }, &RoomKey { room_id: 1 })
+    ///         .await;
+    /// }
+    /// ```
+    #[instrument(skip(old, new))]
+    pub async fn remap_key<K>(&mut self, old: &K, new: &K)
+    where
+        K: Serialize,
+    {
+        let old = codec::to_key(old).unwrap();
+        let new = codec::to_key(new).unwrap();
+
+        if old == new {
+            return;
+        }
+
+        if let Some(msg) = self.latest_values.remove(&old) {
+            self.latest_values.entry(new.clone()).or_insert(msg);
+        }
+        if let Some(msg) = self.retained_values.remove(&old) {
+            self.retained_values.entry(new.clone()).or_insert(msg);
+        }
+        if let Some(history) = self.histories.remove(&old) {
+            self.histories.entry(new.clone()).or_insert(history);
+        }
+        if let Some(pinned) = self.pinned_msgs.remove(&old) {
+            self.pinned_msgs.entry(new.clone()).or_insert(pinned);
+        }
+
+        self.key_seqs.remove(&old);
+
+        let Some(old_sender) = self.sender_map.remove(&old) else {
+            return;
+        };
+
+        let remapped = SharedFrame::new(ChannelMsg::KeyRemapped {
+            old: old.clone(),
+            new: new.clone(),
+        });
+
+        let mut subscribed_to_both = vec![];
+        let mut moved_count = 0;
+
+        for (client_id, keys) in &mut self.client_keys {
+            if !keys.remove(&old) {
+                continue;
+            }
+
+            let handle = self.handles.remove(&(*client_id, old.clone()));
+
+            if keys.insert(new.clone()) {
+                moved_count += 1;
+
+                if let Some(handle) = handle {
+                    self.handles.insert((*client_id, new.clone()), handle);
+                }
+            } else {
+                // Already receives the messages of the new key
+                if let Some(handle) = handle {
+                    handle.abort();
+                }
+                subscribed_to_both.push(*client_id);
+            }
+        }
+
+        // Sent through the broadcast channel so that it arrives before any message of the new key.
+        // The clients that are subscribed to both keys no longer receive from it, they are notified
+        // directly below.
+        let _ = old_sender.send(Arc::clone(&remapped));
+
+        if let Some(new_sender) = self.sender_map.get(&new) {
+            // The forwarding tasks of the subscribers keep receiving from the old sender so relay the
+            // messages of the new key to it until nobody is listening anymore.
+            let mut new_rx = new_sender.subscribe();
+
+            self.spawner.spawn(async move {
+                loop {
+                    match new_rx.recv().await {
+                        Ok(frame) => {
+                            if old_sender.send(frame).is_err() {
+                                return;
+                            }
+                        }
+                        Err(RecvError::Lagged(count)) => {
+                            warn!("Remapped key lagged behind. Skipped {count} messages");
+                        }
+                        Err(RecvError::Closed) => return,
+                    }
+                }
+            });
+        } else {
+            self.sender_map.insert(new.clone(), old_sender);
+        }
+
+        if self.subscriber_counts.remove(&old).is_some() {
+            self.publish_subscriber_count(&old, 0);
+        }
+        if moved_count > 0 {
+            let count = self.subscriber_counts.entry(new.clone()).or_default();
+            *count += moved_count;
+
+            let count = *count;
+            self.publish_subscriber_count(&new, count);
+        }
+
+        for client_id in subscribed_to_both {
+            if let Some(sender) = self.client_to_sender.get(&client_id)
+                && let Err(err) = sender.send(Arc::clone(&remapped)).await
+            {
+                debug!("Failed to send websocket message: {:?}", err);
+            }
+        }
+
+        self.subscribe_notify.notify_waiters();
+    }
+
+    /// Removes the key's sender and subscriptions. Returns the clients that were subscribed.
+    fn remove_key_serialized(&mut self, key: &CanonicalKey) -> Vec<Uuid> {
+        let mut client_ids = vec![];
+
+        for (client_id, keys) in &mut self.client_keys {
+            if keys.remove(key) {
+                client_ids.push(*client_id);
+            }
+        }
+
+        for client_id in &client_ids {
+            if let Some(handle) = self.handles.remove(&(*client_id, key.clone())) {
+                handle.abort();
+            }
+        }
+
+        if self.subscriber_counts.remove(key).is_some() {
+            self.publish_subscriber_count(key, 0);
+        }
+
+        self.sender_map.remove(key);
+        self.latest_values.remove(key);
+        self.retained_values.remove(key);
+        self.histories.remove(key);
+        self.pinned_msgs.remove(key);
+        self.key_seqs.remove(key);
+        self.paused_keys.remove(key);
+
+        client_ids
+    }
+
+    #[cfg(feature = "longpoll")]
+    pub(crate) fn insert_longpoll_session(
+        &mut self,
+        client_id: Uuid,
+        session: crate::handlers::longpoll::LongPollSession,
+    ) {
+        self.longpoll_sessions.insert(client_id, session);
+    }
+
+    #[cfg(feature = "longpoll")]
+    pub(crate) fn longpoll_session_mut(
+        &mut self,
+        client_id: Uuid,
+    ) -> Option<&mut crate::handlers::longpoll::LongPollSession> {
+        self.longpoll_sessions.get_mut(&client_id)
+    }
+
+    #[cfg(feature = "longpoll")]
+    pub(crate) fn remove_longpoll_session(&mut self, client_id: Uuid) {
+        self.longpoll_sessions.remove(&client_id);
+        self.remove_client(client_id);
+    }
+
+    /// Marks all keys of type `K` as stateful.
+    ///
+    /// Stateful keys represent a current value (a live score, a toggle) rather than a stream of events.
+    /// The server retains only the latest message that was sent with such a key and delivers it
+    /// immediately to every new subscriber. After that the subscriber receives updates as usual.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::ServerSocket;
+    /// # use serde::{Serialize, Deserialize};
+    /// #
+    /// #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// pub struct ScoreKey {
+    ///     pub game_id: u32,
+    /// }
+    ///
+    /// # async fn init(socket: ServerSocket) {
+    /// socket.lock().await.set_stateful::<ScoreKey>();
+    /// # }
+    /// ```
+    pub fn set_stateful<K>(&mut self)
+    where
+        for<'de> K: Deserialize<'de>,
+    {
+        self.stateful_keys
+            .push(Arc::new(|key: &Value| K::deserialize(key).is_ok()));
+    }
+
+    /// Keeps the last `len` messages of every key of type `K`, for example for moderation or audits.
+    /// They can be read with [`ServerSocketInner::history`] and removed with
+    /// [`ServerSocketInner::flush_history`]. Clients that subscribe to the key receive the history
+    /// before any live message.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::ServerSocket;
+    /// # use serde::{Serialize, Deserialize};
+    /// #
+    /// #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// pub struct RoomKey(pub u32);
+    ///
+    /// # async fn init(socket: ServerSocket) {
+    /// socket.lock().await.set_history_len::<RoomKey>(100);
+    /// # }
+    /// ```
+    pub fn set_history_len<K>(&mut self, len: usize)
+    where
+        for<'de> K: Deserialize<'de>,
+    {
+        self.history_lens
+            .push((Arc::new(|key: &Value| K::deserialize(key).is_ok()), len));
+    }
+
+    /// Marks all keys of type `K` as hierarchical. `K` has to serialize to a list of path segments like
+    /// `Vec<String>`.
+    ///
+    /// A message that is sent with a hierarchical key is also delivered to the subscribers of all
+    /// ancestor paths. For example, a subscriber of `["org", "1"]` receives messages that are sent with
+    /// `["org", "1", "team", "2"]`. On the client you can use `SocketContext::subscribe_path` to find out
+    /// the full path a message was sent with.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::ServerSocket;
+    /// # use serde::{Serialize, Deserialize};
+    /// #
+    /// #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// pub struct OrgPath(pub Vec<String>);
+    ///
+    /// # async fn init(socket: ServerSocket) {
+    /// socket.lock().await.set_hierarchical::<OrgPath>();
+    /// # }
+    /// ```
+    pub fn set_hierarchical<K>(&mut self)
+    where
+        for<'de> K: Deserialize<'de>,
     {
-        let key = serde_json::to_value(key).unwrap();
-        let msg = serde_json::to_value(msg).unwrap();
-
-        self.send_serialized(key, msg);
+        self.hierarchical_keys.push(Arc::new(|key: &Value| {
+            key.as_array()
+                .is_some_and(|segments| segments.iter().all(Value::is_string))
+                && K::deserialize(key).is_ok()
+        }));
     }
 
-    /// Broadcast a message from the server to the subscribers of the given key.
+    /// Allows clients to subscribe to every message of type `Msg` regardless of the key with
+    /// `SocketContext::subscribe_type`. Type subscriptions are denied for types that are not registered.
     ///
-    /// This is used to send messages from an axum handler.
-    /// If you want to send from a server function, use the module level [`send_to_self`] function.
+    /// The type is identified by `name` on the wire. The client has to subscribe with the same name,
+    /// so pick one that doesn't change when the type is renamed or moved.
     ///
-    /// ## Example
+    /// Subscribe filters are only called with the concrete keys of normal subscriptions. A client that
+    /// subscribes to a type receives the messages of all keys, so only register types that every
+    /// client is allowed to see.
     ///
     /// ```
     /// # use leptos_axum_socket::{ServerSocket, SocketMsg};
     /// # use serde::{Serialize, Deserialize};
-    /// # use axum::{extract::{State, FromRef}, http::header::HeaderMap};
+    /// # use axum::extract::FromRef;
     /// #
     /// # #[derive(FromRef, Clone)]
     /// # pub struct AppState {
     /// #     pub socket: ServerSocket,
     /// # }
     /// #
-    /// # #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
-    /// # struct TheKey;
-    /// #
-    /// # #[derive(Clone, Serialize, Deserialize, Debug)]
-    /// # struct TheMessage;
-    /// #
-    /// # impl SocketMsg for TheMessage {
-    /// #     type Key = TheKey;
-    /// #     #[cfg(feature = "ssr")]
-    /// #     type AppState = AppState;
-    /// # }
+    /// #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// pub struct RoomKey(pub u64);
     ///
-    /// async fn axum_handler(State(socket): State<ServerSocket>, headers: HeaderMap) {
-    ///     socket.lock().await.send_to_self(&TheKey, &TheMessage, &headers).await;
+    /// #[derive(Clone, Serialize, Deserialize, Debug)]
+    /// pub struct RoomMsg(pub String);
+    ///
+    /// impl SocketMsg for RoomMsg {
+    ///     type Key = RoomKey;
     /// }
+    ///
+    /// # async fn init(socket: ServerSocket) {
+    /// socket.lock().await.register_type::<RoomMsg>("room_msg");
+    /// # }
     /// ```
-    #[instrument]
-    pub async fn send_to_self<Msg>(&mut self, key: &Msg::Key, msg: &Msg, headers: &HeaderMap)
+    pub fn register_type<Msg>(&mut self, name: &str)
     where
-        Msg: SocketMsg + Serialize + Clone + Send + Sync + Debug + 'static,
+        Msg: SocketMsg + 'static,
         for<'de> Msg: Deserialize<'de>,
-        Msg::Key: Hash + Eq + Serialize + Clone + Send + Sync + Debug + 'static,
         for<'de> Msg::Key: Deserialize<'de>,
     {
-        if let Some(cookie_header) = headers.get(COOKIE) {
-            match cookie_header.to_str() {
-                Ok(cookie_header) => match read_client_id_from_cookie_header(cookie_header) {
-                    Ok(client_id) => {
-                        let key = serde_json::to_value(key).unwrap();
-                        let msg = serde_json::to_value(msg).unwrap();
-
-                        self.send_serialized_to_self(client_id, key, msg).await;
-                    }
-                    Err(err) => error!("Failed to parse Uuid from cookie: {:?}", err),
-                },
-                Err(err) => {
-                    error!("Failed to parse cookie header: {:?}", err);
-                }
-            }
-        } else {
-            error!("Cookie header not found. Can't send to self");
-        }
-    }
-
-    #[instrument]
-    pub(crate) fn send_serialized(&mut self, key: Value, msg: Value) {
-        if let Err(err) = self.sender(key.clone()).send(ChannelMsg::Msg { msg, key }) {
-            debug!(
-                "Failed to send message because there are no receivers: {:?}",
-                err
-            );
-        }
+        self.registered_types.insert(
+            name.to_string(),
+            Arc::new(|key: &Value, msg: &Value| {
+                Msg::Key::deserialize(key).is_ok() && Msg::deserialize(msg).is_ok()
+            }),
+        );
     }
 
-    #[instrument]
-    pub(crate) async fn send_serialized_to_self(&self, client_id: Uuid, key: Value, msg: Value) {
-        if let Some(sender) = self.client_to_sender.get(&client_id) {
-            if let Err(err) = sender.send(ChannelMsg::Msg { key, msg }).await {
-                debug!("Failed to send websocket message: {:?}", err);
-            }
-        } else {
-            error!(
-                "WebSocket transmitter for client ID {} not found",
-                client_id
-            );
-        }
+    /// Registers the JSON schemas of `Msg` and its key. They are returned by
+    /// [`ServerSocket::export_schemas`] to document the protocol or to generate clients in other
+    /// languages. Requires the `json-schema` feature.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::{ServerSocket, SocketMsg};
+    /// # use schemars::JsonSchema;
+    /// # use serde::{Serialize, Deserialize};
+    /// #
+    /// #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug, JsonSchema)]
+    /// pub struct RoomKey(pub u64);
+    ///
+    /// #[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+    /// pub struct RoomMsg(pub String);
+    ///
+    /// impl SocketMsg for RoomMsg {
+    ///     type Key = RoomKey;
+    /// }
+    ///
+    /// # async fn init(socket: ServerSocket) {
+    /// socket.lock().await.register_schema::<RoomMsg>();
+    /// # }
+    /// ```
+    #[cfg(feature = "json-schema")]
+    pub fn register_schema<Msg>(&mut self)
+    where
+        Msg: SocketMsg + schemars::JsonSchema,
+        Msg::Key: schemars::JsonSchema,
+    {
+        self.schemas.insert(
+            Msg::schema_name().into_owned(),
+            serde_json::json!({
+                "key": schemars::schema_for!(Msg::Key),
+                "msg": schemars::schema_for!(Msg),
+            }),
+        );
     }
 
-    pub(crate) fn insert_client_sender(
-        &mut self,
-        client_id: Uuid,
-        sender: mpsc::Sender<ChannelMsg>,
-    ) {
-        self.client_to_sender.insert(client_id, sender);
+    /// Sets how many clients can be connected at the same time. This protects a small server from
+    /// running out of resources. Further WebSocket upgrades and long-polling sessions are rejected
+    /// with `503 Service Unavailable` and no client ID cookie, so the clients retry with their
+    /// reconnect backoff. Clients that are already connected aren't affected.
+    ///
+    /// Unlimited by default.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::ServerSocket;
+    /// #
+    /// let socket = ServerSocket::builder().max_connections(10_000).build();
+    /// ```
+    pub fn set_max_connections(&mut self, max: usize) {
+        self.connection_slots.max.store(max, Ordering::SeqCst);
     }
 
-    pub(crate) fn remove_client_sender(&mut self, client_id: Uuid) {
-        self.client_to_sender.remove(&client_id);
+    /// Sets how many keys a single client can be subscribed to at the same time. Further subscriptions
+    /// are ignored and answered with a `SubscribeDenied` frame. This protects the server from clients
+    /// that spawn lots of tasks by subscribing to many keys.
+    ///
+    /// Defaults to [`DEFAULT_MAX_SUBSCRIPTIONS_PER_CLIENT`].
+    pub fn set_max_subscriptions_per_client(&mut self, max: usize) {
+        self.max_subscriptions_per_client = Some(max);
     }
 
-    #[instrument]
-    pub(crate) fn subscribe(&mut self, key: Value) -> Receiver<ChannelMsg> {
-        self.sender(key).subscribe()
+    /// Sets how many messages of a key are buffered for subscribers that can't keep up. Subscribers
+    /// that fall further behind skip the oldest messages, which `SocketContext::on_gap` reports on
+    /// the client. Only applies to keys that are created afterwards.
+    ///
+    /// Defaults to [`DEFAULT_CHANNEL_CAPACITY`].
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is 0.
+    pub fn set_channel_capacity(&mut self, capacity: usize) {
+        assert!(capacity > 0, "The channel capacity must be at least 1");
+        self.channel_capacity = Some(capacity);
     }
 
-    pub(crate) fn remember_handle(&mut self, client_id: Uuid, key: Value, handle: JoinHandle<()>) {
-        self.handles.insert((client_id, key), handle);
+    /// Add a handler that is called for every message that couldn't be delivered. This gives visibility
+    /// into message loss and can feed a persistence or retry layer.
+    ///
+    /// The handler is called with the reason, the key and the message. It is called while the socket
+    /// is locked so don't lock it again inside the handler.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::ServerSocket;
+    /// #
+    /// # async fn init(socket: ServerSocket) {
+    /// socket.lock().await.on_dead_letter(|reason, key, msg| {
+    ///     tracing::warn!("Message {msg} with key {key} was not delivered: {reason:?}");
+    /// });
+    /// # }
+    /// ```
+    pub fn on_dead_letter<F>(&mut self, handler: F)
+    where
+        F: Fn(DeadLetterReason, &Value, &Value) + Send + Sync + 'static,
+    {
+        self.dead_letters.push(Arc::new(handler));
     }
 
-    pub(crate) fn unsubscribe(&mut self, client_id: Uuid, key: Value) {
-        if let Some(handle) = self.handles.remove(&(client_id, key)) {
-            handle.abort();
-        }
+    /// Add a handler that is called whenever a client disconnects, for example to mark the user as
+    /// offline or to release the locks it held. Clients that are closed with
+    /// [`ServerSocketInner::close_client`] or time out are included.
+    ///
+    /// The handler is called while the socket is locked so don't lock it again inside the handler.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::ServerSocket;
+    /// #
+    /// # async fn init(socket: ServerSocket) {
+    /// socket.lock().await.on_disconnect(|info| {
+    ///     tracing::info!(
+    ///         "Client {} left after being subscribed to {} keys",
+    ///         info.client_id,
+    ///         info.meta.keys.len(),
+    ///     );
+    /// });
+    /// # }
+    /// ```
+    pub fn on_disconnect<F>(&mut self, handler: F)
+    where
+        F: Fn(&DisconnectInfo) + Send + Sync + 'static,
+    {
+        self.disconnect_handlers.push(Arc::new(handler));
     }
 
     /// Add a subscribe filter to the server. Whenever someone wants to subscribe ,
@@ -237,6 +3084,12 @@ impl ServerSocketInner {
     /// It can then return `true` to allow the subscription or `false` to deny it.
     /// If multiple filters are found for a given key,
     /// the subscription will only be allowed if all filters return `true`.
+    ///
+    /// If the context of the connection is a [`ContextMap`](crate::ContextMap), `C` can be the type
+    /// of any of its values. The same applies to send mappers and [`ServerSocketInner::stamp_sender_with`].
+    ///
+    /// The filter is added to the middleware chain, see [`SocketMiddleware`]. It drops the
+    /// `Subscribe` frames of denied keys and removes them from `SubscribeMany` frames.
     pub fn add_subscribe_filter<K, C, F, Fut>(&mut self, filter: F)
     where
         K: Send + Sync,
@@ -245,9 +3098,9 @@ impl ServerSocketInner {
         Fut: Future<Output = bool> + Send,
         C: Clone + Send + Sync + 'static,
     {
-        self.subscribe_filters
-            .push(Arc::new(move |key: Value, ctx: &dyn Any| {
-                let ctx: &C = ctx.downcast_ref().expect("Invalid context type");
+        self.middlewares.push(SubscribeFilterLayer(Arc::new(
+            move |key: Value, ctx: &dyn Any| {
+                let ctx: &C = context_of(ctx);
                 let ctx = ctx.clone();
 
                 let filter = filter.clone();
@@ -261,7 +3114,8 @@ impl ServerSocketInner {
                         }
                     }
                 })
-            }));
+            },
+        )));
     }
 
     /// Add a send mapper to the server. Whenever someone wants to send a message,
@@ -269,9 +3123,10 @@ impl ServerSocketInner {
     /// It can then return `Some(message)` to allow the message to be sent or `None` to deny it.
     /// It can also modify the message before sending it.
     ///
-    /// Make sure you only add one mapper per message type (the message type also specifies the key type).
-    /// If you add multiple mappers for the same message type,
-    /// the first one added will be used and all subsequent ones will be ignored.
+    /// The mapper is added to the middleware chain, see [`SocketMiddleware`]. If you add multiple
+    /// mappers for the same message type (the message type also specifies the key type), each one
+    /// receives the message that the previous one returned. Messages that are dropped are passed
+    /// to the [`ServerSocketInner::on_dead_letter`] handlers.
     pub fn add_send_mapper<M, C, F>(&mut self, mapper: F)
     where
         M: SocketMsg + Serialize,
@@ -280,41 +3135,371 @@ impl ServerSocketInner {
         F: Fn(M::Key, M, &C) -> Option<M> + Send + Sync + 'static,
         C: 'static,
     {
-        self.send_mappers
-            .push(Arc::new(move |key: Value, msg: Value, ctx: &dyn Any| {
+        self.middlewares.push(SendMapperLayer {
+            mapper: Arc::new(move |key: Value, msg: Value, ctx: &dyn Any| {
                 let key: M::Key = serde_json::from_value(key)?;
                 let msg: M = serde_json::from_value(msg)?;
 
-                let ctx: &C = ctx.downcast_ref().expect("Invalid context type");
+                let ctx: &C = context_of(ctx);
 
-                mapper(key, msg, ctx).map(serde_json::to_value).transpose()
-            }));
+                mapper(key, msg, ctx)
+                    .map(|msg| codec::to_value(&msg))
+                    .transpose()
+            }),
+            dead_letters: self.dead_letters.clone(),
+        });
+    }
+
+    /// Like [`ServerSocketInner::add_send_mapper`] but the mapper is async, for example to call an
+    /// external moderation API or to look something up in the database.
+    ///
+    /// The socket is not locked while the mapper runs. Like the sync send mappers it's part of the
+    /// middleware chain and called in the order it was added. Only messages that clients send are
+    /// mapped.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::{ServerSocket, SocketMsg};
+    /// # use serde::{Serialize, Deserialize};
+    /// # use axum::extract::FromRef;
+    /// #
+    /// # #[derive(FromRef, Clone)]
+    /// # pub struct AppState {
+    /// #     pub socket: ServerSocket,
+    /// # }
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// # struct ChatKey { room_id: u32 }
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, Debug)]
+    /// # struct ChatMsg { text: String }
+    /// #
+    /// # impl SocketMsg for ChatMsg {
+    /// #     type Key = ChatKey;
+    /// # }
+    /// #
+    /// # async fn is_allowed(text: &str) -> bool { true }
+    /// #
+    /// async fn setup(socket: ServerSocket) {
+    ///     socket
+    ///         .lock()
+    ///         .await
+    ///         .add_async_send_mapper(async |_key: ChatKey, msg: ChatMsg, _ctx: ()| {
+    ///             is_allowed(&msg.text).await.then_some(msg)
+    ///         });
+    /// }
+    /// ```
+    pub fn add_async_send_mapper<M, C, F, Fut>(&mut self, mapper: F)
+    where
+        M: SocketMsg + Serialize,
+        for<'de> M: Deserialize<'de>,
+        for<'de> M::Key: Deserialize<'de>,
+        F: Fn(M::Key, M, C) -> Fut + Clone + Send + Sync + 'static,
+        Fut: Future<Output = Option<M>> + Send + 'static,
+        C: Clone + Send + Sync + 'static,
+    {
+        self.middlewares.push(AsyncSendMapperLayer {
+            mapper: Arc::new(move |key: Value, msg: Value, ctx: &dyn Any| {
+                let key: M::Key = serde_json::from_value(key)?;
+                let msg: M = serde_json::from_value(msg)?;
+
+                let ctx: &C = context_of(ctx);
+                let mapped = mapper(key, msg, ctx.clone());
+
+                Ok(Box::pin(async move {
+                    codec::to_value(&mapped.await?)
+                        .map_err(|err| error!("Failed to serialize mapped message: {err}"))
+                        .ok()
+                }))
+            }),
+            dead_letters: self.dead_letters.clone(),
+        });
     }
 
-    pub(crate) async fn can_subscribe<C>(&self, key: Value, ctx: &C) -> bool
+    /// Add a validator for the messages of type `M` that clients send. Messages whose key is an `M::Key`
+    /// but that can't be deserialized into `M` or for which the validator returns an error are dropped.
+    /// The client that sent the message receives the reason.
+    ///
+    /// Unlike send mappers, validators can't change the message. They are about the integrity of the data,
+    /// for example when old clients send messages that newer clients can't handle. Validators are called
+    /// after the middleware chain, so they see the messages as the send mappers returned them. Messages
+    /// sent from the server are not validated.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::{ServerSocket, SocketMsg};
+    /// # use serde::{Serialize, Deserialize};
+    /// # use axum::extract::FromRef;
+    /// #
+    /// # #[derive(FromRef, Clone)]
+    /// # pub struct AppState {
+    /// #     pub socket: ServerSocket,
+    /// # }
+    /// #
+    /// # #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// # struct ChatKey { room_id: u32 }
+    /// #
+    /// #[derive(Clone, Serialize, Deserialize, Debug)]
+    /// struct ChatMsg {
+    ///     text: String,
+    /// }
+    /// #
+    /// # impl SocketMsg for ChatMsg {
+    /// #     type Key = ChatKey;
+    /// # }
+    ///
+    /// async fn setup(socket: ServerSocket) {
+    ///     socket.lock().await.add_validator(|msg: &ChatMsg| {
+    ///         if msg.text.len() > 1000 {
+    ///             Err("Message too long".to_string())
+    ///         } else {
+    ///             Ok(())
+    ///         }
+    ///     });
+    /// }
+    /// ```
+    pub fn add_validator<M, F>(&mut self, validator: F)
     where
-        C: Send + Sync + 'static,
+        M: SocketMsg,
+        for<'de> M: Deserialize<'de>,
+        for<'de> M::Key: Deserialize<'de>,
+        F: Fn(&M) -> Result<(), String> + Send + Sync + 'static,
     {
-        let mut can_subscribe = true;
+        self.validators
+            .push(Arc::new(move |key: &Value, msg: &Value| {
+                // This validator doesn't apply to the key
+                M::Key::deserialize(key).ok()?;
 
-        for filter in &self.subscribe_filters {
-            can_subscribe = can_subscribe && filter(key.clone(), ctx).await;
-        }
+                Some(match M::deserialize(msg) {
+                    Ok(msg) => validator(&msg),
+                    Err(err) => Err(err.to_string()),
+                })
+            }));
+    }
+
+    /// Returns the error of the first validator that rejects the message.
+    pub(crate) fn validate(&self, key: &CanonicalKey, msg: &Value) -> Result<(), String> {
+        self.validators
+            .iter()
+            .filter_map(|validator| validator(key, msg))
+            .find(Result::is_err)
+            .unwrap_or(Ok(()))
+    }
+
+    /// Adds a middleware that intercepts the frames of every connection, including the connections
+    /// that are already open. See [`SocketMiddleware`].
+    pub fn add_middleware(&mut self, middleware: impl SocketMiddleware) {
+        self.middlewares.push(middleware);
+    }
+
+    pub(crate) fn middlewares(&self) -> Middlewares {
+        self.middlewares.clone()
+    }
+
+    /// Calls `hook` with the raw bytes of every frame that is received from or sent to a client,
+    /// exactly as they go over the wire. Replaces the previous hook.
+    ///
+    /// This is meant for debugging and for interop tests, e.g. to record golden files of the
+    /// protocol that a client in another language has to produce and consume. Frames that a
+    /// long-polling client sends are captured per request and the frames it polls as the JSON array
+    /// of the response. The hook applies to connections that are opened after it was set.
+    ///
+    /// ```
+    /// # use std::sync::{Arc, Mutex};
+    /// # use leptos_axum_socket::{Direction, ServerSocket};
+    /// #
+    /// async fn record_protocol(socket: ServerSocket) -> Arc<Mutex<Vec<(Direction, String)>>> {
+    ///     let frames = Arc::new(Mutex::new(Vec::new()));
+    ///
+    ///     socket.lock().await.on_wire_frame({
+    ///         let frames = Arc::clone(&frames);
+    ///         move |direction, bytes| {
+    ///             let frame = String::from_utf8_lossy(bytes).into_owned();
+    ///             frames.lock().unwrap().push((direction, frame));
+    ///         }
+    ///     });
+    ///
+    ///     frames
+    /// }
+    /// ```
+    pub fn on_wire_frame(&mut self, hook: impl Fn(Direction, &[u8]) + Send + Sync + 'static) {
+        self.wire_frame_hook = Some(Arc::new(hook));
+    }
+
+    pub(crate) fn wire_frame_hook(&self) -> Option<WireFrameFn> {
+        self.wire_frame_hook.clone()
+    }
 
-        can_subscribe
+    /// Stamps every message that a client sends with the ID of that client before it's delivered
+    /// to the subscribers. Clients receive it with `SocketContext::subscribe_with_sender`.
+    ///
+    /// The ID is taken from the connection, so clients can trust it more than an author field in
+    /// the message itself. Messages that are sent from the server aren't stamped. Use
+    /// [`ServerSocketInner::stamp_sender_with`] to attach more information about the sender.
+    pub fn stamp_sender(&mut self) {
+        self.sender_meta = Some(Arc::new(|_, _| Value::Null));
     }
 
-    pub(crate) fn map_msg<C>(&self, key: Value, msg: Value, ctx: &C) -> Option<Value>
+    /// Like [`ServerSocketInner::stamp_sender`] but also attaches the metadata that `meta` returns
+    /// for the client. It receives the client ID and the context that was passed to the connection
+    /// handler, for example the logged in user.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::ServerSocket;
+    /// #
+    /// #[derive(Clone)]
+    /// struct User {
+    ///     name: String,
+    /// }
+    ///
+    /// async fn setup(socket: ServerSocket) {
+    ///     socket
+    ///         .lock()
+    ///         .await
+    ///         .stamp_sender_with(|_client_id, user: &Option<User>| {
+    ///             user.as_ref().map(|user| user.name.clone())
+    ///         });
+    /// }
+    /// ```
+    pub fn stamp_sender_with<C, Meta, F>(&mut self, meta: F)
     where
+        F: Fn(Uuid, &C) -> Meta + Send + Sync + 'static,
+        Meta: Serialize,
         C: 'static,
     {
-        for mapper in &self.send_mappers {
-            if let Ok(mapped_msg) = mapper(key.clone(), msg.clone(), ctx) {
-                return mapped_msg;
-            }
+        self.sender_meta = Some(Arc::new(move |client_id: Uuid, ctx: &dyn Any| {
+            let ctx: &C = context_of(ctx);
+
+            codec::to_value(&meta(client_id, ctx)).unwrap_or_else(|err| {
+                error!("Failed to serialize sender metadata: {err}");
+                Value::Null
+            })
+        }));
+    }
+
+    /// Returns the sender info to stamp the messages of the client with if enabled.
+    pub(crate) fn sender_info<C>(&self, client_id: Uuid, ctx: &C) -> Option<SenderInfo>
+    where
+        C: 'static,
+    {
+        self.sender_meta.as_ref().map(|meta| SenderInfo {
+            client_id,
+            meta: meta(client_id, ctx),
+        })
+    }
+
+    /// Lets clients subscribe to the keys of `M` with a filter so that only the messages they're
+    /// interested in are sent to them, which saves bandwidth.
+    ///
+    /// The client sends a filter of type `F` with its subscription (see
+    /// `SocketContext::subscribe_with_server_filter`). For every message of the key the `evaluator`
+    /// is called with that filter and the message. The message is only forwarded to the client if it
+    /// returns `true`. Messages that can't be deserialized into `M` are forwarded unfiltered.
+    ///
+    /// Subscriptions whose filter can't be deserialized into `F` are denied with the reason
+    /// `"filter"`, as are filtered subscriptions to keys that have no evaluator. Unlike
+    /// [`ServerSocketInner::add_subscribe_filter`] this isn't meant for authorization: it only
+    /// narrows down what a client receives.
+    ///
+    /// Like with send mappers, only the first evaluator that accepts the key and the filter is used.
+    ///
+    /// ```
+    /// # use leptos_axum_socket::{ServerSocket, SocketMsg};
+    /// # use serde::{Serialize, Deserialize};
+    /// # use axum::extract::FromRef;
+    /// #
+    /// # #[derive(FromRef, Clone)]
+    /// # pub struct AppState {
+    /// #     pub socket: ServerSocket,
+    /// # }
+    /// #
+    /// #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+    /// pub struct LogKey;
+    ///
+    /// #[derive(Clone, Serialize, Deserialize, Debug)]
+    /// pub struct LogMsg {
+    ///     pub severity: u8,
+    ///     pub text: String,
+    /// }
+    ///
+    /// impl SocketMsg for LogMsg {
+    ///     type Key = LogKey;
+    /// }
+    ///
+    /// #[derive(Deserialize)]
+    /// pub struct MinSeverity {
+    ///     pub min_severity: u8,
+    /// }
+    ///
+    /// async fn setup(socket: ServerSocket) {
+    ///     socket
+    ///         .lock()
+    ///         .await
+    ///         .add_subscription_filter_evaluator(|filter: &MinSeverity, msg: &LogMsg| {
+    ///             msg.severity >= filter.min_severity
+    ///         });
+    /// }
+    /// ```
+    pub fn add_subscription_filter_evaluator<M, F, E>(&mut self, evaluator: E)
+    where
+        M: SocketMsg + 'static,
+        for<'de> M: Deserialize<'de>,
+        for<'de> M::Key: Deserialize<'de>,
+        for<'de> F: Deserialize<'de>,
+        F: Send + Sync + 'static,
+        E: Fn(&F, &M) -> bool + Send + Sync + 'static,
+    {
+        let evaluator = Arc::new(evaluator);
+
+        self.subscription_filter_evaluators
+            .push(Arc::new(move |key: &Value, filter: &Value| {
+                M::Key::deserialize(key)?;
+                let filter = F::deserialize(filter)?;
+                let evaluator = Arc::clone(&evaluator);
+
+                Ok(Arc::new(move |msg: &Value| match M::deserialize(msg) {
+                    Ok(msg) => evaluator(&filter, &msg),
+                    Err(_) => true,
+                }))
+            }));
+    }
+
+    /// Returns the filter for a subscription to `key` or `None` if no evaluator accepts `filter`.
+    pub(crate) fn subscriber_filter(
+        &self,
+        key: &CanonicalKey,
+        filter: &Value,
+    ) -> Option<SubscriberFilter> {
+        if filter.is_null() {
+            return Some(SubscriberFilter::default());
         }
 
-        Some(msg)
+        self.subscription_filter_evaluators
+            .iter()
+            .find_map(|evaluator| evaluator(key, filter).ok())
+            .map(|predicate| SubscriberFilter(Some(predicate)))
+    }
+
+    /// Removes all subscribe filters that were added with [`ServerSocketInner::add_subscribe_filter`].
+    /// Together with adding new filters this allows to reconfigure the authorization at runtime without
+    /// losing the connections. Existing subscriptions are not affected.
+    pub fn clear_subscribe_filters(&mut self) {
+        self.middlewares.remove(&[LayerKind::SubscribeFilter]);
+    }
+
+    /// Removes all send mappers that were added with [`ServerSocketInner::add_send_mapper`] and
+    /// [`ServerSocketInner::add_async_send_mapper`].
+    pub fn clear_send_mappers(&mut self) {
+        self.middlewares
+            .remove(&[LayerKind::SendMapper, LayerKind::AsyncSendMapper]);
+    }
+
+    /// Subscriptions to all messages of a type are only allowed if the type is registered. The other
+    /// keys are checked by the subscribe filters in the middleware chain.
+    pub(crate) fn can_subscribe(&self, key: &CanonicalKey) -> bool {
+        type_name_of_key(key).is_none_or(|type_name| self.registered_types.contains_key(type_name))
+    }
+
+    pub(crate) fn dead_letter(&self, reason: DeadLetterReason, key: &Value, msg: &Value) {
+        self.dead_letters.report(reason, key, msg);
     }
 }
 
@@ -322,10 +3507,12 @@ impl ServerSocketInner {
 ///
 /// You can call this function only from a server function.
 /// If you want to call this from an axum handler, use `ServerSocketInner::send` instead.
+///
+/// Returns the number of receivers the message was delivered to. See [`ServerSocketInner::send`].
 #[instrument]
-pub async fn send<Msg>(key: &Msg::Key, msg: &Msg)
+pub async fn send<Msg>(key: &Msg::Key, msg: &Msg) -> usize
 where
-    Msg: SocketMsg + Serialize + Clone + Send + Sync + Debug + 'static,
+    Msg: ServerSocketMsg + Serialize + Clone + Send + Sync + Debug + 'static,
     for<'de> Msg: Deserialize<'de>,
     Msg::Key: Hash + Eq + Serialize + Clone + Send + Sync + Debug + 'static,
     for<'de> Msg::Key: Deserialize<'de>,
@@ -338,11 +3525,56 @@ where
             error!(
                 "Failed to get the app state context. You can call this function only from a server function. If you want to call this from an axum handler, use `ServerSocketInner::send` instead."
             );
-            return;
+            return 0;
         }
     };
 
-    ServerSocket::from_ref(&state).lock().await.send(key, msg);
+    send_in(&state, key, msg).await
+}
+
+/// Like [`send`] but with an explicitly passed state instead of `Msg::AppState` from the context.
+///
+/// This is useful in apps that are composed of multiple sub-states. Any state that provides a
+/// [`ServerSocket`] via `FromRef` can be used.
+///
+/// ```
+/// # use axum::extract::FromRef;
+/// # use leptos_axum_socket::{ServerSocket, SocketMsg, send_in};
+/// # use serde::{Serialize, Deserialize};
+/// #
+/// # #[derive(FromRef, Clone)]
+/// # pub struct AppState {
+/// #     pub socket: ServerSocket,
+/// # }
+/// #
+/// # #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+/// # struct TheKey;
+/// #
+/// # #[derive(Clone, Serialize, Deserialize, Debug)]
+/// # struct TheMessage;
+/// #
+/// # impl SocketMsg for TheMessage {
+/// #     type Key = TheKey;
+/// # }
+/// #
+/// #[derive(FromRef, Clone)]
+/// pub struct ChatState {
+///     pub socket: ServerSocket,
+/// }
+///
+/// async fn notify(state: ChatState) {
+///     send_in(&state, &TheKey, &TheMessage).await;
+/// }
+/// ```
+pub async fn send_in<Msg, S>(state: &S, key: &Msg::Key, msg: &Msg) -> usize
+where
+    Msg: SocketMsg + Serialize + Clone + Send + Sync + Debug + 'static,
+    for<'de> Msg: Deserialize<'de>,
+    Msg::Key: Hash + Eq + Serialize + Clone + Send + Sync + Debug + 'static,
+    for<'de> Msg::Key: Deserialize<'de>,
+    ServerSocket: FromRef<S>,
+{
+    ServerSocket::from_ref(state).lock().await.send(key, msg)
 }
 
 /// Send a message from a server function only to the connection that called this server function.
@@ -352,7 +3584,7 @@ where
 #[instrument]
 pub async fn send_to_self<Msg>(key: &Msg::Key, msg: &Msg)
 where
-    Msg: SocketMsg + Serialize + Clone + Send + Sync + Debug + 'static,
+    Msg: ServerSocketMsg + Serialize + Clone + Send + Sync + Debug + 'static,
     for<'de> Msg: Deserialize<'de>,
     Msg::Key: Hash + Eq + Serialize + Clone + Send + Sync + Debug + 'static,
     for<'de> Msg::Key: Deserialize<'de>,
@@ -370,25 +3602,47 @@ where
         }
     };
 
-    let key = serde_json::to_value(key).unwrap();
-    let msg = serde_json::to_value(msg).unwrap();
+    let key = codec::to_key(key).unwrap();
+    let msg = codec::to_value(msg).unwrap();
 
     let state: Msg::AppState = expect_context();
 
     ServerSocket::from_ref(&state)
         .lock()
         .await
-        .send_serialized_to_self(client_id, key, msg)
+        .deliver_serialized(DeliveryTarget::Client(client_id), key, msg)
         .await;
 }
 
 fn extract_client_id_server_fn() -> Result<Uuid, String> {
-    let cookie_header = header(COOKIE).ok_or("No cookie header found")?;
+    read_client_id(
+        header(COOKIE).as_deref(),
+        header(HeaderName::from_static(CLIENT_ID_HEADER)).as_deref(),
+    )
+}
+
+/// Reads the client ID from the `socket_client_id` cookie or, if there is none, from the
+/// [`CLIENT_ID_HEADER`].
+fn read_client_id(
+    cookie_header: Option<&str>,
+    client_id_header: Option<&str>,
+) -> Result<Uuid, String> {
+    let cookie_err = match cookie_header.map(read_client_id_from_cookie_header) {
+        Some(Ok(client_id)) => return Ok(client_id),
+        Some(Err(err)) => err,
+        None => "No cookie header found".to_string(),
+    };
 
-    read_client_id_from_cookie_header(&cookie_header)
+    match client_id_header {
+        Some(client_id) => Uuid::parse_str(client_id.trim())
+            .map_err(|err| format!("Invalid UUID in {CLIENT_ID_HEADER} header: {}", err)),
+        None => Err(format!(
+            "{cookie_err} and no {CLIENT_ID_HEADER} header found"
+        )),
+    }
 }
 
-fn read_client_id_from_cookie_header(cookie_header: &str) -> Result<Uuid, String> {
+pub(crate) fn read_client_id_from_cookie_header(cookie_header: &str) -> Result<Uuid, String> {
     // Parse value of cookie called socket_client_id
     let re = Regex::new(r"socket_client_id=([^;]+)").unwrap();
     let caps = re
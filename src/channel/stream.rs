@@ -0,0 +1,50 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_channel::mpsc::UnboundedReceiver;
+use futures_core::Stream;
+
+use super::dispatch::HandlerFn;
+use crate::{CanonicalKey, SocketContext};
+
+/// The messages of a key as a [`Stream`]. The subscription is removed when this is dropped.
+///
+/// See [`SocketContext::messages_stream`].
+pub struct MessageStream<Msg> {
+    context: SocketContext,
+    key: CanonicalKey,
+    handler: HandlerFn,
+    rx: UnboundedReceiver<Msg>,
+}
+
+impl<Msg> MessageStream<Msg> {
+    pub(crate) fn new(
+        context: SocketContext,
+        key: CanonicalKey,
+        handler: HandlerFn,
+        rx: UnboundedReceiver<Msg>,
+    ) -> Self {
+        Self {
+            context,
+            key,
+            handler,
+            rx,
+        }
+    }
+}
+
+impl<Msg> Stream for MessageStream<Msg> {
+    type Item = Msg;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Msg>> {
+        Pin::new(&mut self.get_mut().rx).poll_next(cx)
+    }
+}
+
+impl<Msg> Drop for MessageStream<Msg> {
+    fn drop(&mut self) {
+        self.context.remove_subscription(&self.key, &self.handler);
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    fmt::{Debug, Formatter},
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+};
+
+use crate::SocketMsg;
+
+/// A key that is tied to the message type that is sent with it.
+///
+/// [`SocketContext::subscribe`](crate::SocketContext::subscribe) infers the message type from the
+/// handler. If two message types share the same key type, subscribing with the wrong handler compiles
+/// fine and only fails at runtime when the messages can't be deserialized. With a `Topic` the message
+/// type is fixed when the topic is created, so
+/// [`SocketContext::send_topic`](crate::SocketContext::send_topic) and
+/// [`SocketContext::subscribe_topic`](crate::SocketContext::subscribe_topic) only accept messages
+/// and handlers of that type.
+///
+/// ```
+/// # use leptos::prelude::*;
+/// # use leptos_axum_socket::{expect_socket_context, ServerSocket, SocketMsg, Topic};
+/// # use serde::{Serialize, Deserialize};
+/// # use axum::extract::FromRef;
+/// #
+/// # #[derive(FromRef, Clone)]
+/// # pub struct AppState {
+/// #     pub socket: ServerSocket,
+/// # }
+/// #
+/// #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+/// pub struct RoomKey(u64);
+///
+/// #[derive(Clone, Serialize, Deserialize, Debug)]
+/// pub struct ChatMsg(String);
+///
+/// impl SocketMsg for ChatMsg {
+///     type Key = RoomKey;
+/// }
+///
+/// #[component]
+/// pub fn Room() -> impl IntoView {
+///     let socket = expect_socket_context();
+///     let topic = Topic::<ChatMsg>::new(RoomKey(1));
+///
+///     socket.subscribe_topic(&topic, |msg| leptos::logging::log!("{msg:?}"));
+///     socket.send_topic(&topic, ChatMsg("Hi".to_string()));
+///
+///     view! { "..." }
+/// }
+/// ```
+pub struct Topic<Msg: SocketMsg> {
+    key: Msg::Key,
+    msg: PhantomData<fn() -> Msg>,
+}
+
+impl<Msg: SocketMsg> Topic<Msg> {
+    pub fn new(key: Msg::Key) -> Self {
+        Self {
+            key,
+            msg: PhantomData,
+        }
+    }
+
+    pub fn key(&self) -> &Msg::Key {
+        &self.key
+    }
+
+    pub fn into_key(self) -> Msg::Key {
+        self.key
+    }
+}
+
+impl<Msg: SocketMsg> Clone for Topic<Msg>
+where
+    Msg::Key: Clone,
+{
+    fn clone(&self) -> Self {
+        Self::new(self.key.clone())
+    }
+}
+
+impl<Msg: SocketMsg> Copy for Topic<Msg> where Msg::Key: Copy {}
+
+impl<Msg: SocketMsg> Debug for Topic<Msg>
+where
+    Msg::Key: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Topic").field(&self.key).finish()
+    }
+}
+
+impl<Msg: SocketMsg> PartialEq for Topic<Msg>
+where
+    Msg::Key: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl<Msg: SocketMsg> Eq for Topic<Msg> where Msg::Key: Eq {}
+
+impl<Msg: SocketMsg> Hash for Topic<Msg>
+where
+    Msg::Key: Hash,
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key.hash(state);
+    }
+}
@@ -0,0 +1,69 @@
+use super::ChannelMsg;
+#[cfg(all(feature = "trace-protocol", debug_assertions))]
+use super::{CanonicalKey, codec};
+
+/// Payloads longer than this are truncated in the trace.
+#[cfg(all(feature = "trace-protocol", debug_assertions))]
+const MAX_PAYLOAD_CHARS: usize = 200;
+
+/// The direction of a frame as seen from the side that handles it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// The frame was received.
+    In,
+    /// The frame is sent.
+    Out,
+}
+
+/// Logs `msg` with its direction, its key and its (truncated) payload if the `trace-protocol` feature
+/// is enabled in a debug build. Does nothing otherwise.
+#[inline]
+pub(crate) fn trace_frame(direction: Direction, msg: &ChannelMsg) {
+    #[cfg(all(feature = "trace-protocol", debug_assertions))]
+    {
+        let arrow = match direction {
+            Direction::In => "<-",
+            Direction::Out => "->",
+        };
+        let key = frame_key(msg).map_or_else(|| "-".to_string(), ToString::to_string);
+
+        let mut payload = codec::to_string(msg).unwrap_or_else(|err| format!("<{err}>"));
+        if let Some((end, _)) = payload.char_indices().nth(MAX_PAYLOAD_CHARS) {
+            payload.truncate(end);
+            payload.push('…');
+        }
+
+        #[cfg(feature = "ssr")]
+        tracing::debug!(target: "leptos_axum_socket::protocol", "{arrow} {key} {payload}");
+        #[cfg(not(feature = "ssr"))]
+        leptos::logging::log!("[socket] {arrow} {key} {payload}");
+    }
+
+    #[cfg(not(all(feature = "trace-protocol", debug_assertions)))]
+    let _ = (direction, msg);
+}
+
+#[cfg(all(feature = "trace-protocol", debug_assertions))]
+fn frame_key(msg: &ChannelMsg) -> Option<&CanonicalKey> {
+    match msg {
+        ChannelMsg::Msg { key, .. }
+        | ChannelMsg::PathMsg { key, .. }
+        | ChannelMsg::Subscribe { key, .. }
+        | ChannelMsg::Unsubscribe { key }
+        | ChannelMsg::SubscribeResult { key, .. }
+        | ChannelMsg::KeyClosed { key }
+        | ChannelMsg::KeyRemapped { old: key, .. }
+        | ChannelMsg::SubscribeDenied { key, .. }
+        | ChannelMsg::Invalid { key, .. } => Some(key),
+        #[cfg(feature = "patch")]
+        ChannelMsg::Patch { key, .. } => Some(key),
+        ChannelMsg::SubscribeMany { .. }
+        | ChannelMsg::SubscribeManyResult { .. }
+        | ChannelMsg::TooLarge { .. }
+        | ChannelMsg::Welcome { .. } => None,
+        #[cfg(feature = "reliable")]
+        ChannelMsg::Reliable { msg, .. } => frame_key(msg),
+        #[cfg(feature = "reliable")]
+        ChannelMsg::Ack { .. } => None,
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    borrow::Borrow,
+    fmt::{Debug, Formatter},
+    marker::PhantomData,
+};
+
+use crate::{SocketContext, SocketMsg};
+
+/// A [`SocketContext`] that is bound to the message type `Msg`, so the calls don't need a
+/// turbofish or a typed handler to infer it. It's as cheap to copy as the context.
+///
+/// See [`SocketContext::channel`].
+///
+/// ```ignore
+/// let chat = expect_socket_context().channel::<ChatMsg>();
+///
+/// chat.subscribe(ChatKey { room_id }, |msg| leptos::logging::log!("{msg:?}"));
+/// chat.send(ChatKey { room_id }, ChatMsg::new("Hi"));
+/// ```
+pub struct TypedChannel<Msg: SocketMsg> {
+    context: SocketContext,
+    msg: PhantomData<fn() -> Msg>,
+}
+
+impl<Msg: SocketMsg> TypedChannel<Msg> {
+    pub(crate) fn new(context: SocketContext) -> Self {
+        Self {
+            context,
+            msg: PhantomData,
+        }
+    }
+
+    /// Returns the context this channel sends and subscribes with.
+    pub fn context(self) -> SocketContext {
+        self.context
+    }
+}
+
+impl<Msg> TypedChannel<Msg>
+where
+    Msg: SocketMsg + serde::Serialize + Clone + 'static,
+    for<'de> Msg: serde::Deserialize<'de>,
+    Msg::Key: serde::Serialize,
+    for<'de> Msg::Key: serde::Deserialize<'de>,
+{
+    /// See [`SocketContext::send`].
+    pub fn send(self, key: impl Borrow<Msg::Key>, msg: Msg) {
+        self.context.send(key, msg);
+    }
+
+    /// See [`SocketContext::subscribe`].
+    pub fn subscribe(
+        self,
+        key: impl Borrow<Msg::Key>,
+        handler: impl Fn(&Msg) + Send + Sync + 'static,
+    ) {
+        self.context.subscribe(key, handler);
+    }
+
+    /// See [`SocketContext::unsubscribe_msg`].
+    pub fn unsubscribe(self, key: impl Borrow<Msg::Key>) {
+        self.context.unsubscribe_msg::<Msg>(key);
+    }
+}
+
+impl<Msg: SocketMsg> Clone for TypedChannel<Msg> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Msg: SocketMsg> Copy for TypedChannel<Msg> {}
+
+impl<Msg: SocketMsg> Debug for TypedChannel<Msg> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TypedChannel")
+            .field("msg", &std::any::type_name::<Msg>())
+            .finish()
+    }
+}
@@ -0,0 +1,127 @@
+//! Processing of messages in a Web Worker so that decoding large payloads doesn't block the UI.
+//! Requires the `worker` feature.
+//!
+//! The main thread only forwards the JSON of the message to the worker. The worker deserializes and
+//! processes it and posts the result back, which is passed to the handler of
+//! [`SocketContext::subscribe_in_worker`](crate::SocketContext::subscribe_in_worker).
+
+use leptos::logging::error;
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use wasm_bindgen::{JsCast, JsValue, closure::Closure};
+use web_sys::{DedicatedWorkerGlobalScope, MessageEvent, Worker, WorkerOptions, WorkerType};
+
+/// A running Web Worker together with the callback that receives its results. The worker is
+/// terminated when this is dropped.
+pub(crate) struct MessageWorker {
+    worker: Worker,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+}
+
+impl MessageWorker {
+    /// Starts the module worker at `script_url`. Every result it posts is deserialized and passed to
+    /// `handler`.
+    pub(crate) fn spawn<Out>(
+        script_url: &str,
+        handler: impl Fn(&Out) + 'static,
+    ) -> Result<Self, JsValue>
+    where
+        Out: DeserializeOwned,
+    {
+        let options = WorkerOptions::new();
+        options.set_type(WorkerType::Module);
+        let worker = Worker::new_with_options(script_url, &options)?;
+
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            let Some(data) = event.data().as_string() else {
+                error!("The worker posted a result that isn't a string");
+                return;
+            };
+
+            match serde_json::from_str::<Out>(&data) {
+                Ok(out) => handler(&out),
+                Err(err) => error!("Failed to deserialize the result of the worker: {}", err),
+            }
+        });
+        worker.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        Ok(Self {
+            worker,
+            _on_message: on_message,
+        })
+    }
+
+    /// Passes the message on to the worker.
+    pub(crate) fn post(&self, msg: &Value) {
+        if let Err(err) = self
+            .worker
+            .post_message(&JsValue::from_str(&msg.to_string()))
+        {
+            error!("Failed to post message to the worker: {:?}", err);
+        }
+    }
+}
+
+impl Drop for MessageWorker {
+    fn drop(&mut self) {
+        self.worker.set_onmessage(None);
+        self.worker.terminate();
+    }
+}
+
+/// Call this in the entry point of the worker that is started by
+/// [`SocketContext::subscribe_in_worker`](crate::SocketContext::subscribe_in_worker). Every message
+/// of the subscription is deserialized into `Msg` and passed to `process` in the worker. The value
+/// it returns is posted back to the main thread.
+///
+/// The worker is started as a module worker, so the script can be the JS glue of a wasm-bindgen
+/// build with `--target web` that initializes the wasm module and calls the exported entry point.
+///
+/// ## Example
+///
+/// ```ignore
+/// #[wasm_bindgen]
+/// pub fn chart_worker() {
+///     // Runs off the main thread
+///     serve_in_worker(|msg: SamplesMsg| ChartPoints::downsample(&msg.samples, 500));
+/// }
+/// ```
+pub fn serve_in_worker<Msg, Out>(process: impl Fn(Msg) -> Out + 'static)
+where
+    Msg: DeserializeOwned,
+    Out: Serialize,
+{
+    let scope = js_sys::global().unchecked_into::<DedicatedWorkerGlobalScope>();
+
+    let on_message = Closure::<dyn FnMut(MessageEvent)>::new({
+        let scope = scope.clone();
+
+        move |event: MessageEvent| {
+            let Some(data) = event.data().as_string() else {
+                error!("The worker received a message that isn't a string");
+                return;
+            };
+
+            let msg = match serde_json::from_str::<Msg>(&data) {
+                Ok(msg) => msg,
+                Err(err) => {
+                    error!("Failed to deserialize message in the worker: {}", err);
+                    return;
+                }
+            };
+
+            match serde_json::to_string(&process(msg)) {
+                Ok(out) => {
+                    if let Err(err) = scope.post_message(&JsValue::from_str(&out)) {
+                        error!("Failed to post the result of the worker: {:?}", err);
+                    }
+                }
+                Err(err) => error!("Failed to serialize the result of the worker: {}", err),
+            }
+        }
+    });
+    scope.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+    // The worker handles messages until it's terminated
+    on_message.forget();
+}
@@ -0,0 +1,309 @@
+//! HTTP long-polling fallback for clients that can't establish a WebSocket connection.
+//!
+//! The same [`ChannelMsg`] protocol is used as over the WebSocket. Messages from the client are
+//! posted to `/socket-msg/send` and messages to the client are fetched from `/socket-msg/poll`.
+//! Both routes are registered by [`SocketRoute::longpoll_route`](crate::SocketRoute::longpoll_route).
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Json,
+    extract::State,
+    http::{
+        HeaderMap, StatusCode,
+        header::{CONTENT_TYPE, COOKIE},
+    },
+    response::{IntoResponse, Response},
+};
+use futures_util::FutureExt;
+use tokio::sync::{Mutex, broadcast::error::RecvError, mpsc};
+use tracing::{Instrument, debug, info_span, warn};
+use uuid::Uuid;
+
+use super::{catch_panic, handle_channel_msg, set_client_id_cookie};
+use crate::{
+    ChannelMsg, ConnectionSlot, ServerSocket, SharedFrame, read_client_id_from_cookie_header,
+    trace::{Direction, trace_frame},
+};
+
+/// How long a poll request waits for a message before it returns an empty response.
+const POLL_TIMEOUT: Duration = Duration::from_secs(25);
+
+/// Sessions that haven't been polled for this long are considered disconnected.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Number of messages that are buffered for a client between two polls.
+const SESSION_BUFFER: usize = 64;
+
+pub(crate) struct LongPollSession {
+    client_rx: Arc<Mutex<mpsc::Receiver<Arc<SharedFrame>>>>,
+    last_seen: Instant,
+    /// Freed when the session expires
+    _slot: ConnectionSlot,
+}
+
+/// Axum handler for `GET /socket-msg/poll`. This is registered by
+/// [`SocketRoute::longpoll_route`](crate::SocketRoute::longpoll_route).
+///
+/// If the client doesn't have a session yet, a new one is created and an empty response with status
+/// `201 Created` is returned immediately. Otherwise this waits until at least one message is available for the client (or a timeout
+/// is reached) and returns all buffered messages.
+pub async fn poll(State(socket): State<ServerSocket>, headers: HeaderMap) -> Response {
+    let session = {
+        let mut socket = socket.lock().await;
+        let middlewares = socket.middlewares();
+        let wire_frame_hook = socket.wire_frame_hook();
+
+        client_id_from_headers(&headers).and_then(|client_id| {
+            let traffic = socket.traffic_counter(client_id);
+
+            socket.longpoll_session_mut(client_id).map(|session| {
+                session.last_seen = Instant::now();
+                (
+                    client_id,
+                    Arc::clone(&session.client_rx),
+                    middlewares,
+                    wire_frame_hook,
+                    traffic,
+                )
+            })
+        })
+    };
+
+    let Some((client_id, client_rx, middlewares, wire_frame_hook, traffic)) = session else {
+        return start_session(socket).await;
+    };
+
+    let mut client_rx = client_rx.lock().await;
+    let mut msgs = vec![];
+
+    if let Ok(Some(msg)) = tokio::time::timeout(POLL_TIMEOUT, client_rx.recv()).await {
+        msgs.push(msg);
+
+        while let Ok(msg) = client_rx.try_recv() {
+            msgs.push(msg);
+        }
+    }
+
+    let mut outbound = Vec::with_capacity(msgs.len());
+    for frame in msgs {
+        if let Some(msg) = middlewares
+            .outbound(client_id, SharedFrame::into_msg(frame))
+            .await
+        {
+            trace_frame(Direction::Out, &msg);
+            outbound.push(msg);
+        }
+    }
+
+    let Ok(body) = serde_json::to_vec(&outbound) else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    if let Some(hook) = wire_frame_hook {
+        hook(Direction::Out, &body);
+    }
+    if let Some(traffic) = traffic {
+        // Responses aren't compressed by this crate
+        traffic.count(body.len(), body.len());
+    }
+
+    ([(CONTENT_TYPE, "application/json")], body).into_response()
+}
+
+/// This is used to handle the messages that a long-polling client sends to `POST /socket-msg/send`.
+///
+/// It's the long-polling counterpart of [`upgrade_websocket`](super::upgrade_websocket) and
+/// receives the same context that is passed to the subscribe filters and send mappers.
+///
+/// ```
+/// # use axum::{extract::State, http::HeaderMap, response::Response};
+/// # use leptos_axum_socket::{ServerSocket, handlers::longpoll::handle_longpoll_send};
+/// #
+/// pub async fn longpoll_send(
+///     State(socket): State<ServerSocket>,
+///     headers: HeaderMap,
+///     body: String,
+/// ) -> Response {
+///     // You could do authentication here
+///
+///     // Provide the same context as in your websocket handler
+///     let ctx = ();
+///
+///     handle_longpoll_send(socket, &headers, &body, ctx).await
+/// }
+/// ```
+pub async fn handle_longpoll_send<C>(
+    socket: ServerSocket,
+    headers: &HeaderMap,
+    body: &str,
+    context: C,
+) -> Response
+where
+    C: Send + Sync + 'static,
+{
+    let Some(client_id) = client_id_from_headers(headers) else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    let wire_frame_hook = socket.lock().await.wire_frame_hook();
+    if let Some(hook) = wire_frame_hook {
+        hook(Direction::In, body.as_bytes());
+    }
+
+    let msg: ChannelMsg = match serde_json::from_str(body) {
+        Ok(msg) => msg,
+        Err(err) => {
+            debug!("Failed to parse long-polling message: {err}");
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+
+    trace_frame(Direction::In, &msg);
+
+    let (client_tx, middlewares) = {
+        let mut socket = socket.lock().await;
+
+        if socket.longpoll_session_mut(client_id).is_none() {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        let Some(client_tx) = socket.client_sender(client_id) else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+
+        (client_tx, socket.middlewares())
+    };
+
+    let Some(msg) = middlewares.inbound(client_id, &context, msg).await else {
+        return StatusCode::NO_CONTENT.into_response();
+    };
+
+    let span = info_span!("socket_connection", %client_id);
+
+    handle_channel_msg(
+        &socket,
+        client_id,
+        msg,
+        &context,
+        |result, subscriptions| {
+            // Sent once by whichever task gets to it first. The others wait for it.
+            let result_sent = {
+                let client_tx = client_tx.clone();
+                async move { client_tx.send(SharedFrame::new(result)).await.is_ok() }
+                    .boxed()
+                    .shared()
+            };
+
+            subscriptions
+                .into_iter()
+                .map(|(mut broadcast_rx, filter)| {
+                    let client_tx = client_tx.clone();
+                    let result_sent = result_sent.clone();
+
+                    socket.spawner().spawn(
+                        catch_panic(async move {
+                            if !result_sent.await {
+                                return; // disconnected.
+                            }
+
+                            loop {
+                                let frame = match broadcast_rx.recv().await {
+                                    Ok(frame) => frame,
+                                    // The seq of the next message tells the client that it
+                                    // missed some
+                                    Err(RecvError::Lagged(count)) => {
+                                        warn!(
+                                            "Subscription lagged behind. Skipped {count} messages"
+                                        );
+                                        continue;
+                                    }
+                                    Err(RecvError::Closed) => return,
+                                };
+
+                                if !filter.accepts(frame.msg()) {
+                                    continue;
+                                }
+
+                                if client_tx.send(frame).await.is_err() {
+                                    return; // disconnected.
+                                }
+                            }
+                        })
+                        .map(|_| ())
+                        .in_current_span(),
+                    )
+                })
+                .collect()
+        },
+    )
+    .instrument(span)
+    .await;
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn start_session(socket: ServerSocket) -> Response {
+    let Some(slot) = socket.acquire_connection_slot() else {
+        debug!(
+            "Rejected long-polling session because the maximum number of connections is reached"
+        );
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+
+    let client_id = Uuid::new_v4();
+    let (client_tx, client_rx) = mpsc::channel(SESSION_BUFFER);
+
+    {
+        let mut socket = socket.lock().await;
+        socket.insert_client_sender(client_id, client_tx);
+        socket.insert_longpoll_session(
+            client_id,
+            LongPollSession {
+                client_rx: Arc::new(Mutex::new(client_rx)),
+                last_seen: Instant::now(),
+                _slot: slot,
+            },
+        );
+    }
+
+    debug!("Started long-polling session {client_id}");
+
+    socket.spawner().clone().spawn(
+        expire_session(socket, client_id).instrument(info_span!("socket_connection", %client_id)),
+    );
+
+    let mut response = (StatusCode::CREATED, Json(Vec::<ChannelMsg>::new())).into_response();
+    set_client_id_cookie(&mut response, client_id);
+
+    response
+}
+
+/// Cleans up the session once the client stopped polling.
+async fn expire_session(socket: ServerSocket, client_id: Uuid) {
+    loop {
+        tokio::time::sleep(SESSION_TIMEOUT).await;
+
+        let mut socket = socket.lock().await;
+
+        match socket.longpoll_session_mut(client_id) {
+            Some(session) if session.last_seen.elapsed() >= SESSION_TIMEOUT => {
+                debug!("Long-polling session {client_id} expired");
+                socket.remove_longpoll_session(client_id);
+                return;
+            }
+            Some(_) => (),
+            None => return,
+        }
+    }
+}
+
+fn client_id_from_headers(headers: &HeaderMap) -> Option<Uuid> {
+    let cookie_header = headers.get(COOKIE)?.to_str().ok()?;
+
+    read_client_id_from_cookie_header(cookie_header)
+        .map_err(|err| debug!("No long-polling session: {err}"))
+        .ok()
+}
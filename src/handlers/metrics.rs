@@ -0,0 +1,111 @@
+//! Prometheus metrics of the server socket.
+//!
+//! The route is registered by [`SocketRoute::metrics_route`](crate::SocketRoute::metrics_route).
+//! The following metrics are exported:
+//!
+//! - `leptos_axum_socket_connections`: number of connected clients.
+//! - `leptos_axum_socket_keys`: number of keys that have a broadcast sender.
+//! - `leptos_axum_socket_subscribers{key="..."}`: number of subscribers per key. The label is the
+//!   key serialized as JSON.
+//! - `leptos_axum_socket_messages_sent_total`: number of messages that were sent with a key. Use
+//!   `rate()` on it to get the messages per second.
+
+use std::fmt::Write;
+
+use axum::{
+    extract::State,
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+};
+
+use crate::{ServerSocket, SocketSnapshot};
+
+/// The content type of the Prometheus text format.
+const CONTENT_TYPE_PROMETHEUS: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Axum handler that renders the current state of the socket in the Prometheus text format.
+/// Requires the `metrics` feature.
+///
+/// ```
+/// # use axum::{Router, extract::FromRef};
+/// # use leptos_axum_socket::{ServerSocket, SocketRoute};
+/// #
+/// # #[derive(FromRef, Clone)]
+/// # pub struct AppState {
+/// #     pub socket: ServerSocket,
+/// # }
+/// #
+/// // Serves the metrics on `/socket-metrics`
+/// let app: Router<AppState> = Router::new().metrics_route();
+/// ```
+pub async fn metrics_handler(State(socket): State<ServerSocket>) -> Response {
+    let snapshot = socket.lock().await.snapshot();
+
+    ([(CONTENT_TYPE, CONTENT_TYPE_PROMETHEUS)], render(&snapshot)).into_response()
+}
+
+fn render(snapshot: &SocketSnapshot) -> String {
+    let mut out = String::new();
+
+    write_metric(
+        &mut out,
+        "leptos_axum_socket_connections",
+        "gauge",
+        "Number of connected clients.",
+    );
+    let _ = writeln!(
+        out,
+        "leptos_axum_socket_connections {}",
+        snapshot.client_ids.len()
+    );
+
+    write_metric(
+        &mut out,
+        "leptos_axum_socket_keys",
+        "gauge",
+        "Number of keys that have a broadcast sender.",
+    );
+    let _ = writeln!(out, "leptos_axum_socket_keys {}", snapshot.sender_count);
+
+    write_metric(
+        &mut out,
+        "leptos_axum_socket_subscribers",
+        "gauge",
+        "Number of subscribers per key.",
+    );
+    for key in &snapshot.keys {
+        let _ = writeln!(
+            out,
+            "leptos_axum_socket_subscribers{{key=\"{}\"}} {}",
+            escape_label(&key.key.to_string()),
+            key.receiver_count
+        );
+    }
+
+    write_metric(
+        &mut out,
+        "leptos_axum_socket_messages_sent_total",
+        "counter",
+        "Number of messages that were sent with a key.",
+    );
+    let _ = writeln!(
+        out,
+        "leptos_axum_socket_messages_sent_total {}",
+        snapshot.messages_sent
+    );
+
+    out
+}
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Escapes a label value as required by the Prometheus text format.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
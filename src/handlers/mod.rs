@@ -0,0 +1,1050 @@
+use std::{
+    collections::HashSet,
+    panic::AssertUnwindSafe,
+    pin::{Pin, pin},
+    sync::{Arc, OnceLock},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Bytes,
+    extract::{
+        WebSocketUpgrade,
+        ws::{self, Message, Utf8Bytes, WebSocket},
+    },
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+#[cfg(feature = "ssr")]
+use cookie::{Cookie, SameSite};
+use futures_util::{FutureExt, Sink, SinkExt, Stream, StreamExt, future};
+use serde_json::Value;
+use tokio::sync::{Mutex, broadcast, broadcast::error::RecvError, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{Instrument, debug, error, info_span, warn};
+use uuid::Uuid;
+
+pub use crate::CloseFrame;
+use crate::{
+    CanonicalKey, ChannelMsg, Middlewares, ServerSocket, ServerSocketInner, SharedFrame,
+    SubscriberFilter, TrafficCounter, WireFrameFn, codec,
+    trace::{Direction, trace_frame},
+};
+use resume::Resume;
+
+#[cfg(feature = "longpoll")]
+pub mod longpoll;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "reliable")]
+mod reliable;
+mod resume;
+
+/// Options for a client connection.
+///
+/// See [`upgrade_websocket_with_options`].
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionOptions {
+    idle_timeout: Option<Duration>,
+    max_message_size: Option<usize>,
+    no_client_id_cookie: bool,
+    resume: Option<Resume>,
+}
+
+impl ConnectionOptions {
+    /// Closes the connection if nothing was sent or received for the given duration. This frees the
+    /// resources of clients that are connected but don't do anything. Disabled by default.
+    ///
+    /// This is about idle connections. Connections that are dead are detected when sending to them fails.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Rejects frames from the client that are larger than `max_size` bytes with a `TooLarge` frame.
+    /// Disabled by default.
+    ///
+    /// The size is checked after the frame has been decoded by the transport. This crate doesn't
+    /// compress frames, so this is the size of the uncompressed frame. If you add compression to a
+    /// custom transport, make sure to decompress with a bounded decompressor so a small compressed frame
+    /// can't expand to gigabytes before this limit is checked.
+    pub fn max_message_size(mut self, max_size: usize) -> Self {
+        self.max_message_size = Some(max_size);
+        self
+    }
+
+    /// Whether the `socket_client_id` cookie is set on the upgrade response. Defaults to `true`.
+    ///
+    /// Disable this if your deployment doesn't allow cookies on the WebSocket upgrade. The client
+    /// still receives its ID in the first frame and has to send it in the
+    /// [`CLIENT_ID_HEADER`](crate::CLIENT_ID_HEADER) header for `send_to_self` to work.
+    /// See `SocketContext::client_id`.
+    pub fn client_id_cookie(mut self, enabled: bool) -> Self {
+        self.no_client_id_cookie = !enabled;
+        self
+    }
+
+    /// Lets a reconnecting client keep its previous client ID. Disabled by default.
+    ///
+    /// Next to the `socket_client_id` cookie a `socket_resume_token` cookie is set that contains an
+    /// HMAC of the client ID signed with `secret`. When the client reconnects, `headers` (the headers
+    /// of the upgrade request) have to contain both cookies with a valid signature for the previous
+    /// client ID to be reused. Otherwise the client gets a new ID like before. This way a client can't
+    /// pretend to be another client. Keep `secret` private and use the same one on all servers.
+    ///
+    /// The ID is not reused while another connection with the same ID is still open (e.g. a second
+    /// browser tab). Only the ID is kept. The client has to subscribe again as usual, which
+    /// `SocketContext` does automatically. This requires the client ID cookie, see
+    /// [`ConnectionOptions::client_id_cookie`].
+    ///
+    /// ```
+    /// # use axum::{extract::{State, WebSocketUpgrade}, http::HeaderMap, response::Response};
+    /// # use leptos_axum_socket::{ServerSocket, handlers::{ConnectionOptions, upgrade_websocket_with_options}};
+    /// #
+    /// pub async fn connect_to_websocket(
+    ///     ws: WebSocketUpgrade,
+    ///     State(socket): State<ServerSocket>,
+    ///     headers: HeaderMap,
+    /// ) -> Response {
+    ///     let secret = std::env::var("SOCKET_RESUME_SECRET").unwrap_or_default();
+    ///     let options = ConnectionOptions::default().resume_client_id(secret, &headers);
+    ///
+    ///     upgrade_websocket_with_options(ws, socket, (), options)
+    /// }
+    /// ```
+    pub fn resume_client_id(mut self, secret: impl AsRef<[u8]>, headers: &HeaderMap) -> Self {
+        self.resume = Some(Resume::new(secret.as_ref().into(), headers));
+        self
+    }
+}
+
+/// A frame that is sent to the client. The text is shared with the other clients that receive
+/// the same frame.
+enum Outgoing {
+    Text(Utf8Bytes),
+    Close(CloseFrame),
+}
+
+/// Adapts a sink of text frames. Transports that only accept text can't send a `Close` frame so
+/// it's skipped and the sink is closed afterwards.
+struct TextSink<W>(W);
+
+impl<W> Sink<Outgoing> for TextSink<W>
+where
+    W: Sink<String> + Unpin,
+{
+    type Error = W::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.0).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, frame: Outgoing) -> Result<(), Self::Error> {
+        match frame {
+            Outgoing::Text(text) => {
+                // Only copied if the text is shared with other clients
+                let text = String::from_utf8(Bytes::from(text).into()).unwrap_or_default();
+                Pin::new(&mut self.0).start_send(text)
+            }
+            Outgoing::Close(_) => Ok(()),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.0).poll_close(cx)
+    }
+}
+
+/// Passes the text frames to the hook of
+/// [`ServerSocketInner::on_wire_frame`](crate::ServerSocketInner::on_wire_frame) before they are
+/// sent and counts their bytes. Frames aren't compressed so they are counted the same before and
+/// after compression.
+struct WireTap<W>(W, Option<WireFrameFn>, Option<TrafficCounter>);
+
+impl<W> Sink<Outgoing> for WireTap<W>
+where
+    W: Sink<Outgoing> + Unpin,
+{
+    type Error = W::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.0).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, frame: Outgoing) -> Result<(), Self::Error> {
+        if let Outgoing::Text(text) = &frame {
+            if let Some(hook) = &self.1 {
+                hook(Direction::Out, text.as_bytes());
+            }
+            if let Some(traffic) = &self.2 {
+                traffic.count(text.len(), text.len());
+            }
+        }
+        Pin::new(&mut self.0).start_send(frame)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.0).poll_close(cx)
+    }
+}
+
+async fn handle_websocket_with_context<C>(
+    ws: WebSocket,
+    socket: ServerSocket,
+    client_id: Uuid,
+    context: C,
+    options: ConnectionOptions,
+) where
+    C: Send + Sync + 'static,
+{
+    let (ws_tx, ws_rx) = ws.split();
+
+    let outgoing = ws_tx.with(|frame: Outgoing| {
+        future::ready(Ok::<_, axum::Error>(match frame {
+            Outgoing::Text(text) => Message::text(text),
+            Outgoing::Close(CloseFrame { code, reason }) => Message::Close(Some(ws::CloseFrame {
+                code,
+                reason: reason.into(),
+            })),
+        }))
+    });
+
+    let incoming = ws_rx
+        .take_while(|msg| {
+            future::ready(matches!(msg, Ok(msg) if !matches!(msg, Message::Close(_))))
+        })
+        .filter_map(|msg| {
+            future::ready(match msg {
+                Ok(Message::Text(text)) => Some(text.to_string()),
+                _ => None,
+            })
+        });
+
+    serve_connection(incoming, outgoing, socket, client_id, context, options)
+        .instrument(info_span!("socket_connection", %client_id))
+        .await;
+}
+
+/// Serves a client connection over an arbitrary transport.
+///
+/// This is what [`upgrade_websocket`] uses under the hood. You can use it to run the same pub/sub
+/// protocol over another transport like WebTransport. `incoming` yields the text frames received
+/// from the client and `outgoing` accepts the text frames that should be sent to the client.
+/// This returns when `incoming` ends, i.e. the client disconnected.
+///
+/// The `client_id` identifies the connection. If you want to use `send_to_self` it has to be
+/// the same ID that is stored in the `socket_client_id` cookie of the client.
+///
+/// Like a WebSocket connection, this counts towards [`ServerSocketInner::set_max_connections`].
+/// If the maximum is reached or another connection with the same `client_id` is open, `outgoing`
+/// is closed and this returns right away.
+///
+/// The tasks spawned for the connection log within the span that is current when this is called.
+/// Instrument the returned future with a span that carries the `client_id` to correlate the logs.
+pub async fn handle_connection<C, R, W>(
+    incoming: R,
+    outgoing: W,
+    socket: ServerSocket,
+    client_id: Uuid,
+    context: C,
+) where
+    C: Send + Sync + 'static,
+    R: Stream<Item = String>,
+    W: Sink<String> + Unpin + Send + 'static,
+{
+    handle_connection_with_options(
+        incoming,
+        outgoing,
+        socket,
+        client_id,
+        context,
+        ConnectionOptions::default(),
+    )
+    .await;
+}
+
+/// Like [`handle_connection`] but with [`ConnectionOptions`].
+///
+/// When the server closes the connection, `outgoing` is closed. A `Close` frame can't be sent over
+/// a sink of text frames so its code and reason are not sent to the client.
+pub async fn handle_connection_with_options<C, R, W>(
+    incoming: R,
+    mut outgoing: W,
+    socket: ServerSocket,
+    client_id: Uuid,
+    context: C,
+    options: ConnectionOptions,
+) where
+    C: Send + Sync + 'static,
+    R: Stream<Item = String>,
+    W: Sink<String> + Unpin + Send + 'static,
+{
+    let Some(mut slot) = socket.acquire_connection_slot() else {
+        debug!("Rejected connection because the maximum number of connections is reached");
+        let _ = outgoing.close().await;
+        return;
+    };
+
+    if !slot.claim_client_id(client_id) {
+        debug!("Rejected connection because client {client_id} is already connected");
+        let _ = outgoing.close().await;
+        return;
+    }
+
+    serve_connection(
+        incoming,
+        TextSink(outgoing),
+        socket,
+        client_id,
+        context,
+        options,
+    )
+    .await;
+}
+
+async fn serve_connection<C, R, W>(
+    incoming: R,
+    outgoing: W,
+    socket: ServerSocket,
+    client_id: Uuid,
+    context: C,
+    options: ConnectionOptions,
+) where
+    C: Send + Sync + 'static,
+    R: Stream<Item = String>,
+    W: Sink<Outgoing> + Unpin + Send + 'static,
+{
+    let (client_tx, client_rx) = mpsc::channel::<Arc<SharedFrame>>(16);
+    let (close_tx, close_rx) = oneshot::channel();
+
+    let (middlewares, wire_frame_hook, traffic) = {
+        let mut socket = socket.lock().await;
+        socket.insert_client_sender(client_id, client_tx);
+        socket.insert_close_sender(client_id, close_tx);
+
+        (
+            socket.middlewares(),
+            socket.wire_frame_hook(),
+            socket.traffic_counter(client_id),
+        )
+    };
+
+    let outgoing = WireTap(outgoing, wire_frame_hook.clone(), traffic);
+
+    let ws_tx = Arc::new(ClientTx::new(outgoing, client_id, middlewares.clone()));
+
+    ws_tx.send(ChannelMsg::Welcome { client_id }).await;
+
+    // The sender is removed together with the client. Removing it here as well could remove the
+    // sender of a client that already reconnected with the same ID.
+    socket.spawner().spawn({
+        let ws_tx = Arc::clone(&ws_tx);
+
+        async move {
+            catch_panic(recv_client_send(ws_tx, client_rx)).await;
+        }
+        .in_current_span()
+    });
+
+    #[cfg(feature = "reliable")]
+    let redelivery = socket.spawner().spawn({
+        let ws_tx = Arc::clone(&ws_tx);
+
+        async move { ws_tx.redeliver().await }.in_current_span()
+    });
+
+    let close_requested = async {
+        match close_rx.await {
+            Ok(frame) => frame,
+            Err(_) => future::pending().await,
+        }
+    };
+    let idle = {
+        let ws_tx = Arc::clone(&ws_tx);
+
+        async move {
+            idle_watchdog(ws_tx, options.idle_timeout).await;
+            debug!("Closing idle connection");
+
+            CloseFrame::new(1000, "idle timeout")
+        }
+    };
+    let close_frame = OnceLock::new();
+    let close = async {
+        let (frame, _) = future::select(pin!(close_requested), pin!(idle))
+            .await
+            .factor_first();
+        let _ = close_frame.set(frame);
+    };
+
+    let mut incoming = pin!(incoming.take_until(close));
+
+    while let Some(text) = incoming.next().await {
+        debug!("Received Text: {text}");
+
+        if let Some(hook) = &wire_frame_hook {
+            hook(Direction::In, text.as_bytes());
+        }
+
+        ws_tx.touch();
+
+        if let Some(max_size) = options.max_message_size
+            && text.len() > max_size
+        {
+            debug!("Rejected frame of {} bytes", text.len());
+
+            let too_large = ChannelMsg::TooLarge {
+                size: text.len(),
+                max_size,
+            };
+            if !ws_tx.send(too_large).await {
+                break; // disconnected.
+            }
+
+            continue;
+        }
+
+        let msg: ChannelMsg = match serde_json::from_str(&text) {
+            Ok(msg) => msg,
+            Err(err) => {
+                debug!("Failed to parse message: {err}");
+                continue;
+            }
+        };
+
+        trace_frame(Direction::In, &msg);
+
+        #[cfg(feature = "reliable")]
+        if let ChannelMsg::Ack { seq } = msg {
+            ws_tx.outstanding.lock().unwrap().ack(seq);
+            continue;
+        }
+
+        let Some(msg) = middlewares.inbound(client_id, &context, msg).await else {
+            continue;
+        };
+
+        handle_channel_msg(
+            &socket,
+            client_id,
+            msg,
+            &context,
+            |result, subscriptions| {
+                // Sent once by whichever task gets to it first. The others wait for it.
+                let result_sent = {
+                    let ws_tx = Arc::clone(&ws_tx);
+                    async move { ws_tx.send(result).await }.boxed().shared()
+                };
+
+                subscriptions
+                    .into_iter()
+                    .map(|(broadcast_rx, filter)| {
+                        let ws_tx = Arc::clone(&ws_tx);
+                        let result_sent = result_sent.clone();
+
+                        socket.spawner().spawn(
+                            async move {
+                                let forwarded = catch_panic({
+                                    let ws_tx = Arc::clone(&ws_tx);
+
+                                    async move {
+                                        if !result_sent.await {
+                                            return; // disconnected.
+                                        }
+
+                                        recv_broadcast(ws_tx, broadcast_rx, filter).await;
+                                    }
+                                })
+                                .await;
+
+                                if !forwarded {
+                                    // Close the connection instead of leaving a client behind that
+                                    // silently stops receiving. It reconnects and subscribes again.
+                                    ws_tx.close(CloseFrame::new(1011, "internal error")).await;
+                                }
+                            }
+                            .in_current_span(),
+                        )
+                    })
+                    .collect()
+            },
+        )
+        .await;
+    }
+
+    if let Some(frame) = close_frame.get() {
+        ws_tx.close(frame.clone()).await;
+    }
+
+    // Cleanup on disconnect
+    #[cfg(feature = "reliable")]
+    redelivery.abort();
+
+    socket.lock().await.remove_client(client_id);
+}
+
+/// The broadcast receiver of an accepted subscription and the filter of the subscription.
+pub(crate) type Subscription = (broadcast::Receiver<Arc<SharedFrame>>, SubscriberFilter);
+
+/// Processes a message received from a client independent of the transport.
+///
+/// `forward` is called with the `SubscribeResult` or `SubscribeManyResult` frame and the broadcast
+/// receiver and filter of every accepted subscription. It has to spawn a task per subscription that
+/// forwards the messages that pass the filter to the client, but only after the result frame was
+/// sent. The handles are returned in the order of the subscriptions.
+pub(crate) async fn handle_channel_msg<C, F>(
+    socket: &ServerSocket,
+    client_id: Uuid,
+    msg: ChannelMsg,
+    context: &C,
+    forward: F,
+) where
+    C: Send + Sync + 'static,
+    F: FnOnce(ChannelMsg, Vec<Subscription>) -> Vec<JoinHandle<()>>,
+{
+    // The sender the client claims is ignored, it's stamped by the server if enabled
+    if let ChannelMsg::Msg { msg, key, id, .. } = msg {
+        return handle_client_msg(socket, client_id, key, msg, id, context).await;
+    }
+
+    let mut socket = socket.lock().await;
+
+    match msg {
+        ChannelMsg::Subscribe { key, filter } => {
+            match accept_subscription(&mut socket, client_id, &key, &filter, 0) {
+                Ok((result, subscription)) => {
+                    let handles = forward(result, vec![subscription]);
+
+                    for handle in handles {
+                        socket.remember_handle(client_id, key.clone(), handle);
+                    }
+                }
+                Err(Some(denied)) => socket.send_serialized_to_client(client_id, denied),
+                Err(None) => (),
+            }
+        }
+        ChannelMsg::SubscribeMany { mut keys } => {
+            let mut seen = HashSet::new();
+            keys.retain(|key| seen.insert(key.clone()));
+
+            let mut results = Vec::with_capacity(keys.len());
+            let mut accepted_keys = Vec::new();
+            let mut subscriptions = Vec::new();
+
+            for key in keys {
+                let accepted = accept_subscription(
+                    &mut socket,
+                    client_id,
+                    &key,
+                    &Value::Null,
+                    accepted_keys.len(),
+                );
+
+                match accepted {
+                    Ok((result, subscription)) => {
+                        results.push(result);
+                        accepted_keys.push(key);
+                        subscriptions.push(subscription);
+                    }
+                    Err(Some(denied)) => results.push(denied),
+                    Err(None) => (),
+                }
+            }
+
+            let result = ChannelMsg::SubscribeManyResult { results };
+
+            if subscriptions.is_empty() {
+                socket.send_serialized_to_client(client_id, result);
+                return;
+            }
+
+            let handles = forward(result, subscriptions);
+
+            for (key, handle) in accepted_keys.into_iter().zip(handles) {
+                socket.remember_handle(client_id, key, handle);
+            }
+        }
+        ChannelMsg::Unsubscribe { key } => {
+            socket.unsubscribe(client_id, key);
+        }
+        #[cfg(feature = "patch")]
+        ChannelMsg::Patch { key, patch, .. } => {
+            let patched = socket.patched(&key, &patch).and_then(|patched| {
+                socket
+                    .validate(&key, &patched)
+                    .map(|()| patched)
+                    .map_err(crate::PatchError::Rejected)
+            });
+
+            match patched {
+                Ok(patched) => {
+                    socket.send_patch_serialized(key, patch, patched);
+                }
+                Err(err) => {
+                    debug!("Dropped patch from client {client_id}: {err}");
+                    socket.send_serialized_to_client(
+                        client_id,
+                        ChannelMsg::Invalid {
+                            key,
+                            reason: err.to_string(),
+                        },
+                    );
+                }
+            }
+        }
+        ChannelMsg::Msg { .. }
+        | ChannelMsg::PathMsg { .. }
+        | ChannelMsg::KeyClosed { .. }
+        | ChannelMsg::KeyRemapped { .. }
+        | ChannelMsg::SubscribeResult { .. }
+        | ChannelMsg::SubscribeDenied { .. }
+        | ChannelMsg::SubscribeManyResult { .. }
+        | ChannelMsg::Invalid { .. }
+        | ChannelMsg::TooLarge { .. }
+        | ChannelMsg::Welcome { .. } => (),
+        #[cfg(feature = "reliable")]
+        ChannelMsg::Reliable { .. } | ChannelMsg::Ack { .. } => (),
+    }
+}
+
+/// Checks whether the client may subscribe to `key` and subscribes it to the broadcast of the key if
+/// so. Returns the `SubscribeResult` frame and the subscription, or the
+/// `SubscribeDenied` frame if the subscription is denied. `pending` is the number of subscriptions
+/// of the client that are accepted but not remembered yet.
+#[allow(clippy::result_large_err)] // The denial is a frame that is sent to the client
+fn accept_subscription(
+    socket: &mut ServerSocketInner,
+    client_id: Uuid,
+    key: &CanonicalKey,
+    filter: &Value,
+    pending: usize,
+) -> Result<(ChannelMsg, Subscription), Option<ChannelMsg>> {
+    if !socket.is_subscribed(client_id, key)
+        && socket.subscription_count(client_id) + pending >= socket.max_subscriptions_per_client()
+    {
+        debug!("Client {client_id} reached the subscription limit");
+        return Err(Some(ChannelMsg::SubscribeDenied {
+            key: key.clone(),
+            reason: "limit".to_string(),
+        }));
+    }
+
+    let Some(filter) = socket.subscriber_filter(key, filter) else {
+        debug!("Client {client_id} subscribed with a filter that no evaluator accepts");
+        return Err(Some(ChannelMsg::SubscribeDenied {
+            key: key.clone(),
+            reason: "filter".to_string(),
+        }));
+    };
+
+    if !socket.can_subscribe(key) {
+        return Err(None);
+    }
+
+    let broadcast_rx = socket.subscribe(key.clone());
+    let pinned = socket
+        .pinned_msgs(key)
+        .filter(|msg| filter.accepts_msg(msg))
+        .cloned()
+        .collect::<Vec<_>>();
+    let retained = socket
+        .latest_msg(key)
+        .filter(|msg| filter.accepts_msg(msg))
+        .cloned();
+    let history = socket
+        .history_msgs(key)
+        .filter(|msg| filter.accepts_msg(msg))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let result = ChannelMsg::SubscribeResult {
+        key: key.clone(),
+        pinned,
+        history,
+        retained,
+    };
+
+    Ok((result, (broadcast_rx, filter)))
+}
+
+/// Validates and broadcasts a message that the client sent. It was already mapped by the send mappers
+/// in the middleware chain.
+async fn handle_client_msg<C>(
+    socket: &ServerSocket,
+    client_id: Uuid,
+    key: CanonicalKey,
+    msg: Value,
+    id: Option<Uuid>,
+    context: &C,
+) where
+    C: Send + Sync + 'static,
+{
+    let mut socket = socket.lock().await;
+
+    if let Some(id) = id
+        && !socket.remember_msg_id(client_id, id)
+    {
+        // Only the sender is still waiting for the echo
+        debug!("Client {client_id} sent message {id} again");
+        socket.send_serialized_to_client(
+            client_id,
+            ChannelMsg::Msg {
+                key,
+                msg,
+                id: Some(id),
+                sender: None,
+                seq: None,
+            },
+        );
+        return;
+    }
+
+    if let Err(reason) = socket.validate(&key, &msg) {
+        debug!("Dropped invalid message from client {client_id}: {reason}");
+        socket.send_serialized_to_client(client_id, ChannelMsg::Invalid { key, reason });
+        return;
+    }
+
+    let sender = socket.sender_info(client_id, context);
+    socket.send_serialized_from_client(key, msg, id, sender);
+}
+
+/// Resolves once nothing was sent or received for `timeout`. Never resolves if `timeout` is `None`.
+async fn idle_watchdog<W>(ws_tx: Arc<ClientTx<W>>, timeout: Option<Duration>) {
+    let Some(timeout) = timeout else {
+        return future::pending().await;
+    };
+
+    loop {
+        let deadline = *ws_tx.last_activity.lock().unwrap() + timeout;
+
+        if Instant::now() >= deadline {
+            return;
+        }
+
+        tokio::time::sleep_until(deadline.into()).await;
+    }
+}
+
+/// The sending half of a client connection.
+struct ClientTx<W> {
+    ws_tx: Mutex<W>,
+    client_id: Uuid,
+    middlewares: Middlewares,
+    last_activity: std::sync::Mutex<Instant>,
+    #[cfg(feature = "reliable")]
+    outstanding: std::sync::Mutex<reliable::Outstanding>,
+}
+
+impl<W> ClientTx<W>
+where
+    W: Sink<Outgoing> + Unpin,
+{
+    fn new(ws_tx: W, client_id: Uuid, middlewares: Middlewares) -> Self {
+        Self {
+            ws_tx: Mutex::new(ws_tx),
+            client_id,
+            middlewares,
+            last_activity: std::sync::Mutex::new(Instant::now()),
+            #[cfg(feature = "reliable")]
+            outstanding: Default::default(),
+        }
+    }
+
+    /// Records that something was sent or received.
+    fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    /// Returns `false` if the client is disconnected.
+    async fn send(&self, msg: ChannelMsg) -> bool {
+        self.touch();
+
+        let Some(msg) = self.middlewares.outbound(self.client_id, msg).await else {
+            return true; // dropped by middleware.
+        };
+
+        #[cfg(feature = "reliable")]
+        let msg = self.outstanding.lock().unwrap().track(msg);
+
+        send_msg(&self.ws_tx, &msg).await
+    }
+
+    /// Like [`ClientTx::send`] but reuses the serialization that is shared with the other receivers
+    /// of the frame.
+    async fn send_shared(&self, frame: &SharedFrame) -> bool {
+        // Reliable frames carry a sequence number per client so they can't be shared
+        #[cfg(feature = "reliable")]
+        {
+            self.send(frame.msg().clone()).await
+        }
+
+        #[cfg(not(feature = "reliable"))]
+        {
+            // Middleware might change the frame so it has to be serialized for this client only
+            if self.middlewares.intercepts_outbound() {
+                return self.send(frame.msg().clone()).await;
+            }
+
+            self.touch();
+            trace_frame(Direction::Out, frame.msg());
+
+            match frame.text() {
+                Some(text) => self
+                    .ws_tx
+                    .lock()
+                    .await
+                    .send(Outgoing::Text(text.clone()))
+                    .await
+                    .is_ok(),
+                None => true,
+            }
+        }
+    }
+}
+
+impl<W> ClientTx<W>
+where
+    W: Sink<Outgoing> + Unpin,
+{
+    /// Sends a `Close` frame and closes the sink.
+    async fn close(&self, frame: CloseFrame) {
+        let mut ws_tx = self.ws_tx.lock().await;
+
+        let _ = ws_tx.send(Outgoing::Close(frame)).await;
+        let _ = ws_tx.close().await;
+    }
+}
+
+async fn recv_client_send<W>(
+    ws_tx: Arc<ClientTx<W>>,
+    mut client_rx: mpsc::Receiver<Arc<SharedFrame>>,
+) where
+    W: Sink<Outgoing> + Unpin,
+{
+    while let Some(frame) = client_rx.recv().await {
+        if !ws_tx.send_shared(&frame).await {
+            return; // disconnected.
+        }
+    }
+}
+
+async fn recv_broadcast<W>(
+    ws_tx: Arc<ClientTx<W>>,
+    mut broadcast_rx: broadcast::Receiver<Arc<SharedFrame>>,
+    filter: SubscriberFilter,
+) where
+    W: Sink<Outgoing> + Unpin,
+{
+    loop {
+        let frame = match broadcast_rx.recv().await {
+            Ok(frame) => frame,
+            // The seq of the next message tells the client that it missed some
+            Err(RecvError::Lagged(count)) => {
+                warn!("Subscription lagged behind. Skipped {count} messages");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+
+        if !filter.accepts(frame.msg()) {
+            continue;
+        }
+
+        if !ws_tx.send_shared(&frame).await {
+            return; // disconnected.
+        }
+    }
+}
+
+/// Returns `false` if the client is disconnected. Frames that can't be serialized are skipped.
+async fn send_msg<W>(ws_tx: &Mutex<W>, msg: &ChannelMsg) -> bool
+where
+    W: Sink<Outgoing> + Unpin,
+{
+    trace_frame(Direction::Out, msg);
+
+    let text = match codec::to_string(msg) {
+        Ok(text) => text,
+        Err(err) => {
+            error!("Failed to serialize frame: {err}");
+            return true;
+        }
+    };
+
+    ws_tx
+        .lock()
+        .await
+        .send(Outgoing::Text(text.into()))
+        .await
+        .is_ok()
+}
+
+/// Awaits `fut` and logs if it panics. Returns `false` if it panicked.
+async fn catch_panic(fut: impl Future<Output = ()>) -> bool {
+    match AssertUnwindSafe(fut).catch_unwind().await {
+        Ok(()) => true,
+        Err(panic) => {
+            let reason = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown");
+
+            error!("Connection task panicked: {reason}");
+            false
+        }
+    }
+}
+
+/// This is used to handle the incoming WebSocket connection.
+///
+/// ```
+/// # use axum::{extract::{State, WebSocketUpgrade}, response::Response};
+/// # use leptos_axum_socket::{ServerSocket, handlers::upgrade_websocket};
+/// #
+/// #[cfg(feature = "ssr")]
+/// pub async fn connect_to_websocket(
+///     ws: WebSocketUpgrade,
+///     State(socket): State<ServerSocket>,
+/// ) -> Response {
+///     // You could do authentication here
+///
+///     // Provide extra context like the user's ID for example that is passed to the permission filters
+///     let ctx = ();
+///
+///     upgrade_websocket( ws, socket, ctx)
+/// }
+/// ```
+pub fn upgrade_websocket<C>(ws: WebSocketUpgrade, socket: ServerSocket, context: C) -> Response
+where
+    C: Send + Sync + 'static,
+{
+    upgrade_websocket_with_options(ws, socket, context, ConnectionOptions::default())
+}
+
+/// Like [`upgrade_websocket`] but with [`ConnectionOptions`].
+///
+/// ```
+/// # use std::time::Duration;
+/// # use axum::{extract::{State, WebSocketUpgrade}, response::Response};
+/// # use leptos_axum_socket::{ServerSocket, handlers::{ConnectionOptions, upgrade_websocket_with_options}};
+/// #
+/// pub async fn connect_to_websocket(
+///     ws: WebSocketUpgrade,
+///     State(socket): State<ServerSocket>,
+/// ) -> Response {
+///     let options = ConnectionOptions::default().idle_timeout(Duration::from_secs(15 * 60));
+///
+///     upgrade_websocket_with_options(ws, socket, (), options)
+/// }
+/// ```
+pub fn upgrade_websocket_with_options<C>(
+    ws: WebSocketUpgrade,
+    socket: ServerSocket,
+    context: C,
+    options: ConnectionOptions,
+) -> Response
+where
+    C: Send + Sync + 'static,
+{
+    let Some(mut slot) = socket.acquire_connection_slot() else {
+        debug!("Rejected connection because the maximum number of connections is reached");
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+
+    // The ID is settled before the cookies are set so that they name this connection
+    let client_id = match options.resume.as_ref().and_then(Resume::verified_client_id) {
+        Some(client_id) if slot.claim_client_id(client_id) => client_id,
+        resumed_client_id => {
+            if let Some(resumed_client_id) = resumed_client_id {
+                debug!("Client {resumed_client_id} is still connected. Not resuming its ID");
+            }
+
+            let client_id = Uuid::new_v4();
+            slot.claim_client_id(client_id);
+            client_id
+        }
+    };
+    let set_cookie = !options.no_client_id_cookie;
+    let resume = options.resume.clone();
+
+    let mut response = ws.on_upgrade(move |websocket| async move {
+        // Freed when the connection is closed
+        let _slot = slot;
+
+        handle_websocket_with_context(websocket, socket, client_id, context, options).await
+    });
+
+    if set_cookie {
+        set_client_id_cookie(&mut response, client_id);
+
+        if let Some(resume) = resume {
+            resume.set_token_cookie(response.headers_mut(), client_id);
+        }
+    }
+
+    response
+}
+
+/// Like [`upgrade_websocket`] but the connection is rejected if `context` is an error. This way
+/// the handler can authenticate the client before the upgrade and return a non-101 response.
+/// No client ID cookie is set for rejected connections.
+///
+/// ```
+/// # use axum::{extract::{State, WebSocketUpgrade}, http::{HeaderMap, StatusCode}, response::Response};
+/// # use leptos_axum_socket::{ServerSocket, handlers::try_upgrade_websocket};
+/// #
+/// # #[derive(Clone)]
+/// # struct UserId(u64);
+/// #
+/// # async fn authenticate(headers: &HeaderMap) -> Result<UserId, StatusCode> {
+/// #     Err(StatusCode::UNAUTHORIZED)
+/// # }
+/// #
+/// pub async fn connect_to_websocket(
+///     ws: WebSocketUpgrade,
+///     State(socket): State<ServerSocket>,
+///     headers: HeaderMap,
+/// ) -> Result<Response, Response> {
+///     // Responds with 401 if the user isn't logged in
+///     try_upgrade_websocket(ws, socket, authenticate(&headers).await)
+/// }
+/// ```
+#[allow(clippy::result_large_err)] // Both variants are responses that are returned from a handler
+pub fn try_upgrade_websocket<C, E>(
+    ws: WebSocketUpgrade,
+    socket: ServerSocket,
+    context: Result<C, E>,
+) -> Result<Response, Response>
+where
+    C: Send + Sync + 'static,
+    E: IntoResponse,
+{
+    match context {
+        Ok(context) => Ok(upgrade_websocket(ws, socket, context)),
+        Err(err) => Err(err.into_response()),
+    }
+}
+
+pub(crate) fn set_client_id_cookie(response: &mut Response, client_id: Uuid) {
+    let cookie = Cookie::build(("socket_client_id", client_id.to_string()))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .build();
+
+    response.headers_mut().append(
+        header::SET_COOKIE,
+        HeaderValue::from_str(&cookie.to_string()).unwrap(),
+    );
+}
@@ -0,0 +1,129 @@
+//! At-least-once delivery of messages to WebSocket clients.
+//!
+//! Every message frame that is sent to the client is wrapped in a [`ChannelMsg::Reliable`] frame with
+//! a sequence number that the client acknowledges with [`ChannelMsg::Ack`]. Frames that aren't
+//! acknowledged in time are sent again until they are acknowledged or the retry limit is reached.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use futures_util::Sink;
+use tracing::warn;
+
+use super::{ClientTx, send_msg};
+use crate::ChannelMsg;
+
+/// How long to wait for an acknowledgement before a frame is sent again.
+const REDELIVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often a frame is sent at most before it is given up.
+const MAX_DELIVERY_ATTEMPTS: u32 = 5;
+
+/// How many unacknowledged frames are kept per client. If there are more, the oldest one is dropped.
+const MAX_OUTSTANDING: usize = 1024;
+
+/// The frames that have been sent to a client but haven't been acknowledged yet.
+#[derive(Default)]
+pub(super) struct Outstanding {
+    next_seq: u64,
+    frames: BTreeMap<u64, PendingFrame>,
+}
+
+struct PendingFrame {
+    frame: ChannelMsg,
+    sent_at: Instant,
+    attempts: u32,
+}
+
+impl Outstanding {
+    /// Wraps message frames in a [`ChannelMsg::Reliable`] frame and remembers them until they are
+    /// acknowledged. Other frames are returned unchanged.
+    pub(super) fn track(&mut self, msg: ChannelMsg) -> ChannelMsg {
+        #[cfg(feature = "patch")]
+        let is_patch = matches!(msg, ChannelMsg::Patch { .. });
+        #[cfg(not(feature = "patch"))]
+        let is_patch = false;
+
+        if !is_patch && !matches!(msg, ChannelMsg::Msg { .. } | ChannelMsg::PathMsg { .. }) {
+            return msg;
+        }
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        let frame = ChannelMsg::Reliable {
+            seq,
+            msg: Box::new(msg),
+        };
+
+        if self.frames.len() >= MAX_OUTSTANDING
+            && let Some((seq, _)) = self.frames.pop_first()
+        {
+            warn!("Too many unacknowledged messages. Giving up on message {seq}");
+        }
+
+        self.frames.insert(
+            seq,
+            PendingFrame {
+                frame: frame.clone(),
+                sent_at: Instant::now(),
+                attempts: 1,
+            },
+        );
+
+        frame
+    }
+
+    pub(super) fn ack(&mut self, seq: u64) {
+        self.frames.remove(&seq);
+    }
+
+    /// Returns the frames that have to be sent again.
+    fn due(&mut self) -> Vec<ChannelMsg> {
+        let now = Instant::now();
+        let mut due = vec![];
+
+        self.frames.retain(|seq, pending| {
+            if now.duration_since(pending.sent_at) < REDELIVERY_TIMEOUT {
+                return true;
+            }
+
+            if pending.attempts >= MAX_DELIVERY_ATTEMPTS {
+                warn!("Message {seq} wasn't acknowledged after {MAX_DELIVERY_ATTEMPTS} attempts");
+                return false;
+            }
+
+            pending.attempts += 1;
+            pending.sent_at = now;
+            due.push(pending.frame.clone());
+
+            true
+        });
+
+        due
+    }
+}
+
+impl<W> ClientTx<W>
+where
+    W: Sink<super::Outgoing> + Unpin,
+{
+    /// Sends unacknowledged frames again until the client disconnects.
+    pub(super) async fn redeliver(&self) {
+        let mut interval = tokio::time::interval(REDELIVERY_TIMEOUT / 5);
+
+        loop {
+            interval.tick().await;
+
+            let due = self.outstanding.lock().unwrap().due();
+
+            for frame in due {
+                if !send_msg(&self.ws_tx, &frame).await {
+                    return; // disconnected.
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Lets a reconnecting client keep its client ID.
+//!
+//! The client ID cookie is accompanied by a token that is an HMAC of the client ID. A client can only
+//! present a client ID on reconnect together with a token that was signed with the same secret. This
+//! prevents clients from taking over the ID of another client.
+
+use std::{fmt::Debug, sync::Arc};
+
+use axum::http::{HeaderMap, HeaderValue, header};
+use cookie::{Cookie, SameSite};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+const RESUME_TOKEN_COOKIE: &str = "socket_resume_token";
+
+/// The secret and the client ID that was presented by the client. See
+/// [`ConnectionOptions::resume_client_id`](super::ConnectionOptions::resume_client_id).
+#[derive(Clone)]
+pub(super) struct Resume {
+    secret: Arc<[u8]>,
+    presented: Option<(Uuid, String)>,
+}
+
+impl Debug for Resume {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Resume")
+            .field(
+                "presented_client_id",
+                &self.presented.as_ref().map(|(id, _)| id),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl Resume {
+    pub(super) fn new(secret: Arc<[u8]>, headers: &HeaderMap) -> Self {
+        let presented = headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|cookie_header| cookie_header.to_str().ok())
+            .find_map(|cookie_header| {
+                let mut client_id = None;
+                let mut token = None;
+
+                for cookie in Cookie::split_parse(cookie_header).flatten() {
+                    match cookie.name() {
+                        "socket_client_id" => client_id = Uuid::parse_str(cookie.value()).ok(),
+                        RESUME_TOKEN_COOKIE => token = Some(cookie.value().to_string()),
+                        _ => (),
+                    }
+                }
+
+                Some((client_id?, token?))
+            });
+
+        Self { secret, presented }
+    }
+
+    /// Returns the presented client ID if its token is valid.
+    pub(super) fn verified_client_id(&self) -> Option<Uuid> {
+        let (client_id, token) = self.presented.as_ref()?;
+        let token = decode_hex(token)?;
+
+        self.mac(*client_id).verify_slice(&token).ok()?;
+
+        Some(*client_id)
+    }
+
+    pub(super) fn set_token_cookie(&self, headers: &mut HeaderMap, client_id: Uuid) {
+        let token = encode_hex(&self.mac(client_id).finalize().into_bytes());
+
+        let cookie = Cookie::build((RESUME_TOKEN_COOKIE, token))
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Strict)
+            .build();
+
+        headers.append(
+            header::SET_COOKIE,
+            HeaderValue::from_str(&cookie.to_string()).unwrap(),
+        );
+    }
+
+    fn mac(&self, client_id: Uuid) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(client_id.as_bytes());
+        mac
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -4,7 +4,7 @@
 //!
 //! ```
 //! # use leptos::prelude::*;
-//! # use leptos_axum_socket::{expect_socket_context, ServerSocket, SocketMsg};
+//! # use leptos_axum_socket::{expect_socket_context, ServerSocket, ServerSocketMsg, SocketMsg};
 //! # use serde::{Serialize, Deserialize};
 //! # use axum::extract::{State, FromRef};
 //! #
@@ -27,7 +27,11 @@
 //! // Implement the SocketMsg trait for MyMsg to link the key and message types
 //! impl SocketMsg for MyMsg {
 //!     type Key = MyKey;
-//!     #[cfg(feature = "ssr")]
+//! }
+//!
+//! // On the server link the message type to the app state that provides the socket
+//! #[cfg(feature = "ssr")]
+//! impl ServerSocketMsg for MyMsg {
 //!     type AppState = AppState;
 //! }
 //!
@@ -129,8 +133,6 @@
 //! #
 //! # impl SocketMsg for MyMsg {
 //! #     type Key = MyKey;
-//! #     #[cfg(feature = "ssr")]
-//! #     type AppState = AppState;
 //! # }
 //! #
 //! #[tokio::main]
@@ -206,31 +208,175 @@
 //! }
 //! ```
 //!
+//! ### Many Message Types
+//!
+//! The app state is the same for every message type. Instead of repeating `type AppState` in every
+//! `ServerSocketMsg` impl, you can use [`socket_msgs!`]:
+//!
+//! ```
+//! # use leptos_axum_socket::{ServerSocket, socket_msgs};
+//! # use serde::{Serialize, Deserialize};
+//! # use axum::extract::FromRef;
+//! #
+//! # #[derive(FromRef, Clone)]
+//! # pub struct AppState {
+//! #     pub socket: ServerSocket,
+//! # }
+//! #
+//! # #[derive(Clone, Serialize, Deserialize)]
+//! # pub struct MyKey;
+//! # #[derive(Clone, Serialize, Deserialize)]
+//! # pub struct MyMsg;
+//! # #[derive(Clone, Serialize, Deserialize)]
+//! # pub struct OtherKey;
+//! # #[derive(Clone, Serialize, Deserialize)]
+//! # pub struct OtherMsg;
+//! #
+//! socket_msgs! {
+//!     type AppState = AppState;
+//!
+//!     MyMsg => MyKey;
+//!     OtherMsg => OtherKey;
+//! }
+//! ```
+//!
+//! ### Rejecting Connections
+//!
+//! To reject a client, for example because it isn't authenticated, return a non-101 response from
+//! `connect_to_websocket` instead of upgrading. [`handlers::try_upgrade_websocket`] does this for you
+//! if the context can't be created.
+//!
 //! ### Axum Handlers
 //!
 //! You can also send messages from inside axum handlers.
 //! Checkout [`ServerSocketInner::send`] and [`ServerSocketInner::send_to_self`].
+//!
+//! ### Reconnecting
+//!
+//! If the connection is lost, the client reconnects with capped exponential backoff and jitter so that not all
+//! clients reconnect at the same time after a server restart. Configure the delays with
+//! [`provide_socket_context_with_options`] and [`ReconnectBackoff`].
+//!
+//! To show an error when the server is unreachable, set a deadline with [`SocketOptions::connect_deadline`].
+//! If the connection isn't open in time, [`SocketContext::connection_state`] becomes [`ConnectionState::Failed`].
+//!
+//! The server can close a connection with a close code, for example when a session is revoked. Codes
+//! from 4000 to 4999 are reserved for applications. After such a close the client doesn't reconnect
+//! on its own. Read the code with [`SocketContext::last_close`] to decide what to do, for example
+//! redirect to the login page on 4001.
+//!
+//! ### Server-Side Rendering
+//!
+//! Realtime communication only starts after hydration. During SSR `provide_socket_context` provides
+//! an inert context that never connects: it stays closed, sending does nothing and subscribed handlers
+//! are never called. So components can subscribe and send unconditionally.
+//!
+//! ### Long-Polling Fallback
+//!
+//! Some networks block WebSockets. With the `longpoll` feature enabled the client automatically
+//! falls back to HTTP long-polling if the WebSocket fails to connect. On the server you have to register
+//! the additional routes with `SocketRoute::longpoll_route` and provide a send handler similar to
+//! `connect_to_websocket`. See `handlers::longpoll::handle_longpoll_send`.
+//!
+//! ### Reliable Delivery
+//!
+//! Messages are delivered best-effort by default. With the `reliable` feature enabled (on the client and
+//! the server) every message that is sent to a WebSocket client carries a sequence number that the client
+//! acknowledges. Messages that aren't acknowledged within 5 seconds are sent again, up to 5 times.
+//! Handlers can therefore be called more than once for the same message.
+//!
+//! The server keeps every unacknowledged message in a buffer per client until it is acknowledged or
+//! given up. This buffer is capped at 1024 messages per client, so with many clients and large messages
+//! this can use a significant amount of memory while clients are slow to respond.
+//!
+//! ### Serialization
+//!
+//! Keys and messages are serialized as JSON. Keys are compared by their serialized form, so they are
+//! always serialized in a compact canonical form: object fields are sorted, fields that are `null` are
+//! left out and integral floats become integers. See `CanonicalKey`.
+//!
+//! The serialization of messages can be configured with features that apply to both sides:
+//!
+//! - `json-skip-nulls`: object fields that are `null` (i.e. `Option::None`) are left out.
+//! - `json-pretty`: frames are pretty printed which makes them easier to read in the browser dev tools.
+//!
+//! ### Protobuf
+//!
+//! With the `protobuf` feature enabled, messages can be [`prost`](https://docs.rs/prost) messages wrapped in `Protobuf`.
+//! Their payload is encoded with protobuf instead of duplicating the schema with serde. See `Protobuf`
+//! for an example.
+//!
+//! ### JSON Schemas
+//!
+//! With the `json-schema` feature enabled, `ServerSocketInner::register_schema` registers message types
+//! that implement [`schemars::JsonSchema`](https://docs.rs/schemars). `ServerSocket::export_schemas`
+//! then returns the JSON schemas of all registered types and their keys, which helps to document the
+//! protocol and to generate clients in other languages.
+//!
+//! ### Web Workers
+//!
+//! Handlers are called on the main thread. To keep the UI responsive with large payloads, the
+//! `worker` feature adds `SocketContext::subscribe_in_worker`. The main thread then only forwards
+//! the messages to a Web Worker which deserializes and processes them with `serve_in_worker` and posts
+//! back the result for the handler.
+//!
+//! ### Tracing the Protocol
+//!
+//! To debug subscription mismatches you can enable the `trace-protocol` feature. In debug builds every
+//! frame that is sent or received is then logged with its direction, its key and its payload (truncated
+//! to 200 characters). On the client this goes to the browser console. On the server it's logged with
+//! `tracing` at the debug level with the target `leptos_axum_socket::protocol`. In release builds the
+//! feature has no effect.
+//!
+//! ### Metrics
+//!
+//! With the `metrics` feature enabled, `SocketRoute::metrics_route` serves the number of connections,
+//! keys, subscribers per key and sent messages on `/socket-metrics` in the Prometheus text format.
+//! You can also mount `metrics_handler` on a route of your choice.
+//!
+//! ### Compression
+//!
+//! Every connection counts the bytes of the frames that are sent to it before and after
+//! compression, and whether compression is active. They are part of the `ClientMeta` that
+//! `ServerSocket::for_each_client` and `ServerSocketInner::on_disconnect` receive, and the totals
+//! of all connections are part of `ServerSocketInner::snapshot`. axum's WebSocket doesn't support
+//! the `permessage-deflate` extension yet, so compression is never negotiated and both counters are
+//! equal. They still tell you how much traffic a workload produces and whether compressing it would
+//! be worth the CPU.
+//!
+//! ### Patches
+//!
+//! With the `patch` feature enabled, large messages don't have to be resent on every change.
+//! `ServerSocketInner::send_patch` and `SocketContext::send_patch` send a JSON patch (RFC 6902)
+//! that is applied to the retained message of the key. The server checks that it applies cleanly
+//! before it forwards it, and the subscribers receive the patched message as usual.
+//!
+//! ### Testing Components
+//!
+//! With the `testing` feature enabled, `test::MockSocket` can be provided instead of the real socket
+//! context. It records everything your components send and calls their subscriptions with the
+//! messages you `emit`, without a websocket or a server.
 
 pub mod channel;
 #[cfg(feature = "ssr")]
 pub mod handlers;
+#[cfg(all(feature = "testing", not(feature = "ssr")))]
+pub mod test;
 
 pub use crate::channel::*;
+#[cfg(all(feature = "ssr", feature = "metrics"))]
+pub use crate::handlers::metrics::metrics_handler;
 
 /// Implement this trait to link your socket message types to your key types.
 /// In order to use this crate you have to implement this trait for your socket messages.
 ///
-/// On the server you have to provide the application state as well.
+/// It's the same in the client and the server build, so the message types can live in a crate that
+/// is shared by both. To send with [`send`] or [`send_to_self`] from a server function, implement
+/// `ServerSocketMsg` as well.
 ///
 /// ```
-/// # use leptos_axum_socket::{ServerSocket, SocketMsg};
+/// # use leptos_axum_socket::SocketMsg;
 /// # use serde::{Serialize, Deserialize};
-/// # use axum::extract::FromRef;
-/// #
-/// # #[derive(FromRef, Clone)]
-/// # pub struct AppState {
-/// #     pub socket: ServerSocket,
-/// # }
 /// #
 /// // Define the key and message types
 /// #[derive(Clone, Serialize, Deserialize)]
@@ -246,16 +392,119 @@ pub use crate::channel::*;
 /// // Implement the SocketMsg trait for MyMsg to link the key and message types
 /// impl SocketMsg for MyMsg {
 ///     type Key = MyKey;
-///     #[cfg(feature = "ssr")]
-///     type AppState = AppState;
 /// }
 /// ```
+///
+/// If you have many message types, [`socket_msgs!`] implements this trait and `ServerSocketMsg` for
+/// all of them with the app state written only once.
 pub trait SocketMsg {
     type Key;
-    #[cfg(feature = "ssr")]
+}
+
+/// The server side of [`SocketMsg`] that links a message type to the app state that provides the
+/// [`ServerSocket`]. It's needed by [`send`] and [`send_to_self`] to find the socket in the context
+/// of a server function. Only available with the `ssr` feature, so implement it under the same
+/// condition.
+///
+/// ```
+/// # use leptos_axum_socket::{ServerSocket, ServerSocketMsg, SocketMsg};
+/// # use serde::{Serialize, Deserialize};
+/// # use axum::extract::FromRef;
+/// #
+/// # #[derive(FromRef, Clone)]
+/// # pub struct AppState {
+/// #     pub socket: ServerSocket,
+/// # }
+/// #
+/// # #[derive(Clone, Serialize, Deserialize)]
+/// # pub struct MyKey;
+/// #
+/// # #[derive(Clone, Serialize, Deserialize, Debug)]
+/// # pub struct MyMsg;
+/// #
+/// impl SocketMsg for MyMsg {
+///     type Key = MyKey;
+/// }
+///
+/// #[cfg(feature = "ssr")]
+/// impl ServerSocketMsg for MyMsg {
+///     type AppState = AppState;
+/// }
+/// ```
+#[cfg(feature = "ssr")]
+pub trait ServerSocketMsg: SocketMsg {
     type AppState;
 }
 
+/// Implements [`SocketMsg`] and `ServerSocketMsg` for several message types that share the same app
+/// state, so that `type AppState` doesn't have to be repeated in every impl.
+///
+/// Each line links a message type to its key type. `ServerSocketMsg` is only implemented when the
+/// `ssr` feature of this crate is enabled, so you don't have to gate it yourself.
+///
+/// ```
+/// # use leptos_axum_socket::{ServerSocket, SocketMsg, socket_msgs};
+/// # use serde::{Serialize, Deserialize};
+/// # use axum::extract::FromRef;
+/// #
+/// # #[derive(FromRef, Clone)]
+/// # pub struct AppState {
+/// #     pub socket: ServerSocket,
+/// # }
+/// #
+/// #[derive(Clone, Serialize, Deserialize)]
+/// pub struct ChatKey(pub u32);
+///
+/// #[derive(Clone, Serialize, Deserialize, Debug)]
+/// pub struct ChatMsg(pub String);
+///
+/// #[derive(Clone, Serialize, Deserialize, Debug)]
+/// pub struct Typing(pub bool);
+///
+/// socket_msgs! {
+///     type AppState = AppState;
+///
+///     ChatMsg => ChatKey;
+///     Typing => ChatKey;
+/// }
+/// #
+/// # fn app_state<Msg: leptos_axum_socket::ServerSocketMsg<AppState = AppState>>() {}
+/// # app_state::<ChatMsg>();
+/// # app_state::<Typing>();
+/// ```
+#[cfg(feature = "ssr")]
+#[macro_export]
+macro_rules! socket_msgs {
+    (type AppState = $app_state:ty; $($msg:ty => $key:ty);* $(;)?) => {
+        $(
+            impl $crate::SocketMsg for $msg {
+                type Key = $key;
+            }
+
+            impl $crate::ServerSocketMsg for $msg {
+                type AppState = $app_state;
+            }
+        )*
+    };
+}
+
+/// Implements [`SocketMsg`] and `ServerSocketMsg` for several message types that share the same app
+/// state, so that `type AppState` doesn't have to be repeated in every impl.
+///
+/// Each line links a message type to its key type. `ServerSocketMsg` is only implemented when the
+/// `ssr` feature of this crate is enabled, so you don't have to gate it yourself.
+#[cfg(not(feature = "ssr"))]
+#[macro_export]
+macro_rules! socket_msgs {
+    (type AppState = $app_state:ty; $($msg:ty => $key:ty);* $(;)?) => {
+        $(
+            impl $crate::SocketMsg for $msg {
+                type Key = $key;
+            }
+        )*
+    };
+}
+
 /// Trait to extend the Axum router
 #[cfg(feature = "ssr")]
 pub trait SocketRoute<S>
@@ -267,6 +516,56 @@ where
     where
         H: axum::handler::Handler<T, S>,
         T: 'static;
+
+    /// Like [`SocketRoute::socket_route`] but `configure` can change the route before it's added,
+    /// for example to apply tower middleware only to the socket endpoint.
+    ///
+    /// ```
+    /// # use axum::{
+    /// #     Router,
+    /// #     extract::{Request, State, WebSocketUpgrade},
+    /// #     http::{StatusCode, header},
+    /// #     middleware::{self, Next},
+    /// #     response::{IntoResponse, Response},
+    /// # };
+    /// # use leptos_axum_socket::{ServerSocket, SocketRoute, handlers::upgrade_websocket};
+    /// #
+    /// # async fn connect_to_websocket(ws: WebSocketUpgrade, State(socket): State<ServerSocket>) -> Response {
+    /// #     upgrade_websocket(ws, socket, ())
+    /// # }
+    /// #
+    /// async fn require_session(request: Request, next: Next) -> Response {
+    ///     if request.headers().contains_key(header::COOKIE) {
+    ///         next.run(request).await
+    ///     } else {
+    ///         StatusCode::UNAUTHORIZED.into_response()
+    ///     }
+    /// }
+    ///
+    /// let app: Router<ServerSocket> = Router::new().socket_route_with(connect_to_websocket, |route| {
+    ///     route.layer(middleware::from_fn(require_session))
+    /// });
+    /// ```
+    fn socket_route_with<H, T, F>(self, handler: H, configure: F) -> Self
+    where
+        H: axum::handler::Handler<T, S>,
+        T: 'static,
+        F: FnOnce(axum::routing::MethodRouter<S>) -> axum::routing::MethodRouter<S>;
+
+    /// Add the routes for the long-polling fallback to the Axum router.
+    ///
+    /// The `send_handler` is called for every message a long-polling client sends.
+    /// See [`handlers::longpoll::handle_longpoll_send`] for how to implement it.
+    #[cfg(feature = "longpoll")]
+    fn longpoll_route<H, T>(self, send_handler: H) -> Self
+    where
+        H: axum::handler::Handler<T, S>,
+        T: 'static;
+
+    /// Add the route that serves the Prometheus metrics of the socket on [`METRICS_URL`].
+    /// See [`handlers::metrics`] for the exported metrics.
+    #[cfg(feature = "metrics")]
+    fn metrics_route(self) -> Self;
 }
 
 #[cfg(feature = "ssr")]
@@ -279,12 +578,46 @@ where
     where
         H: axum::handler::Handler<T, S>,
         T: 'static,
+    {
+        self.socket_route_with(handler, |route| route)
+    }
+
+    fn socket_route_with<H, T, F>(self, handler: H, configure: F) -> Self
+    where
+        H: axum::handler::Handler<T, S>,
+        T: 'static,
+        F: FnOnce(axum::routing::MethodRouter<S>) -> axum::routing::MethodRouter<S>,
     {
         use axum::routing::get;
         use tracing::debug;
 
         debug!("Adding websocket route to {WEBSOCKET_CHANNEL_URL}");
 
-        self.route(WEBSOCKET_CHANNEL_URL, get(handler))
+        self.route(WEBSOCKET_CHANNEL_URL, configure(get(handler)))
+    }
+
+    #[cfg(feature = "longpoll")]
+    fn longpoll_route<H, T>(self, send_handler: H) -> Self
+    where
+        H: axum::handler::Handler<T, S>,
+        T: 'static,
+    {
+        use axum::routing::{get, post};
+        use tracing::debug;
+
+        debug!("Adding long-polling routes to {LONGPOLL_SEND_URL} and {LONGPOLL_POLL_URL}");
+
+        self.route(LONGPOLL_SEND_URL, post(send_handler))
+            .route(LONGPOLL_POLL_URL, get(handlers::longpoll::poll))
+    }
+
+    #[cfg(feature = "metrics")]
+    fn metrics_route(self) -> Self {
+        use axum::routing::get;
+        use tracing::debug;
+
+        debug!("Adding metrics route to {METRICS_URL}");
+
+        self.route(METRICS_URL, get(metrics_handler))
     }
 }
@@ -0,0 +1,143 @@
+//! Test helpers for components that use the socket context. Requires the `testing` feature.
+//!
+//! [`MockSocket`] is a [`SocketContext`] without a websocket. It records every frame that would be
+//! sent to the server and lets you feed messages to the subscriptions with [`MockSocket::emit`].
+//! Like the rest of the client, it only works if the `ssr` feature is disabled.
+//!
+//! Provide the mock instead of calling [`provide_socket_context`](crate::provide_socket_context) so
+//! that `expect_socket_context` returns it:
+//!
+//! ```ignore
+//! use leptos::prelude::*;
+//! use leptos_axum_socket::{expect_socket_context, test::MockSocket};
+//!
+//! #[test]
+//! fn shows_the_latest_message() {
+//!     let owner = Owner::new();
+//!     owner.set();
+//!
+//!     let socket = MockSocket::new();
+//!     socket.provide();
+//!
+//!     let latest = RwSignal::new(String::new());
+//!     expect_socket_context().subscribe(ChatKey { room_id: 1 }, move |msg: &ChatMsg| {
+//!         latest.set(msg.0.clone());
+//!     });
+//!
+//!     socket.emit(&ChatKey { room_id: 1 }, &ChatMsg("hello".to_string()));
+//!     assert_eq!(latest.get_untracked(), "hello");
+//!
+//!     expect_socket_context().send(ChatKey { room_id: 1 }, ChatMsg("hi".to_string()));
+//!     assert_eq!(socket.sent_msgs::<ChatMsg>().len(), 1);
+//! }
+//! ```
+
+use std::sync::Arc;
+
+use leptos::prelude::*;
+use leptos_use::core::ConnectionReadyState;
+use serde::{Deserialize, Serialize};
+
+use crate::{ChannelMsg, SocketContext, SocketMsg, codec};
+
+/// A [`SocketContext`] that isn't connected to a server. See the [module docs](self).
+///
+/// The connection is always open so subscriptions are recorded as `Subscribe` frames right away.
+#[derive(Clone, Copy)]
+pub struct MockSocket {
+    context: SocketContext,
+    sent: StoredValue<Vec<ChannelMsg>>,
+}
+
+impl Default for MockSocket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockSocket {
+    /// Creates a new mock. Like all reactive values it belongs to the current owner.
+    pub fn new() -> Self {
+        let sent = StoredValue::new(Vec::new());
+
+        let context = SocketContext::with_transport(
+            Signal::stored(None),
+            Arc::new(move |msg: &ChannelMsg| sent.write_value().push(msg.clone())),
+            Signal::stored(ConnectionReadyState::Open),
+            Arc::new(|| ()),
+            Arc::new(|| ()),
+            RwSignal::new(None),
+        );
+
+        Self { context, sent }
+    }
+
+    /// Provides the mock as the socket context so that `expect_socket_context` returns it.
+    pub fn provide(self) {
+        provide_context(self.context);
+    }
+
+    /// Returns the context that is backed by this mock.
+    pub fn context(self) -> SocketContext {
+        self.context
+    }
+
+    /// Calls the handlers that are subscribed to `key` with `msg` as if the server sent it. The
+    /// handlers are called synchronously before this returns.
+    pub fn emit<Msg>(self, key: &Msg::Key, msg: &Msg)
+    where
+        Msg: SocketMsg + Serialize,
+        Msg::Key: Serialize,
+    {
+        let frame = ChannelMsg::Msg {
+            key: codec::to_key(key).expect("Failed to serialize key"),
+            msg: codec::to_value(msg).expect("Failed to serialize message"),
+            id: None,
+            sender: None,
+            seq: None,
+        };
+
+        self.context.receive_frame(&frame);
+    }
+
+    /// Returns all frames that were sent to the server in the order they were sent, including
+    /// subscriptions.
+    pub fn sent(self) -> Vec<ChannelMsg> {
+        self.sent.get_value()
+    }
+
+    /// Returns the keys and messages of type `Msg` that were sent. Messages of other types are
+    /// skipped.
+    pub fn sent_msgs<Msg>(self) -> Vec<(Msg::Key, Msg)>
+    where
+        Msg: SocketMsg,
+        for<'de> Msg: Deserialize<'de>,
+        for<'de> Msg::Key: Deserialize<'de>,
+    {
+        self.sent.with_value(|sent| {
+            sent.iter()
+                .filter_map(|frame| match frame {
+                    ChannelMsg::Msg { key, msg, .. } => Some((
+                        Msg::Key::deserialize(key.as_value()).ok()?,
+                        Msg::deserialize(msg).ok()?,
+                    )),
+                    _ => None,
+                })
+                .collect()
+        })
+    }
+
+    /// Forgets the frames that were sent so far.
+    pub fn clear_sent(self) {
+        self.sent.write_value().clear();
+    }
+
+    /// Returns `true` if any handler is subscribed to `key`.
+    pub fn is_subscribed<Key>(self, key: &Key) -> bool
+    where
+        Key: Serialize,
+    {
+        let key = codec::to_key(key).expect("Failed to serialize key");
+        self.context.has_subscriptions(&key)
+    }
+}
@@ -0,0 +1,1347 @@
+#![cfg(feature = "ssr")]
+
+use std::time::Duration;
+
+use axum::extract::FromRef;
+use futures_channel::mpsc;
+use futures_util::StreamExt;
+use leptos_axum_socket::{
+    ServerSocket, SocketMsg,
+    handlers::{CloseFrame, handle_connection},
+};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+#[derive(FromRef, Clone)]
+pub struct AppState {
+    pub socket: ServerSocket,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+pub struct TheKey;
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TheMessage(String);
+
+impl SocketMsg for TheMessage {
+    type Key = TheKey;
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RoomMessage(String);
+
+impl SocketMsg for RoomMessage {
+    type Key = String;
+}
+
+/// A client that talks to the socket over channels, as opened by [`connect`].
+struct TestClient {
+    incoming_tx: mpsc::UnboundedSender<String>,
+    outgoing_rx: mpsc::UnboundedReceiver<String>,
+    connection: JoinHandle<()>,
+}
+
+impl TestClient {
+    /// Sends a frame to the server.
+    fn send(&self, frame: impl Into<String>) {
+        self.incoming_tx.unbounded_send(frame.into()).unwrap();
+    }
+
+    /// Waits for the next frame from the server.
+    async fn next_frame(&mut self) -> String {
+        tokio::time::timeout(Duration::from_secs(5), self.outgoing_rx.next())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    /// Disconnects and waits until the server has cleaned up the connection.
+    async fn disconnect(self) {
+        drop(self.incoming_tx);
+        tokio::time::timeout(Duration::from_secs(5), self.connection)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
+
+/// Connects a new client to the socket without any context.
+fn connect(socket: &ServerSocket) -> TestClient {
+    connect_with(socket, Uuid::new_v4(), ())
+}
+
+/// Connects a client with the given ID and context to the socket.
+fn connect_with<C>(socket: &ServerSocket, client_id: Uuid, context: C) -> TestClient
+where
+    C: Send + Sync + 'static,
+{
+    let (incoming_tx, incoming_rx) = mpsc::unbounded::<String>();
+    let (outgoing_tx, outgoing_rx) = mpsc::unbounded::<String>();
+
+    let connection = tokio::spawn(handle_connection(
+        incoming_rx,
+        outgoing_tx,
+        socket.clone(),
+        client_id,
+        context,
+    ));
+
+    TestClient {
+        incoming_tx,
+        outgoing_rx,
+        connection,
+    }
+}
+
+#[tokio::test]
+async fn malformed_frames_are_ignored() {
+    let socket = ServerSocket::new();
+    let client_id = Uuid::new_v4();
+
+    let mut client = connect_with(&socket, client_id, ());
+
+    // The client ID is delivered in the first frame
+    let welcome = client.next_frame().await;
+    assert!(welcome.contains(&client_id.to_string()));
+
+    client.send("this is not a frame");
+    client.send(r#"{"Subscribe":{"key":null}}"#);
+
+    assert!(
+        socket
+            .wait_for_subscriber(&TheKey, Duration::from_secs(5))
+            .await
+    );
+
+    let subscribed = client.next_frame().await;
+    assert_eq!(subscribed, r#"{"SubscribeResult":{"key":null}}"#);
+
+    let receiver_count = socket
+        .lock()
+        .await
+        .send(&TheKey, &TheMessage("hello".to_string()));
+    assert_eq!(receiver_count, 1);
+
+    let frame = client.next_frame().await;
+    assert!(frame.contains("hello"));
+    assert!(socket.lock().await.is_client_connected(client_id));
+
+    // The connection is torn down and cleaned up once the client disconnects
+    client.disconnect().await;
+
+    assert!(socket.lock().await.snapshot().client_ids.is_empty());
+    assert!(!socket.lock().await.is_client_connected(client_id));
+}
+
+#[tokio::test]
+async fn remapped_subscribers_receive_the_new_key() {
+    let socket = ServerSocket::new();
+
+    let mut client = connect(&socket);
+
+    client.send(r#"{"Subscribe":{"key":"old"}}"#);
+
+    assert!(
+        socket
+            .wait_for_subscriber(&"old", Duration::from_secs(5))
+            .await
+    );
+
+    socket.lock().await.remap_key(&"old", &"new").await;
+    socket
+        .lock()
+        .await
+        .send(&"new".to_string(), &RoomMessage("hello".to_string()));
+
+    let mut frames = vec![];
+    while frames.len() < 4 {
+        let frame = client.next_frame().await;
+        frames.push(frame);
+    }
+
+    assert!(frames[0].contains("Welcome"));
+    assert!(frames[1].contains("SubscribeResult"));
+    assert!(frames[2].contains("KeyRemapped"));
+    assert!(frames[3].contains("hello") && frames[3].contains("new"));
+}
+
+#[tokio::test]
+async fn clients_subscribed_to_both_remapped_keys_are_notified_once() {
+    let socket = ServerSocket::new();
+
+    let mut client = connect(&socket);
+
+    // Welcome
+    client.next_frame().await;
+
+    for key in ["old", "new"] {
+        client.send(format!(r#"{{"Subscribe":{{"key":"{key}"}}}}"#));
+        assert!(client.next_frame().await.contains("SubscribeResult"));
+    }
+
+    socket.lock().await.remap_key(&"old", &"new").await;
+    socket
+        .lock()
+        .await
+        .send(&"new".to_string(), &RoomMessage("hello".to_string()));
+    socket
+        .lock()
+        .await
+        .send(&"new".to_string(), &RoomMessage("bye".to_string()));
+
+    let mut frames = vec![];
+    while !frames
+        .last()
+        .is_some_and(|frame: &String| frame.contains("bye"))
+    {
+        frames.push(client.next_frame().await);
+    }
+
+    assert_eq!(
+        frames
+            .iter()
+            .filter(|frame| frame.contains("KeyRemapped"))
+            .count(),
+        1,
+        "{frames:?}"
+    );
+    assert_eq!(frames.len(), 3, "{frames:?}");
+}
+
+#[tokio::test]
+async fn types_are_subscribed_by_their_registered_name() {
+    let socket = ServerSocket::new();
+    socket
+        .lock()
+        .await
+        .register_type::<RoomMessage>("room_message");
+
+    let mut client = connect(&socket);
+
+    // Welcome
+    client.next_frame().await;
+
+    client.send(r#"{"Subscribe":{"key":{"$type":"room_message"}}}"#);
+    assert!(client.next_frame().await.contains("SubscribeResult"));
+
+    socket
+        .lock()
+        .await
+        .send(&"lobby".to_string(), &RoomMessage("hello".to_string()));
+
+    let mut frame: serde_json::Value = serde_json::from_str(&client.next_frame().await).unwrap();
+    if let Some(msg) = frame.get_mut("Reliable") {
+        frame = msg["msg"].take();
+    }
+    assert_eq!(
+        frame["PathMsg"],
+        serde_json::json!({"key": {"$type": "room_message"}, "path": "lobby", "msg": "hello"})
+    );
+}
+
+#[tokio::test]
+async fn subscriber_counts_are_streamed() {
+    let socket = ServerSocket::new();
+    let mut counts = socket.subscribe_count_stream().await;
+
+    let client = connect(&socket);
+
+    client.send(r#"{"Subscribe":{"key":"room"}}"#);
+
+    let subscribed = tokio::time::timeout(Duration::from_secs(5), counts.next())
+        .await
+        .unwrap();
+    assert_eq!(subscribed, Some(("room".into(), 1)));
+
+    client.disconnect().await;
+
+    let disconnected = tokio::time::timeout(Duration::from_secs(5), counts.next())
+        .await
+        .unwrap();
+    assert_eq!(disconnected, Some(("room".into(), 0)));
+}
+
+#[tokio::test]
+async fn closed_clients_are_disconnected() {
+    let socket = ServerSocket::new();
+    let client_id = Uuid::new_v4();
+
+    let mut client = connect_with(&socket, client_id, ());
+
+    // Welcome
+    client.next_frame().await;
+
+    let connected_since = socket.lock().await.connected_since(client_id).unwrap();
+    assert!(connected_since.elapsed() < Duration::from_secs(5));
+
+    assert!(
+        socket
+            .lock()
+            .await
+            .close_client(client_id, CloseFrame::new(4000, "kicked"))
+    );
+
+    // The connection ends although the client didn't disconnect
+    tokio::time::timeout(Duration::from_secs(5), client.connection)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(client.outgoing_rx.next().await, None);
+    assert!(!socket.lock().await.is_client_connected(client_id));
+    assert_eq!(socket.lock().await.connected_since(client_id), None);
+}
+
+#[tokio::test]
+async fn removed_keys_evict_their_subscribers() {
+    let socket = ServerSocket::new();
+    let client_id = Uuid::new_v4();
+
+    let client = connect_with(&socket, client_id, ());
+
+    client.send(r#"{"Subscribe":{"key":"room"}}"#);
+
+    assert!(
+        socket
+            .wait_for_subscriber(&"room", Duration::from_secs(5))
+            .await
+    );
+    assert!(socket.lock().await.key_exists(&"room"));
+
+    let evicted = socket.lock().await.remove_key(&"room");
+    assert_eq!(evicted, vec![client_id]);
+
+    let mut socket = socket.lock().await;
+    assert!(!socket.key_exists(&"room"));
+    assert!(socket.snapshot().keys.is_empty());
+    assert_eq!(
+        socket.send(&"room".to_string(), &RoomMessage("hello".to_string())),
+        0
+    );
+}
+
+#[tokio::test]
+async fn senders_are_stamped_by_the_server() {
+    let socket = ServerSocket::new();
+    socket.lock().await.stamp_sender();
+
+    let client_id = Uuid::new_v4();
+    let forged_id = Uuid::new_v4();
+
+    let mut client = connect_with(&socket, client_id, ());
+
+    // Welcome
+    client.next_frame().await;
+
+    client.send(r#"{"Subscribe":{"key":"room"}}"#);
+
+    assert!(
+        socket
+            .wait_for_subscriber(&"room", Duration::from_secs(5))
+            .await
+    );
+
+    // SubscribeResult
+    client.next_frame().await;
+
+    client.send(format!(
+        r#"{{"Msg":{{"key":"room","msg":"hello","sender":{{"client_id":"{forged_id}"}}}}}}"#
+    ));
+
+    let frame = client.next_frame().await;
+
+    assert!(frame.contains("hello"));
+    assert!(frame.contains(&client_id.to_string()));
+    assert!(!frame.contains(&forged_id.to_string()));
+}
+
+#[cfg(feature = "metrics")]
+#[tokio::test]
+async fn metrics_are_rendered_for_prometheus() {
+    use axum::extract::State;
+    use leptos_axum_socket::metrics_handler;
+
+    let socket = ServerSocket::new();
+    socket
+        .lock()
+        .await
+        .send(&"room".to_string(), &RoomMessage("hello".to_string()));
+
+    let response = metrics_handler(State(socket)).await;
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+
+    assert!(body.contains("leptos_axum_socket_connections 0\n"));
+    assert!(body.contains("leptos_axum_socket_keys 1\n"));
+    assert!(body.contains(r#"leptos_axum_socket_subscribers{key="\"room\""} 0"#));
+    assert!(body.contains("leptos_axum_socket_messages_sent_total 1\n"));
+}
+
+#[tokio::test]
+async fn subscription_filters_are_applied_per_subscriber() {
+    let socket = ServerSocket::new();
+    socket
+        .lock()
+        .await
+        .add_subscription_filter_evaluator(|prefix: &String, msg: &RoomMessage| {
+            msg.0.starts_with(prefix.as_str())
+        });
+
+    let mut client = connect(&socket);
+
+    // Welcome
+    client.next_frame().await;
+
+    client.send(r#"{"Subscribe":{"key":"room","filter":"warn"}}"#);
+
+    assert!(
+        socket
+            .wait_for_subscriber(&"room", Duration::from_secs(5))
+            .await
+    );
+
+    // SubscribeResult
+    client.next_frame().await;
+
+    let room = "room".to_string();
+    let mut socket = socket.lock().await;
+    socket.send(&room, &RoomMessage("info: skipped".to_string()));
+    socket.send(&room, &RoomMessage("warn: forwarded".to_string()));
+
+    let frame = client.next_frame().await;
+    assert!(frame.contains("warn: forwarded"));
+}
+
+#[tokio::test]
+async fn wire_frames_are_captured_as_sent() {
+    use std::sync::{Arc, Mutex};
+
+    use leptos_axum_socket::Direction;
+
+    let socket = ServerSocket::new();
+    let frames = Arc::new(Mutex::new(Vec::new()));
+    socket.lock().await.on_wire_frame({
+        let frames = Arc::clone(&frames);
+        move |direction, bytes: &[u8]| frames.lock().unwrap().push((direction, bytes.to_vec()))
+    });
+
+    let client_id = Uuid::new_v4();
+
+    let mut client = connect_with(&socket, client_id, ());
+
+    let welcome = client.next_frame().await;
+
+    let subscribe = r#"{"Subscribe":{"key":"room"}}"#;
+    client.send(subscribe);
+
+    assert!(
+        socket
+            .wait_for_subscriber(&"room", Duration::from_secs(5))
+            .await
+    );
+
+    let subscribed = client.next_frame().await;
+
+    socket
+        .lock()
+        .await
+        .send(&"room".to_string(), &RoomMessage("hello".to_string()));
+
+    let msg = client.next_frame().await;
+
+    assert_eq!(
+        *frames.lock().unwrap(),
+        [
+            (Direction::Out, welcome.into_bytes()),
+            (Direction::In, subscribe.as_bytes().to_vec()),
+            (Direction::Out, subscribed.into_bytes()),
+            (Direction::Out, msg.into_bytes()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn messages_are_numbered_per_key() {
+    let socket = ServerSocket::new();
+    let client_id = Uuid::new_v4();
+
+    let mut client = connect_with(&socket, client_id, ());
+
+    // Welcome
+    client.next_frame().await;
+
+    client.send(r#"{"Subscribe":{"key":"room"}}"#);
+
+    assert!(
+        socket
+            .wait_for_subscriber(&"room", Duration::from_secs(5))
+            .await
+    );
+
+    // SubscribeResult
+    client.next_frame().await;
+
+    {
+        let mut socket = socket.lock().await;
+        socket.send(&"other".to_string(), &RoomMessage("elsewhere".to_string()));
+        socket.send(&"room".to_string(), &RoomMessage("first".to_string()));
+        socket.send(&"room".to_string(), &RoomMessage("second".to_string()));
+    }
+
+    for seq in [1, 2] {
+        let frame = client.next_frame().await;
+
+        assert!(frame.contains(&format!(r#""seq":{seq}"#)), "{frame}");
+    }
+}
+
+#[tokio::test]
+async fn lagging_subscribers_receive_a_seq_gap() {
+    let socket = ServerSocket::new();
+    socket.lock().await.set_channel_capacity(2);
+
+    let mut client = connect(&socket);
+
+    // Welcome
+    client.next_frame().await;
+
+    client.send(r#"{"Subscribe":{"key":"room"}}"#);
+    assert!(client.next_frame().await.contains("SubscribeResult"));
+
+    // The subscription can't forward anything while the lock is held, so it falls behind
+    {
+        let mut socket = socket.lock().await;
+        for i in 1..=5 {
+            socket.send(&"room".to_string(), &RoomMessage(i.to_string()));
+        }
+    }
+
+    // The skipped messages show up as a gap instead of ending the subscription
+    assert_eq!(msg_of_frame(&client.next_frame().await)["seq"], 4);
+    assert_eq!(msg_of_frame(&client.next_frame().await)["seq"], 5);
+
+    socket
+        .lock()
+        .await
+        .send(&"room".to_string(), &RoomMessage("6".to_string()));
+    assert_eq!(msg_of_frame(&client.next_frame().await)["seq"], 6);
+}
+
+#[cfg(feature = "patch")]
+#[tokio::test]
+async fn patches_are_applied_to_the_retained_message() {
+    let socket = ServerSocket::new();
+    socket
+        .lock()
+        .await
+        .send_retained(&"doc".to_string(), &RoomMessage("draft".to_string()));
+
+    let client_id = Uuid::new_v4();
+
+    let mut client = connect_with(&socket, client_id, ());
+
+    // Welcome
+    client.next_frame().await;
+
+    client.send(r#"{"Subscribe":{"key":"doc"}}"#);
+
+    // The retained message is sent along with the acknowledgement
+    assert_eq!(
+        client.next_frame().await,
+        r#"{"SubscribeResult":{"key":"doc","retained":"draft"}}"#
+    );
+
+    client.send(
+        r#"{"Patch":{"key":"doc","patch":[{"op":"replace","path":"","value":"final"}]}}"#
+            .to_string(),
+    );
+
+    let patch = client.next_frame().await;
+    assert!(
+        patch.contains("Patch") && patch.contains("final"),
+        "{patch}"
+    );
+
+    client.send(
+        r#"{"Patch":{"key":"doc","patch":[{"op":"test","path":"","value":"draft"}]}}"#.to_string(),
+    );
+
+    assert!(client.next_frame().await.contains("Invalid"));
+
+    assert_eq!(
+        socket
+            .lock()
+            .await
+            .send_patch::<RoomMessage>(&"doc".to_string(), &serde_json::json!([])),
+        Ok(1)
+    );
+    assert!(client.next_frame().await.contains("Patch"));
+}
+
+#[tokio::test]
+async fn tasks_are_spawned_on_the_given_runtime() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let socket = ServerSocket::with_runtime_handle(runtime.handle().clone());
+    let task = socket.publish_stream(futures_util::stream::pending::<(String, RoomMessage)>());
+
+    // Only tasks of the shut down runtime are cancelled
+    runtime.shutdown_background();
+
+    let result = tokio::time::timeout(Duration::from_secs(5), task)
+        .await
+        .unwrap();
+    assert!(result.unwrap_err().is_cancelled());
+}
+
+#[tokio::test]
+async fn paused_keys_deliver_buffered_messages_on_resume() {
+    use std::sync::{Arc, Mutex};
+
+    use leptos_axum_socket::{DeadLetterReason, KeyPausePolicy};
+
+    let socket = ServerSocket::new();
+    let dead_letters = Arc::new(Mutex::new(Vec::new()));
+    socket.lock().await.on_dead_letter({
+        let dead_letters = Arc::clone(&dead_letters);
+        move |reason, _, msg| dead_letters.lock().unwrap().push((reason, msg.clone()))
+    });
+
+    let mut client = connect(&socket);
+
+    // Welcome
+    client.next_frame().await;
+
+    client.send(r#"{"Subscribe":{"key":"room"}}"#);
+
+    assert!(client.next_frame().await.contains("SubscribeResult"));
+
+    let room = "room".to_string();
+    {
+        let mut socket = socket.lock().await;
+        socket.pause_key(&room, KeyPausePolicy::Buffer { max: 2 });
+
+        for text in ["first", "second", "third"] {
+            assert_eq!(socket.send(&room, &RoomMessage(text.to_string())), 0);
+        }
+
+        assert!(socket.is_key_paused(&room));
+        assert_eq!(socket.resume_key(&room), 2);
+        assert!(!socket.is_key_paused(&room));
+    }
+
+    // The oldest message didn't fit into the buffer
+    assert_eq!(
+        *dead_letters.lock().unwrap(),
+        [(DeadLetterReason::KeyPaused, serde_json::json!("first"))]
+    );
+
+    assert!(client.next_frame().await.contains("second"));
+    assert!(client.next_frame().await.contains("third"));
+}
+
+#[tokio::test]
+async fn messages_received_by_ancestors_are_not_dead_lettered() {
+    use std::sync::{Arc, Mutex};
+
+    use leptos_axum_socket::DeadLetterReason;
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    struct OrgMessage(String);
+
+    impl SocketMsg for OrgMessage {
+        type Key = Vec<String>;
+    }
+
+    let socket = ServerSocket::new();
+    let dead_letters = Arc::new(Mutex::new(Vec::new()));
+    {
+        let mut socket = socket.lock().await;
+        socket.set_hierarchical::<Vec<String>>();
+        socket.on_dead_letter({
+            let dead_letters = Arc::clone(&dead_letters);
+            move |reason, _, msg| dead_letters.lock().unwrap().push((reason, msg.clone()))
+        });
+    }
+
+    let mut client = connect(&socket);
+
+    // Welcome
+    client.next_frame().await;
+
+    client.send(r#"{"Subscribe":{"key":["org"]}}"#);
+    assert!(client.next_frame().await.contains("SubscribeResult"));
+
+    {
+        let mut socket = socket.lock().await;
+        let team = vec!["org".to_string(), "team".to_string()];
+        let other = vec!["other".to_string(), "team".to_string()];
+
+        assert_eq!(socket.send(&team, &OrgMessage("hello".to_string())), 0);
+        assert_eq!(socket.send(&other, &OrgMessage("lost".to_string())), 0);
+    }
+
+    assert!(client.next_frame().await.contains("hello"));
+    assert_eq!(
+        *dead_letters.lock().unwrap(),
+        [(DeadLetterReason::NoReceivers, serde_json::json!("lost"))]
+    );
+}
+
+#[tokio::test]
+async fn retried_sends_are_dead_lettered_for_missing_clients() {
+    use std::sync::{Arc, Mutex};
+
+    use leptos_axum_socket::{DeadLetterReason, ReconnectBackoff};
+
+    let socket = ServerSocket::new();
+    let dead_letters = Arc::new(Mutex::new(Vec::new()));
+    socket.lock().await.on_dead_letter({
+        let dead_letters = Arc::clone(&dead_letters);
+        move |reason, _, msg| dead_letters.lock().unwrap().push((reason, msg.clone()))
+    });
+
+    let client_id = Uuid::new_v4();
+    let mut client = connect_with(&socket, client_id, ());
+
+    // Welcome
+    client.next_frame().await;
+
+    let backoff = ReconnectBackoff::new(Duration::from_millis(1), Duration::from_millis(5));
+
+    assert!(
+        socket
+            .send_to_client_retry(
+                client_id,
+                &TheKey,
+                &TheMessage("hi".to_string()),
+                2,
+                backoff
+            )
+            .await
+    );
+
+    let frame = client.next_frame().await;
+    assert!(frame.contains("hi"));
+
+    assert!(
+        !socket
+            .send_to_client_retry(
+                Uuid::new_v4(),
+                &TheKey,
+                &TheMessage("lost".to_string()),
+                2,
+                backoff,
+            )
+            .await
+    );
+
+    assert_eq!(
+        *dead_letters.lock().unwrap(),
+        [(DeadLetterReason::ClientNotFound, serde_json::json!("lost"))]
+    );
+}
+
+#[tokio::test]
+async fn imported_state_is_sent_to_reconnecting_clients() {
+    use leptos_axum_socket::KeyPausePolicy;
+
+    let primary = ServerSocket::new();
+    let room = "room".to_string();
+    {
+        let mut primary = primary.lock().await;
+        primary.send_retained(&room, &RoomMessage("retained".to_string()));
+        primary.pause_key(&room, KeyPausePolicy::Buffer { max: 10 });
+        primary.send(&room, &RoomMessage("buffered".to_string()));
+    }
+
+    let state = serde_json::to_string(&primary.export_state().await).unwrap();
+
+    let standby = ServerSocket::new();
+    standby
+        .lock()
+        .await
+        .send(&"warmup".to_string(), &RoomMessage("ping".to_string()));
+    standby
+        .import_state(serde_json::from_str(&state).unwrap())
+        .await;
+
+    // The counter continues where the primary left off
+    assert_eq!(standby.lock().await.snapshot().messages_sent, 1);
+
+    let mut client = connect(&standby);
+
+    // Welcome
+    client.next_frame().await;
+
+    client.send(r#"{"Subscribe":{"key":"room"}}"#);
+
+    assert_eq!(
+        client.next_frame().await,
+        r#"{"SubscribeResult":{"key":"room","retained":"retained"}}"#
+    );
+
+    assert!(standby.lock().await.is_key_paused(&room));
+    assert_eq!(standby.lock().await.resume_key(&room), 1);
+    assert!(client.next_frame().await.contains("buffered"));
+}
+
+#[tokio::test]
+async fn many_keys_are_answered_with_one_result() {
+    let socket = ServerSocket::new();
+    {
+        let mut socket = socket.lock().await;
+        socket.set_max_subscriptions_per_client(2);
+        socket.send_retained(&"a".to_string(), &RoomMessage("retained".to_string()));
+    }
+
+    let mut client = connect(&socket);
+
+    // Welcome
+    client.next_frame().await;
+
+    client.send(r#"{"SubscribeMany":{"keys":["a","b","a","c"]}}"#);
+
+    assert_eq!(
+        client.next_frame().await,
+        concat!(
+            r#"{"SubscribeManyResult":{"results":["#,
+            r#"{"SubscribeResult":{"key":"a","retained":"retained"}},"#,
+            r#"{"SubscribeResult":{"key":"b"}},"#,
+            r#"{"SubscribeDenied":{"key":"c","reason":"limit"}}"#,
+            r#"]}}"#
+        )
+    );
+
+    assert_eq!(
+        socket
+            .lock()
+            .await
+            .send(&"b".to_string(), &RoomMessage("live".to_string())),
+        1
+    );
+    assert!(client.next_frame().await.contains("live"));
+}
+
+#[tokio::test]
+async fn disconnects_are_reported_with_the_subscriptions() {
+    use std::sync::{Arc, Mutex};
+
+    let socket = ServerSocket::new();
+    let disconnects = Arc::new(Mutex::new(Vec::new()));
+    socket.lock().await.on_disconnect({
+        let disconnects = Arc::clone(&disconnects);
+        move |info| disconnects.lock().unwrap().push(info.clone())
+    });
+
+    let client_id = Uuid::new_v4();
+    let mut client = connect_with(&socket, client_id, ());
+
+    // Welcome
+    client.next_frame().await;
+
+    client.send(r#"{"Subscribe":{"key":"room"}}"#);
+    assert!(client.next_frame().await.contains("SubscribeResult"));
+
+    assert!(disconnects.lock().unwrap().is_empty());
+
+    client.disconnect().await;
+
+    let disconnects = disconnects.lock().unwrap();
+    assert_eq!(disconnects.len(), 1);
+    assert_eq!(disconnects[0].client_id, client_id);
+    assert_eq!(
+        disconnects[0].meta.keys.iter().collect::<Vec<_>>(),
+        [&leptos_axum_socket::CanonicalKey::new(serde_json::json!(
+            "room"
+        ))]
+    );
+}
+
+#[cfg(feature = "longpoll")]
+#[tokio::test]
+async fn connections_above_the_maximum_are_rejected() {
+    use axum::{
+        extract::State,
+        http::{HeaderMap, StatusCode, header::SET_COOKIE},
+    };
+    use leptos_axum_socket::handlers::longpoll::poll;
+
+    let socket = ServerSocket::builder().max_connections(1).build();
+
+    let response = poll(State(socket.clone()), HeaderMap::new()).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = poll(State(socket.clone()), HeaderMap::new()).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(response.headers().get(SET_COOKIE).is_none());
+}
+
+#[tokio::test]
+async fn custom_transports_count_towards_the_connections() {
+    let socket = ServerSocket::builder().max_connections(2).build();
+    let client_id = Uuid::new_v4();
+
+    let mut client = connect_with(&socket, client_id, ());
+
+    // Welcome
+    client.next_frame().await;
+
+    // The ID is taken
+    let mut rejected = connect_with(&socket, client_id, ());
+    assert_eq!(rejected.outgoing_rx.next().await, None);
+
+    let mut other_client = connect(&socket);
+
+    // Welcome
+    other_client.next_frame().await;
+
+    // The maximum is reached
+    let mut rejected = connect(&socket);
+    assert_eq!(rejected.outgoing_rx.next().await, None);
+}
+
+#[tokio::test]
+async fn histories_are_kept_until_flushed() {
+    let socket = ServerSocket::new();
+    let mut socket = socket.lock().await;
+    socket.set_history_len::<String>(2);
+
+    let room = "room".to_string();
+    for text in ["one", "two", "three"] {
+        socket.send(&room, &RoomMessage(text.to_string()));
+    }
+
+    let history = socket.history::<RoomMessage>(&room);
+    assert_eq!(
+        history.iter().map(|msg| msg.0.as_str()).collect::<Vec<_>>(),
+        ["two", "three"]
+    );
+
+    assert_eq!(socket.flush_history(&room), 2);
+    assert!(socket.history::<RoomMessage>(&room).is_empty());
+
+    socket.send(&room, &RoomMessage("four".to_string()));
+    assert_eq!(socket.history::<RoomMessage>(&room).len(), 1);
+}
+
+#[tokio::test]
+async fn histories_are_sent_to_new_subscribers() {
+    let socket = ServerSocket::new();
+    {
+        let mut socket = socket.lock().await;
+        socket.set_history_len::<String>(3);
+
+        let room = "room".to_string();
+        socket.send(&room, &RoomMessage("one".to_string()));
+        socket.send(&room, &RoomMessage("two".to_string()));
+        socket.send_retained(&room, &RoomMessage("topic".to_string()));
+
+        let lobby = "lobby".to_string();
+        socket.send(&lobby, &RoomMessage("topic".to_string()));
+        socket.send_retained(&lobby, &RoomMessage("topic".to_string()));
+        socket.send(&lobby, &RoomMessage("news".to_string()));
+    }
+
+    let mut client = connect(&socket);
+
+    // Welcome
+    client.next_frame().await;
+
+    // The retained message is the last message of the history but is only sent once
+    client.send(r#"{"Subscribe":{"key":"room"}}"#);
+    assert_eq!(
+        client.next_frame().await,
+        r#"{"SubscribeResult":{"key":"room","history":["one","two"],"retained":"topic"}}"#
+    );
+
+    // Messages that were sent after the retained one don't bring it back into the history, while
+    // other messages with the same content stay in it
+    client.send(r#"{"Subscribe":{"key":"lobby"}}"#);
+    assert_eq!(
+        client.next_frame().await,
+        r#"{"SubscribeResult":{"key":"lobby","history":["topic","news"],"retained":"topic"}}"#
+    );
+}
+
+#[tokio::test]
+async fn sync_sends_fail_while_the_socket_is_locked() {
+    let socket = ServerSocket::new();
+    let room = "room".to_string();
+
+    {
+        let _guard = socket.lock().await;
+        assert!(
+            socket
+                .try_send_blocking(&room, &RoomMessage("busy".to_string()))
+                .is_err()
+        );
+    }
+
+    assert!(
+        socket
+            .try_send_blocking(&room, &RoomMessage("free".to_string()))
+            .is_ok()
+    );
+    assert_eq!(socket.lock().await.snapshot().messages_sent, 1);
+}
+
+/// Returns the `Msg` of a frame, unwrapped from `Reliable` if the feature is enabled.
+fn msg_of_frame(frame: &str) -> serde_json::Value {
+    let mut frame: serde_json::Value = serde_json::from_str(frame).unwrap();
+    if let Some(msg) = frame.get_mut("Reliable") {
+        frame = msg["msg"].take();
+    }
+    frame["Msg"].take()
+}
+
+#[tokio::test]
+async fn resent_messages_are_only_echoed_to_the_sender() {
+    let socket = ServerSocket::new();
+
+    let connect_to_room = async || {
+        let mut client = connect(&socket);
+
+        // Welcome
+        client.next_frame().await;
+
+        client.send(r#"{"Subscribe":{"key":"room"}}"#);
+        assert!(client.next_frame().await.contains("SubscribeResult"));
+
+        client
+    };
+
+    let mut client = connect_to_room().await;
+    let mut other_client = connect_to_room().await;
+
+    let id = Uuid::new_v4();
+    let frame = format!(r#"{{"Msg":{{"key":"room","msg":"hello","id":"{id}"}}}}"#);
+
+    client.send(frame.clone());
+    assert_eq!(msg_of_frame(&client.next_frame().await)["seq"], 1);
+    assert_eq!(msg_of_frame(&other_client.next_frame().await)["seq"], 1);
+
+    // Sent again after a reconnect
+    client.send(frame.clone());
+    let echo = msg_of_frame(&client.next_frame().await);
+    assert_eq!(echo["id"], id.to_string());
+    assert!(echo.get("seq").is_none());
+
+    // Another client that happens to use the same ID isn't mistaken for the sender
+    other_client.send(frame);
+    assert_eq!(msg_of_frame(&client.next_frame().await)["seq"], 2);
+    assert_eq!(msg_of_frame(&other_client.next_frame().await)["seq"], 2);
+
+    assert_eq!(socket.lock().await.snapshot().messages_sent, 2);
+}
+
+#[tokio::test]
+async fn filters_request_their_context_from_the_map() {
+    use leptos_axum_socket::ContextMap;
+
+    #[derive(Clone)]
+    struct AllowedRooms(Vec<String>);
+
+    let socket = ServerSocket::new();
+    socket
+        .lock()
+        .await
+        .add_subscribe_filter(|key: String, allowed: AllowedRooms| async move {
+            allowed.0.contains(&key)
+        });
+
+    let mut client = connect_with(
+        &socket,
+        Uuid::new_v4(),
+        ContextMap::new()
+            .with(42_u64)
+            .with(AllowedRooms(vec!["lobby".to_string()])),
+    );
+
+    // Welcome
+    client.next_frame().await;
+
+    client.send(r#"{"Subscribe":{"key":"secret"}}"#);
+    client.send(r#"{"Subscribe":{"key":"lobby"}}"#);
+
+    // The denied subscription isn't answered
+    assert_eq!(
+        client.next_frame().await,
+        r#"{"SubscribeResult":{"key":"lobby"}}"#
+    );
+}
+
+#[cfg(feature = "json-schema")]
+#[tokio::test]
+async fn schemas_are_exported_for_registered_types() {
+    #[derive(Clone, Serialize, Deserialize, Debug, schemars::JsonSchema)]
+    pub struct ScoreMsg {
+        pub points: u32,
+    }
+
+    impl SocketMsg for ScoreMsg {
+        type Key = String;
+    }
+
+    let socket = ServerSocket::new();
+    socket.lock().await.register_schema::<ScoreMsg>();
+
+    let schemas = socket.export_schemas().await;
+
+    assert_eq!(schemas["ScoreMsg"]["key"]["type"], "string");
+    assert_eq!(
+        schemas["ScoreMsg"]["msg"]["properties"]["points"]["type"],
+        "integer"
+    );
+}
+
+#[tokio::test]
+async fn pinned_messages_are_sent_before_the_history() {
+    let socket = ServerSocket::new();
+    let room = "room".to_string();
+    let rules = {
+        let mut socket = socket.lock().await;
+        socket.set_history_len::<String>(10);
+
+        let rules = socket.send_pinned(&room, &RoomMessage("rules".to_string()));
+        socket.send(&room, &RoomMessage("hi".to_string()));
+        socket.send_pinned(&room, &RoomMessage("faq".to_string()));
+        socket.send_retained(&room, &RoomMessage("topic".to_string()));
+        rules
+    };
+
+    let mut client = connect(&socket);
+
+    // Welcome
+    client.next_frame().await;
+
+    client.send(r#"{"Subscribe":{"key":"room"}}"#);
+    // Delivered in this order. The pinned messages aren't repeated in the history.
+    assert_eq!(
+        client.next_frame().await,
+        r#"{"SubscribeResult":{"key":"room","pinned":["rules","faq"],"history":["hi"],"retained":"topic"}}"#
+    );
+
+    {
+        let mut socket = socket.lock().await;
+        assert!(socket.unpin(&room, rules));
+        assert!(!socket.unpin(&room, rules));
+    }
+
+    client.send(r#"{"Unsubscribe":{"key":"room"}}"#);
+    client.send(r#"{"Subscribe":{"key":"room"}}"#);
+    // The unpinned message is part of the history again
+    assert_eq!(
+        client.next_frame().await,
+        r#"{"SubscribeResult":{"key":"room","pinned":["faq"],"history":["rules","hi"],"retained":"topic"}}"#
+    );
+}
+
+#[tokio::test]
+async fn resumed_ids_of_connected_clients_are_not_reused() {
+    use axum::{
+        Router,
+        extract::{State, WebSocketUpgrade},
+        http::HeaderMap,
+        response::Response,
+        routing::get,
+    };
+    use leptos_axum_socket::handlers::{ConnectionOptions, upgrade_websocket_with_options};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    async fn connect_to_websocket(
+        ws: WebSocketUpgrade,
+        State(socket): State<ServerSocket>,
+        headers: HeaderMap,
+    ) -> Response {
+        let options = ConnectionOptions::default().resume_client_id("secret", &headers);
+        upgrade_websocket_with_options(ws, socket, (), options)
+    }
+
+    /// Opens a connection and returns its cookies and the text of the welcome frame.
+    async fn connect(addr: std::net::SocketAddr, cookie: &str) -> (TcpStream, Vec<String>, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "GET /ws HTTP/1.1\r\nHost: {addr}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\
+             Cookie: {cookie}\r\n\r\n"
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut buf = Vec::new();
+        let header_end = loop {
+            let mut chunk = [0; 1024];
+            let len = stream.read(&mut chunk).await.unwrap();
+            buf.extend_from_slice(&chunk[..len]);
+            if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+        };
+
+        let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+        assert!(head.starts_with("HTTP/1.1 101"));
+        let cookies = head
+            .lines()
+            .filter_map(|line| line.strip_prefix("set-cookie: "))
+            .map(|cookie| cookie.split(';').next().unwrap().to_string())
+            .collect();
+
+        // A small unmasked text frame
+        while buf.len() < header_end + 2
+            || buf.len() < header_end + 2 + buf[header_end + 1] as usize
+        {
+            let mut chunk = [0; 1024];
+            let len = stream.read(&mut chunk).await.unwrap();
+            buf.extend_from_slice(&chunk[..len]);
+        }
+        let payload_len = buf[header_end + 1] as usize;
+        let welcome =
+            String::from_utf8_lossy(&buf[header_end + 2..header_end + 2 + payload_len]).to_string();
+
+        (stream, cookies, welcome)
+    }
+
+    let socket = ServerSocket::new();
+    let app = Router::new()
+        .route("/ws", get(connect_to_websocket))
+        .with_state(socket.clone());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let (_first_tab, cookies, welcome) =
+        tokio::time::timeout(Duration::from_secs(5), connect(addr, ""))
+            .await
+            .unwrap();
+    let first_id = cookies[0]
+        .strip_prefix("socket_client_id=")
+        .unwrap()
+        .to_string();
+    assert!(welcome.contains(&first_id));
+
+    // A second tab presents the same cookies while the first one is still connected
+    let (_second_tab, cookies, welcome) =
+        tokio::time::timeout(Duration::from_secs(5), connect(addr, &cookies.join("; ")))
+            .await
+            .unwrap();
+    let second_id = cookies[0].strip_prefix("socket_client_id=").unwrap();
+    assert_ne!(second_id, first_id);
+    assert!(welcome.contains(second_id));
+    assert!(
+        socket
+            .lock()
+            .await
+            .is_client_connected(second_id.parse().unwrap())
+    );
+}
+
+#[tokio::test]
+async fn sent_bytes_are_counted_per_client() {
+    use std::sync::{Arc, Mutex};
+
+    let socket = ServerSocket::new();
+    let disconnects = Arc::new(Mutex::new(Vec::new()));
+    socket.lock().await.on_disconnect({
+        let disconnects = Arc::clone(&disconnects);
+        move |info| disconnects.lock().unwrap().push(info.clone())
+    });
+
+    let mut client = connect(&socket);
+
+    // Welcome
+    let welcome = client.next_frame().await;
+
+    client.send(r#"{"Subscribe":{"key":"room"}}"#);
+    let subscribed = client.next_frame().await;
+    assert!(subscribed.contains("SubscribeResult"));
+
+    socket
+        .lock()
+        .await
+        .send(&"room".to_string(), &RoomMessage("hello".to_string()));
+    let msg = client.next_frame().await;
+    assert!(msg.contains("hello"));
+
+    let bytes_received = (welcome.len() + subscribed.len() + msg.len()) as u64;
+
+    let snapshot = socket.lock().await.snapshot();
+    assert_eq!(snapshot.raw_bytes_sent, bytes_received);
+    assert_eq!(snapshot.wire_bytes_sent, bytes_received);
+
+    client.disconnect().await;
+
+    let disconnects = disconnects.lock().unwrap();
+    assert!(!disconnects[0].meta.compression);
+    assert_eq!(disconnects[0].meta.raw_bytes_sent, bytes_received);
+    assert_eq!(disconnects[0].meta.wire_bytes_sent, bytes_received);
+}
+
+#[tokio::test]
+async fn send_mappers_run_in_the_middleware_chain() {
+    use std::sync::{Arc, Mutex};
+
+    use leptos_axum_socket::{ChannelMsg, MiddlewareFuture, SocketMiddleware};
+
+    /// Records the text of every message a client sends.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<serde_json::Value>>>);
+
+    impl SocketMiddleware for Recorder {
+        fn on_inbound(&self, _client_id: Uuid, frame: ChannelMsg) -> MiddlewareFuture<'_> {
+            if let ChannelMsg::Msg { msg, .. } = &frame {
+                self.0.lock().unwrap().push(msg.clone());
+            }
+            Box::pin(async move { Some(frame) })
+        }
+    }
+
+    let before = Recorder::default();
+    let after = Recorder::default();
+
+    let socket = ServerSocket::new();
+    {
+        let mut socket = socket.lock().await;
+        socket.add_middleware(before.clone());
+        socket.add_send_mapper(|_key: String, msg: RoomMessage, _ctx: &()| {
+            Some(RoomMessage(msg.0.to_uppercase()))
+        });
+        socket.add_middleware(after.clone());
+    }
+
+    let mut client = connect(&socket);
+
+    // Welcome
+    client.next_frame().await;
+
+    client.send(r#"{"Subscribe":{"key":"room"}}"#);
+    assert!(client.next_frame().await.contains("SubscribeResult"));
+
+    client.send(r#"{"Msg":{"key":"room","msg":"hello"}}"#);
+    assert_eq!(msg_of_frame(&client.next_frame().await)["msg"], "HELLO");
+
+    assert_eq!(*before.0.lock().unwrap(), ["hello"]);
+    assert_eq!(*after.0.lock().unwrap(), ["HELLO"]);
+
+    // Only the mapper is removed from the chain
+    socket.lock().await.clear_send_mappers();
+    assert_eq!(socket.lock().await.snapshot().send_mapper_count, 0);
+
+    client.send(r#"{"Msg":{"key":"room","msg":"bye"}}"#);
+    assert_eq!(msg_of_frame(&client.next_frame().await)["msg"], "bye");
+    assert_eq!(*after.0.lock().unwrap(), ["HELLO", "bye"]);
+}
+
+#[test]
+#[should_panic(expected = "The channel capacity must be at least 1")]
+fn zero_channel_capacity_is_rejected_when_it_is_set() {
+    ServerSocket::builder().channel_capacity(0);
+}
@@ -0,0 +1,243 @@
+#![cfg(all(feature = "testing", not(feature = "ssr")))]
+
+use leptos::prelude::*;
+use leptos_axum_socket::{ChannelMsg, SocketMsg, expect_socket_context, test::MockSocket};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+pub struct ChatKey {
+    pub room_id: u32,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+pub struct ChatMsg(String);
+
+impl SocketMsg for ChatMsg {
+    type Key = ChatKey;
+}
+
+#[test]
+fn emitted_messages_reach_the_subscribers() {
+    let owner = Owner::new();
+    owner.set();
+
+    let socket = MockSocket::new();
+    socket.provide();
+
+    let received = RwSignal::new(vec![]);
+    expect_socket_context().subscribe(ChatKey { room_id: 1 }, move |msg: &ChatMsg| {
+        received.write().push(msg.clone());
+    });
+
+    assert!(socket.is_subscribed(&ChatKey { room_id: 1 }));
+    assert!(matches!(
+        socket.sent().as_slice(),
+        [ChannelMsg::Subscribe { .. }]
+    ));
+
+    socket.emit(&ChatKey { room_id: 2 }, &ChatMsg("elsewhere".to_string()));
+    socket.emit(&ChatKey { room_id: 1 }, &ChatMsg("hello".to_string()));
+
+    assert_eq!(received.get_untracked(), [ChatMsg("hello".to_string())]);
+}
+
+#[test]
+fn sent_messages_are_recorded() {
+    let owner = Owner::new();
+    owner.set();
+
+    let socket = MockSocket::new();
+    socket.provide();
+
+    expect_socket_context().send(ChatKey { room_id: 1 }, ChatMsg("hi".to_string()));
+
+    assert_eq!(
+        socket.sent_msgs::<ChatMsg>(),
+        [(ChatKey { room_id: 1 }, ChatMsg("hi".to_string()))]
+    );
+
+    socket.clear_sent();
+    assert!(socket.sent().is_empty());
+}
+
+#[test]
+fn typed_unsubscribe_removes_the_subscription() {
+    let owner = Owner::new();
+    owner.set();
+
+    let socket = MockSocket::new();
+    socket.provide();
+
+    let context = expect_socket_context();
+    context.subscribe(ChatKey { room_id: 1 }, |_: &ChatMsg| ());
+    context.unsubscribe_msg::<ChatMsg>(ChatKey { room_id: 1 });
+
+    assert!(!socket.is_subscribed(&ChatKey { room_id: 1 }));
+    assert!(matches!(
+        socket.sent().as_slice(),
+        [ChannelMsg::Subscribe { .. }, ChannelMsg::Unsubscribe { .. }]
+    ));
+}
+
+#[test]
+fn keys_are_subscribed_together_in_one_frame() {
+    let owner = Owner::new();
+    owner.set();
+
+    let socket = MockSocket::new();
+    socket.provide();
+
+    let context = expect_socket_context();
+    context.subscribe(ChatKey { room_id: 1 }, |_: &ChatMsg| ());
+    socket.clear_sent();
+
+    let received = RwSignal::new(vec![]);
+    context.subscribe_many(
+        [1, 2, 3].map(|room_id| ChatKey { room_id }),
+        move |msg: &ChatMsg| received.write().push(msg.clone()),
+    );
+
+    // The first key was subscribed already
+    match socket.sent().as_slice() {
+        [ChannelMsg::SubscribeMany { keys }] => assert_eq!(keys.len(), 2),
+        sent => panic!("unexpected frames: {sent:?}"),
+    }
+
+    socket.emit(&ChatKey { room_id: 1 }, &ChatMsg("one".to_string()));
+    socket.emit(&ChatKey { room_id: 3 }, &ChatMsg("three".to_string()));
+
+    assert_eq!(
+        received.get_untracked(),
+        [ChatMsg("one".to_string()), ChatMsg("three".to_string())]
+    );
+}
+
+#[test]
+fn message_streams_yield_messages_until_dropped() {
+    use std::{
+        pin::Pin,
+        task::{Context, Poll, Waker},
+    };
+
+    use futures_core::Stream;
+
+    let owner = Owner::new();
+    owner.set();
+
+    let socket = MockSocket::new();
+    socket.provide();
+
+    let mut messages = expect_socket_context().messages_stream::<ChatMsg>(ChatKey { room_id: 1 });
+    let mut poll_next =
+        || Pin::new(&mut messages).poll_next(&mut Context::from_waker(Waker::noop()));
+
+    assert_eq!(poll_next(), Poll::Pending);
+
+    socket.emit(&ChatKey { room_id: 1 }, &ChatMsg("hello".to_string()));
+    assert_eq!(poll_next(), Poll::Ready(Some(ChatMsg("hello".to_string()))));
+
+    drop(messages);
+
+    assert!(!socket.is_subscribed(&ChatKey { room_id: 1 }));
+    assert!(matches!(
+        socket.sent().as_slice(),
+        [ChannelMsg::Subscribe { .. }, ChannelMsg::Unsubscribe { .. }]
+    ));
+}
+
+#[test]
+fn components_sharing_a_key_subscribe_it_once() {
+    let owner = Owner::new();
+    owner.set();
+
+    let socket = MockSocket::new();
+    socket.provide();
+
+    let context = expect_socket_context();
+    let received = RwSignal::new(vec![]);
+
+    let first = owner.child();
+    first.with(|| context.subscribe(ChatKey { room_id: 1 }, |_: &ChatMsg| ()));
+
+    let second = owner.child();
+    second.with(|| {
+        context.subscribe(ChatKey { room_id: 1 }, move |msg: &ChatMsg| {
+            received.write().push(msg.clone());
+        })
+    });
+
+    assert!(matches!(
+        socket.sent().as_slice(),
+        [ChannelMsg::Subscribe { .. }]
+    ));
+
+    first.cleanup();
+
+    // The second component is still subscribed
+    assert!(socket.is_subscribed(&ChatKey { room_id: 1 }));
+    socket.emit(&ChatKey { room_id: 1 }, &ChatMsg("hello".to_string()));
+    assert_eq!(received.get_untracked(), [ChatMsg("hello".to_string())]);
+
+    second.cleanup();
+
+    assert!(!socket.is_subscribed(&ChatKey { room_id: 1 }));
+    assert!(matches!(
+        socket.sent().as_slice(),
+        [ChannelMsg::Subscribe { .. }, ChannelMsg::Unsubscribe { .. }]
+    ));
+}
+
+#[test]
+fn namespaced_keys_are_isolated_from_the_host() {
+    use leptos_axum_socket::Namespaced;
+
+    let owner = Owner::new();
+    owner.set();
+
+    let socket = MockSocket::new();
+    socket.provide();
+
+    let context = expect_socket_context().with_namespace("mylib");
+    context.subscribe(ChatKey { room_id: 1 }, |_: &ChatMsg| ());
+
+    assert!(socket.is_subscribed(&Namespaced::new("mylib", ChatKey { room_id: 1 })));
+    assert!(!socket.is_subscribed(&ChatKey { room_id: 1 }));
+
+    socket.clear_sent();
+    context.send(ChatKey { room_id: 1 }, ChatMsg("hi".to_string()));
+
+    match socket.sent().as_slice() {
+        [ChannelMsg::Msg { key, .. }] => assert_eq!(
+            key.as_value(),
+            &serde_json::json!({ "namespace": "mylib", "key": { "room_id": 1 } })
+        ),
+        sent => panic!("unexpected frames: {sent:?}"),
+    }
+}
+
+#[test]
+fn typed_channels_bind_the_message_type() {
+    let owner = Owner::new();
+    owner.set();
+
+    let socket = MockSocket::new();
+    socket.provide();
+
+    let chat = expect_socket_context().channel::<ChatMsg>();
+    let received = RwSignal::new(vec![]);
+
+    chat.subscribe(ChatKey { room_id: 1 }, move |msg| {
+        received.write().push(msg.clone())
+    });
+    socket.emit(&ChatKey { room_id: 1 }, &ChatMsg("hello".to_string()));
+    assert_eq!(received.get_untracked(), [ChatMsg("hello".to_string())]);
+
+    chat.send(ChatKey { room_id: 1 }, ChatMsg("hi".to_string()));
+    assert_eq!(
+        socket.sent_msgs::<ChatMsg>(),
+        [(ChatKey { room_id: 1 }, ChatMsg("hi".to_string()))]
+    );
+
+    chat.unsubscribe(ChatKey { room_id: 1 });
+    assert!(!socket.is_subscribed(&ChatKey { room_id: 1 }));
+}
@@ -0,0 +1,57 @@
+#![cfg(feature = "ssr")]
+
+use axum::extract::FromRef;
+use leptos::prelude::*;
+use leptos_axum_socket::{
+    ConnectionState, ServerSocket, SocketMsg, expect_socket_context, provide_socket_context,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(FromRef, Clone)]
+pub struct AppState {
+    pub socket: ServerSocket,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+pub struct ChatKey {
+    pub room_id: u32,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ChatMsg(String);
+
+impl SocketMsg for ChatMsg {
+    type Key = ChatKey;
+}
+
+#[component]
+fn Room() -> impl IntoView {
+    let socket = expect_socket_context();
+
+    socket.subscribe(ChatKey { room_id: 1 }, |msg: &ChatMsg| {
+        panic!("No message can arrive during SSR: {msg:?}");
+    });
+    socket.send(ChatKey { room_id: 1 }, ChatMsg("Hi".to_string()));
+    socket.send_optimistic(ChatKey { room_id: 1 }, ChatMsg("Hi".to_string()));
+
+    let state = move || format!("{:?}", socket.connection_state().get());
+
+    view! { <p>{state}</p> }
+}
+
+#[component]
+fn App() -> impl IntoView {
+    provide_socket_context();
+
+    view! { <Room /> }
+}
+
+#[test]
+fn components_that_subscribe_render_during_ssr() {
+    let owner = Owner::new();
+    owner.set();
+
+    let html = view! { <App /> }.to_html();
+
+    assert!(html.contains(&format!("{:?}", ConnectionState::Closed)));
+}